@@ -1,172 +1,2705 @@
-use crate::error::Result;
+use crate::error::{FileChanged, FileErrors, Result};
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::io;
-use std::path::{Path, PathBuf};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Mutex;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use ahash::AHashSet;
 use rapidhash::fast::RapidHasher;
+use sha2::Digest as _;
 use std::hash::Hasher;
 
-use indicatif::ProgressBar;
-use nanoserde::SerJson;
+use nanoserde::{DeJson, SerJson};
 use rayon::prelude::*;
+use snafu::ResultExt;
 
+use super::checkpoint::{self, Checkpoint, CheckpointWriter};
+use super::dirlist::{EntryFilter, FileEntry};
+use super::gate::OpenFileGate;
+use super::progress::{ByteCounter, ItemCounter, Phase, ProgressReporter};
+use super::utils::{file_attributes, is_compressed_attribute, is_sparse_attribute};
 use super::DirList;
+use super::{allocated_size, file_identity, named_streams_size};
 
-#[derive(SerJson, Debug, Clone)]
+#[derive(SerJson, DeJson, Debug, Clone)]
 pub struct DuplicateGroup {
     pub size: u64,
     pub paths: Vec<String>,
+    /// Modification time of each path in seconds since the Unix epoch, or 0
+    /// if it could not be read. Filled in when the backend listed them, or
+    /// when asked for, see `fill_mtimes`.
+    pub mtimes: Option<Vec<u64>>,
+    /// Whether the files were compared byte by byte. Exports written before
+    /// this was recorded read as fuzzy, since nothing says otherwise.
+    #[nserde(default)]
+    pub confidence: Confidence,
 }
 
-#[derive(Debug)]
+/// How sure it is that the files of a group are identical
+#[derive(SerJson, DeJson, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Confidence {
+    /// Compared byte by byte, safe to link
+    Exact,
+    /// Only hashes of sampled blocks matched, files may still differ
+    #[default]
+    Fuzzy,
+}
+
+impl Confidence {
+    pub fn of(comparison: &Comparison) -> Self {
+        match comparison {
+            Comparison::Strict => Confidence::Exact,
+            Comparison::Fuzzy => Confidence::Fuzzy,
+        }
+    }
+}
+
+impl std::fmt::Display for Confidence {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Confidence::Exact => "exact",
+            Confidence::Fuzzy => "fuzzy",
+        })
+    }
+}
+
+impl DuplicateGroup {
+    /// Load groups written by a JSON or JSON lines export
+    pub fn load_json(path: &Path) -> Result<Vec<DuplicateGroup>> {
+        let json = fs::read_to_string(path).context(crate::error::IoSnafu)?;
+        let invalid = |e: nanoserde::DeJsonErr| crate::error::AppError::Other {
+            message: format!("invalid export {}: {}", path.display(), e),
+        };
+        // A JSON lines export of a run without duplicates has no lines
+        if json.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        if json.trim_start().starts_with('{') {
+            return json
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| DuplicateGroup::deserialize_json(line).map_err(invalid))
+                .collect();
+        }
+        Vec::<DuplicateGroup>::deserialize_json(&json).map_err(invalid)
+    }
+
+    /// Keep the paths at the indices `keep` accepts, along with their mtimes
+    fn retain_paths(&mut self, keep: impl Fn(usize) -> bool) {
+        let mut index = 0..;
+        self.paths.retain(|_| keep(index.next().unwrap()));
+        if let Some(mtimes) = &mut self.mtimes {
+            let mut index = 0..;
+            mtimes.retain(|_| keep(index.next().unwrap()));
+        }
+    }
+
+    /// Sort paths, keeping each mtime next to its path
+    fn sort_paths(&mut self) {
+        match self.mtimes.take() {
+            Some(mtimes) if mtimes.len() == self.paths.len() => {
+                let mut pairs: Vec<(String, u64)> = self.paths.drain(..).zip(mtimes).collect();
+                pairs.sort();
+                let (paths, mtimes) = pairs.into_iter().unzip();
+                self.paths = paths;
+                self.mtimes = Some(mtimes);
+            }
+            _ => self.paths.sort(),
+        }
+    }
+}
+
+fn mtime_seconds(path: &str) -> u64 {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |age| age.as_secs())
+}
+
+/// Stat every path for its modification time, so the original copy can be
+/// told apart by age. Costs one `fs::metadata` per file, groups that already
+/// carry times from the listing are left as they are.
+pub fn fill_mtimes(groups: &mut [DuplicateGroup]) {
+    groups
+        .par_iter_mut()
+        .filter(|group| group.mtimes.is_none())
+        .for_each(|group| {
+            group.mtimes = Some(group.paths.iter().map(|p| mtime_seconds(p)).collect());
+        });
+}
+
+/// Remove paths that no longer exist or changed size, then groups left with
+/// fewer than two paths. Returns how many paths were removed.
+pub fn drop_stale_paths(groups: &mut Vec<DuplicateGroup>) -> usize {
+    let mut removed = 0;
+    for group in groups.iter_mut() {
+        let before = group.paths.len();
+        let size = group.size;
+        let fresh: Vec<bool> = group
+            .paths
+            .iter()
+            .map(|path| fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() == size))
+            .collect();
+        group.retain_paths(|i| fresh[i]);
+        removed += before - group.paths.len();
+    }
+    groups.retain(|group| group.paths.len() > 1);
+    removed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Summary {
+    pub groups: usize,
+    /// Files that could be removed while keeping one copy per group
+    pub redundant_files: usize,
+    pub reclaimable_bytes: u64,
+}
+
+pub fn summarize(duplicates: &[DuplicateGroup]) -> Summary {
+    duplicates
+        .iter()
+        .fold(Summary::default(), |mut summary, group| {
+            let redundant = group.paths.len().saturating_sub(1);
+            summary.groups += 1;
+            summary.redundant_files += redundant;
+            summary.reclaimable_bytes += reclaimable(group);
+            summary
+        })
+}
+
+/// Alternate data streams on the redundant copies, see `named_stream_usage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StreamUsage {
+    /// Redundant files with at least one named stream
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// Sum the named data streams of every path after the first in each group.
+/// They are not part of `DuplicateGroup::size` nor compared, and linking
+/// only shares the default stream, so what they hold is lost rather than
+/// deduplicated. Costs a stream listing per file.
+pub fn named_stream_usage(groups: &[DuplicateGroup]) -> StreamUsage {
+    groups
+        .par_iter()
+        .flat_map_iter(|group| group.paths.iter().skip(1))
+        .map(|path| match named_streams_size(Path::new(path)) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                log::debug!("Failed to list the streams of {}: {}", path, e);
+                0
+            }
+        })
+        .filter(|&bytes| bytes > 0)
+        .fold(StreamUsage::default, |usage, bytes| StreamUsage {
+            files: usage.files + 1,
+            bytes: usage.bytes + bytes,
+        })
+        .reduce(StreamUsage::default, |a, b| StreamUsage {
+            files: a.files + b.files,
+            bytes: a.bytes + b.bytes,
+        })
+}
+
+/// Disk space of the redundant copies, see `allocated_usage`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AllocatedUsage {
+    /// Redundant files that are sparse
+    pub sparse_files: usize,
+    /// Redundant files that are NTFS-compressed, which may be sparse as well
+    pub compressed_files: usize,
+    pub bytes: u64,
+}
+
+/// Sum the bytes every path after the first in each group occupies on disk,
+/// which is what linking them really frees. Sparse and compressed files may
+/// occupy far less than `DuplicateGroup::size`, the others are counted at
+/// it. Costs an attribute read per file.
+pub fn allocated_usage(groups: &[DuplicateGroup]) -> AllocatedUsage {
+    groups
+        .par_iter()
+        .flat_map_iter(|group| group.paths.iter().skip(1).map(move |path| (group, path)))
+        .map(|(group, path)| {
+            let path = Path::new(path);
+            let attributes = file_attributes(path).unwrap_or(0);
+            let usage = AllocatedUsage {
+                sparse_files: is_sparse_attribute(attributes) as usize,
+                compressed_files: is_compressed_attribute(attributes) as usize,
+                bytes: group.size,
+            };
+            if usage.sparse_files + usage.compressed_files == 0 {
+                return usage;
+            }
+            match allocated_size(path) {
+                Ok(bytes) => AllocatedUsage {
+                    bytes: bytes.min(group.size),
+                    ..usage
+                },
+                Err(e) => {
+                    log::debug!(
+                        "Failed to read the allocated size of {}: {}",
+                        path.display(),
+                        e
+                    );
+                    usage
+                }
+            }
+        })
+        .reduce(AllocatedUsage::default, |a, b| AllocatedUsage {
+            sparse_files: a.sparse_files + b.sparse_files,
+            compressed_files: a.compressed_files + b.compressed_files,
+            bytes: a.bytes + b.bytes,
+        })
+}
+
+/// Reclaimable space of one file extension, see `breakdown_by_extension`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtensionShare {
+    /// Lowercased and without the dot, empty for files without one
+    pub extension: String,
+    pub redundant_files: usize,
+    pub reclaimable_bytes: u64,
+}
+
+/// Bucket reclaimable bytes by file extension and keep the `top` largest.
+/// Every path but the first of a group is a redundant copy counted under its
+/// own extension, so the buckets add up to `Summary::reclaimable_bytes`.
+pub fn breakdown_by_extension(duplicates: &[DuplicateGroup], top: usize) -> Vec<ExtensionShare> {
+    let mut buckets: HashMap<String, (usize, u64)> = HashMap::new();
+    for group in duplicates {
+        for path in group.paths.iter().skip(1) {
+            let extension = Path::new(path)
+                .extension()
+                .map(|e| e.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let bucket = buckets.entry(extension).or_default();
+            bucket.0 += 1;
+            bucket.1 += group.size;
+        }
+    }
+
+    let mut shares: Vec<ExtensionShare> = buckets
+        .into_iter()
+        .map(
+            |(extension, (redundant_files, reclaimable_bytes))| ExtensionShare {
+                extension,
+                redundant_files,
+                reclaimable_bytes,
+            },
+        )
+        .collect();
+    shares.sort_by(|a, b| {
+        b.reclaimable_bytes
+            .cmp(&a.reclaimable_bytes)
+            .then_with(|| a.extension.cmp(&b.extension))
+    });
+    shares.truncate(top);
+    shares
+}
+
+/// Copies in one directory below the common root of all duplicates, see
+/// `breakdown_by_directory`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryShare {
+    /// Name of the directory, empty for files directly in the common root
+    pub directory: String,
+    pub files: usize,
+    pub bytes: u64,
+    /// Part of `bytes` whose content also has a copy in another directory.
+    /// Files without any copy aren't counted at all, so this doesn't mean
+    /// the directory can be deleted.
+    pub elsewhere_bytes: u64,
+}
+
+/// Deepest directory holding every path, compared by component
+pub(crate) fn common_directory<'a>(paths: impl Iterator<Item = &'a Path>) -> PathBuf {
+    let mut common: Option<Vec<Component>> = None;
+    for path in paths {
+        let parent = path.parent().unwrap_or_else(|| Path::new(""));
+        common = Some(match common {
+            None => parent.components().collect(),
+            Some(common) => common
+                .into_iter()
+                .zip(parent.components())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        });
+    }
+    common.unwrap_or_default().iter().collect()
+}
+
+/// Bucket every copy by the directory it lives in right below the common
+/// root of all duplicates, such as the dated folders of a backup, largest
+/// first. Unlike the other breakdowns all copies are counted, since the
+/// question is which directories could go as a whole. Returns the common
+/// root too.
+pub fn breakdown_by_directory(duplicates: &[DuplicateGroup]) -> (PathBuf, Vec<DirectoryShare>) {
+    let root = common_directory(
+        duplicates
+            .iter()
+            .flat_map(|group| group.paths.iter().map(Path::new)),
+    );
+    let directory_of = |path: &str| -> String {
+        let below = Path::new(path)
+            .strip_prefix(&root)
+            .unwrap_or(Path::new(path));
+        match below.components().count() {
+            0 | 1 => String::new(),
+            _ => below
+                .components()
+                .next()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .unwrap_or_default(),
+        }
+    };
+
+    let mut buckets: HashMap<String, DirectoryShare> = HashMap::new();
+    for group in duplicates {
+        let directories: Vec<String> = group.paths.iter().map(|p| directory_of(p)).collect();
+        for directory in &directories {
+            let elsewhere = directories.iter().any(|other| other != directory);
+            let share = buckets
+                .entry(directory.clone())
+                .or_insert_with(|| DirectoryShare {
+                    directory: directory.clone(),
+                    files: 0,
+                    bytes: 0,
+                    elsewhere_bytes: 0,
+                });
+            share.files += 1;
+            share.bytes += group.size;
+            if elsewhere {
+                share.elsewhere_bytes += group.size;
+            }
+        }
+    }
+
+    let mut shares: Vec<DirectoryShare> = buckets.into_values().collect();
+    shares.sort_by(|a, b| {
+        b.bytes
+            .cmp(&a.bytes)
+            .then_with(|| a.directory.cmp(&b.directory))
+    });
+    (root, shares)
+}
+
+/// Machine-readable counts and timings of a run, written by `--stats`
+#[derive(SerJson, DeJson, Debug, Clone, PartialEq, Default)]
+pub struct RunStats {
+    pub files_scanned: usize,
+    /// Files that could not be listed or read
+    pub errors: usize,
+    /// Sizes shared by more than one file
+    pub size_groups: usize,
+    /// Groups left after hashing, before byte-for-byte verification
+    pub hash_groups: usize,
+    pub duplicate_groups: usize,
+    pub redundant_files: usize,
+    pub reclaimable_bytes: u64,
+    pub listing_seconds: f64,
+    pub size_grouping_seconds: f64,
+    pub hashing_seconds: f64,
+    /// Files left out because their size changed after they were listed
+    pub changed_files: usize,
+    /// Stopped early by the `ProgressReporter`'s cancel flag, so only part
+    /// of the size groups were hashed
+    pub interrupted: bool,
+}
+
+/// Everything a run found, for callers that want more than the groups
+#[derive(Debug, Clone)]
+pub struct DedupReport {
+    pub groups: Vec<DuplicateGroup>,
+    pub scanned: usize,
+    pub errors: usize,
+    pub elapsed: Duration,
+    /// Detailed counts and per-phase timings
+    pub stats: RunStats,
+}
+
+impl DedupReport {
+    /// Drop groups with fewer than `min_copies` files and update the totals
+    /// in `stats` to match
+    pub fn retain_min_copies(&mut self, min_copies: usize) {
+        retain_min_copies(&mut self.groups, min_copies);
+        let summary = summarize(&self.groups);
+        self.stats.duplicate_groups = summary.groups;
+        self.stats.redundant_files = summary.redundant_files;
+        self.stats.reclaimable_bytes = summary.reclaimable_bytes;
+    }
+}
+
+/// Drop groups with fewer than `min_copies` files, returning how many went
+pub fn retain_min_copies(groups: &mut Vec<DuplicateGroup>, min_copies: usize) -> usize {
+    let before = groups.len();
+    groups.retain(|group| group.paths.len() >= min_copies);
+    before - groups.len()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Largest reclaimable space first
+    #[default]
+    Size,
+    /// Most copies first
+    Count,
+    /// Alphabetically by first path
+    Path,
+}
+
+impl SortOrder {
+    pub const NAMES: [&'static str; 3] = ["size", "count", "path"];
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "size" => Ok(SortOrder::Size),
+            "count" => Ok(SortOrder::Count),
+            "path" => Ok(SortOrder::Path),
+            _ => Err(format!("Unknown sort order `{}`", s)),
+        }
+    }
+}
+
+fn reclaimable(group: &DuplicateGroup) -> u64 {
+    group.size * group.paths.len().saturating_sub(1) as u64
+}
+
+/// Sort paths within each group, then the groups themselves, so output is
+/// the same from run to run regardless of hashing order.
+pub fn sort_groups(groups: &mut [DuplicateGroup], order: SortOrder) {
+    for group in groups.iter_mut() {
+        group.sort_paths();
+    }
+
+    groups.sort_by(|a, b| {
+        let primary = match order {
+            SortOrder::Size => reclaimable(b)
+                .cmp(&reclaimable(a))
+                .then(b.size.cmp(&a.size)),
+            SortOrder::Count => b
+                .paths
+                .len()
+                .cmp(&a.paths.len())
+                .then(reclaimable(b).cmp(&reclaimable(a))),
+            SortOrder::Path => std::cmp::Ordering::Equal,
+        };
+        primary.then_with(|| a.paths.cmp(&b.paths))
+    });
+}
+
+/// Keep the `limit` groups with the most reclaimable space, largest first,
+/// and return the rest. Sorts before cutting, so the choice doesn't depend
+/// on the order the groups came in.
+pub fn split_off_largest(groups: &mut Vec<DuplicateGroup>, limit: usize) -> Vec<DuplicateGroup> {
+    sort_groups(groups, SortOrder::Size);
+    groups.split_off(limit.min(groups.len()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Comparison {
     Fuzzy,
     Strict,
 }
 
-fn calculate_fuzzy_hash(size: u64, path: &Path) -> io::Result<u64> {
-    if size == 0 {
-        return Ok(0);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Crc32,
+    Sha256,
+    Blake3,
+    XxHash64,
+    RapidHash,
+    Xxh3,
+}
+
+impl HashAlgorithm {
+    pub const NAMES: [&'static str; 6] =
+        ["crc32", "sha256", "blake3", "xxhash64", "rapidhash", "xxh3"];
+
+    /// The algorithm used when none is requested explicitly
+    pub fn default_for(comparison: &Comparison) -> Self {
+        match comparison {
+            Comparison::Fuzzy => HashAlgorithm::Xxh3,
+            Comparison::Strict => HashAlgorithm::Blake3,
+        }
+    }
+}
+
+impl std::str::FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "crc32" => Ok(HashAlgorithm::Crc32),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "blake3" => Ok(HashAlgorithm::Blake3),
+            "xxhash64" => Ok(HashAlgorithm::XxHash64),
+            "rapidhash" => Ok(HashAlgorithm::RapidHash),
+            "xxh3" => Ok(HashAlgorithm::Xxh3),
+            _ => Err(format!("Unknown hash algorithm `{}`", s)),
+        }
+    }
+}
+
+/// Key used to bucket same-size files, kept as raw bytes rather than a
+/// formatted string so strong digests are compared in full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Digest {
+    U32(u32),
+    U64(u64),
+    Bytes32([u8; 32]),
+}
+
+enum DigestState {
+    Crc32(crc32fast::Hasher),
+    Sha256(sha2::Sha256),
+    Blake3(Box<blake3::Hasher>),
+    XxHash64(xxhash_rust::xxh64::Xxh64),
+    RapidHash(RapidHasher<'static>),
+    Xxh3(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl DigestState {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Crc32 => DigestState::Crc32(crc32fast::Hasher::new()),
+            HashAlgorithm::Sha256 => DigestState::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Blake3 => DigestState::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::XxHash64 => DigestState::XxHash64(xxhash_rust::xxh64::Xxh64::new(0)),
+            HashAlgorithm::RapidHash => DigestState::RapidHash(RapidHasher::default()),
+            HashAlgorithm::Xxh3 => DigestState::Xxh3(Box::new(xxhash_rust::xxh3::Xxh3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            DigestState::Crc32(h) => h.update(data),
+            DigestState::Sha256(h) => h.update(data),
+            DigestState::Blake3(h) => {
+                h.update(data);
+            }
+            DigestState::XxHash64(h) => h.update(data),
+            DigestState::RapidHash(h) => h.write(data),
+            DigestState::Xxh3(h) => h.update(data),
+        }
+    }
+
+    fn finalize(self) -> Digest {
+        match self {
+            DigestState::Crc32(h) => Digest::U32(h.finalize()),
+            DigestState::Sha256(h) => Digest::Bytes32(h.finalize().into()),
+            DigestState::Blake3(h) => Digest::Bytes32(*h.finalize().as_bytes()),
+            DigestState::XxHash64(h) => Digest::U64(h.digest()),
+            DigestState::RapidHash(h) => Digest::U64(h.finish()),
+            DigestState::Xxh3(h) => Digest::U64(h.digest()),
+        }
+    }
+}
+
+/// Files larger than this are memory-mapped for hashing instead of read
+pub const DEFAULT_MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+const FUZZY_CHUNK_SIZE: u64 = 4096;
+
+/// Map the file if it exceeds the threshold, or `None` to use buffered reads
+fn map_file(file: &fs::File, size: u64, mmap_threshold: u64) -> Option<memmap2::Mmap> {
+    if size == 0 || size <= mmap_threshold {
+        return None;
+    }
+
+    match unsafe { memmap2::Mmap::map(file) } {
+        // The file may have been truncated since its size was recorded
+        Ok(mmap) if mmap.len() as u64 >= size => Some(mmap),
+        Ok(_) => None,
+        Err(e) => {
+            log::debug!("Falling back to buffered reads, mmap failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Offsets and lengths of the chunks sampled by the fuzzy hash
+fn fuzzy_chunks(size: u64) -> Vec<(u64, usize)> {
+    let mut chunks = Vec::new();
+    let mut offset: u64 = 0;
+
+    // Digest with exponentially decreasing density
+    while offset + FUZZY_CHUNK_SIZE < size {
+        chunks.push((offset, FUZZY_CHUNK_SIZE as usize));
+        offset += FUZZY_CHUNK_SIZE;
+        offset *= 2;
+    }
+
+    // Digest the last chunk
+    let read_size = min(size, FUZZY_CHUNK_SIZE);
+    if read_size > 0 {
+        chunks.push((size - read_size, read_size as usize));
+    }
+
+    chunks
+}
+
+/// Feed the sampled chunks to `state`. Positions come from the file size
+/// alone and every chunk is read in full, so however the reader splits its
+/// reads the same file always hashes the same.
+fn read_chunks<R: Read + Seek>(
+    reader: &mut R,
+    chunks: &[(u64, usize)],
+    state: &mut DigestState,
+    gate: &OpenFileGate,
+) -> io::Result<()> {
+    let mut buffer = [0u8; FUZZY_CHUNK_SIZE as usize];
+    for &(offset, len) in chunks {
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut buffer[..len])?;
+        state.update(&buffer[..len]);
+        gate.throttle(len as u64);
+    }
+    Ok(())
+}
+
+/// Open a file that was listed with `size`. Backends such as WizTree and
+/// Everything report sizes without a stat, and on a live system any file may
+/// have been rewritten since. Reading its length from the open handle is
+/// cheap.
+fn open_listed(path: &Path, size: u64) -> io::Result<fs::File> {
+    let file = fs::File::open(path)?;
+    let actual = file.metadata()?.len();
+    if actual != size {
+        return Err(FileChanged {
+            listed: size,
+            actual,
+        }
+        .into());
+    }
+    Ok(file)
+}
+
+/// A read that ran out of file was cut short by the file shrinking while it
+/// was hashed, report it as such
+fn shrunk_while_reading(error: io::Error, file: &fs::File, size: u64) -> io::Error {
+    if error.kind() != io::ErrorKind::UnexpectedEof {
+        return error;
+    }
+    match file.metadata() {
+        Ok(metadata) => FileChanged {
+            listed: size,
+            actual: metadata.len(),
+        }
+        .into(),
+        Err(_) => error,
+    }
+}
+
+fn calculate_fuzzy_hash(
+    size: u64,
+    path: &Path,
+    algorithm: HashAlgorithm,
+    mmap_threshold: u64,
+    gate: &OpenFileGate,
+) -> io::Result<Digest> {
+    let mut state = DigestState::new(algorithm);
+    if size == 0 {
+        return Ok(state.finalize());
+    }
+
+    let mut file = open_listed(path, size)?;
+    let chunks = fuzzy_chunks(size);
+
+    if let Some(mmap) = map_file(&file, size, mmap_threshold) {
+        for (offset, len) in chunks {
+            let start = offset as usize;
+            state.update(&mmap[start..start + len]);
+            gate.throttle(len as u64);
+        }
+    } else {
+        read_chunks(&mut file, &chunks, &mut state, gate)
+            .map_err(|e| shrunk_while_reading(e, &file, size))?;
+    }
+
+    Ok(state.finalize())
+}
+
+/// Hash all of `file`, taking from the throttle of `gate` per buffer so a
+/// large file is read at the capped rate too rather than in one burst
+fn hash_whole_file(
+    mut file: fs::File,
+    size: u64,
+    algorithm: HashAlgorithm,
+    mmap_threshold: u64,
+    gate: &OpenFileGate,
+) -> io::Result<Digest> {
+    let mut state = DigestState::new(algorithm);
+    // Anything but `size` bytes means the file was written to while hashed,
+    // and the digest would stand for content of another size group
+    let hashed = if let Some(mmap) = map_file(&file, size, mmap_threshold) {
+        for buffer in mmap.chunks(READ_BUFFER_SIZE) {
+            state.update(buffer);
+            gate.throttle(buffer.len() as u64);
+        }
+        mmap.len() as u64
+    } else {
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let mut total = 0;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            state.update(&buffer[..read]);
+            gate.throttle(read as u64);
+            total += read as u64;
+        }
+        total
+    };
+    if hashed != size {
+        return Err(FileChanged {
+            listed: size,
+            actual: hashed,
+        }
+        .into());
+    }
+
+    Ok(state.finalize())
+}
+
+/// Hashing throughput of one algorithm over a set of files, see `bench`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchResult {
+    pub algorithm: HashAlgorithm,
+    pub files: usize,
+    /// Bytes fed to the hasher, only the sampled chunks for a fuzzy
+    /// comparison
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn megabytes_per_second(&self) -> f64 {
+        self.bytes as f64 / 1_000_000.0 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Whether the fuzzy hash samples little enough of a file of `size` to be
+/// worth reading ahead of the whole file. Below that, and for small files
+/// it samples entirely, the prefilter would read most bytes twice.
+fn worth_prefiltering(size: u64) -> bool {
+    hashed_bytes(size, &Comparison::Fuzzy) * 4 <= size
+}
+
+/// Bytes `calculate_digest` reads from a file of `size`
+fn hashed_bytes(size: u64, comparison: &Comparison) -> u64 {
+    match comparison {
+        Comparison::Fuzzy => fuzzy_chunks(size).iter().map(|&(_, len)| len as u64).sum(),
+        Comparison::Strict => size,
+    }
+}
+
+/// Hash every file once per algorithm and time just that, without listing,
+/// grouping or progress reporting in the way. Files that can't be read are
+/// left out of the counts. The first pass warms the OS file cache for the
+/// others, so run it twice to compare algorithms on equal terms.
+pub fn bench(
+    files: &[FileEntry],
+    comparison: &Comparison,
+    algorithms: &[HashAlgorithm],
+    mmap_threshold: u64,
+) -> Vec<BenchResult> {
+    algorithms
+        .iter()
+        .map(|&algorithm| {
+            // Unlimited, but `calculate_digest` takes its reads from a gate
+            let gate = OpenFileGate::default();
+            let start = Instant::now();
+            let (files, bytes) = files
+                .par_iter()
+                .filter_map(|entry| {
+                    let (path, size) = (&entry.path, entry.size);
+                    match calculate_digest(size, path, comparison, algorithm, mmap_threshold, &gate)
+                    {
+                        Ok(_) => Some(hashed_bytes(size, comparison)),
+                        Err(e) => {
+                            log::debug!("Skipping {}: {}", path.display(), e);
+                            None
+                        }
+                    }
+                })
+                .fold(|| (0, 0), |(files, bytes), read| (files + 1, bytes + read))
+                .reduce(|| (0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+            BenchResult {
+                algorithm,
+                files,
+                bytes,
+                elapsed: start.elapsed(),
+            }
+        })
+        .collect()
+}
+
+/// Keep one path per file on disk, so hardlinks that already share their
+/// data are neither hashed twice nor counted as reclaimable. The smallest
+/// path of each set is kept, and paths whose identity can't be read are kept
+/// as they are.
+pub fn collapse_hardlinks<'a>(paths: &[&'a Path]) -> Vec<&'a Path> {
+    let mut sorted = paths.to_vec();
+    sorted.sort();
+
+    let mut seen = HashSet::new();
+    sorted
+        .into_iter()
+        .filter(|path| match file_identity(path) {
+            Ok(id) => seen.insert(id),
+            Err(e) => {
+                log::debug!("Could not identify {}: {}", path.display(), e);
+                true
+            }
+        })
+        .collect()
+}
+
+/// Size of the blocks read from each end of a file by the edge pre-filter
+const EDGE_BLOCK_SIZE: u64 = 4096;
+
+/// Cheaply hash the first and last block of a file
+fn edge_digest(size: u64, path: &Path, gate: &OpenFileGate) -> io::Result<Digest> {
+    let mut state = DigestState::new(HashAlgorithm::RapidHash);
+    let _permit = gate.acquire();
+    let mut file = open_listed(path, size)?;
+    let mut buffer = [0u8; EDGE_BLOCK_SIZE as usize];
+
+    let len = min(size, EDGE_BLOCK_SIZE) as usize;
+    file.read_exact(&mut buffer[..len])
+        .map_err(|e| shrunk_while_reading(e, &file, size))?;
+    state.update(&buffer[..len]);
+
+    if size > EDGE_BLOCK_SIZE {
+        file.seek(SeekFrom::Start(size - len as u64))?;
+        file.read_exact(&mut buffer[..len])
+            .map_err(|e| shrunk_while_reading(e, &file, size))?;
+        state.update(&buffer[..len]);
+    }
+
+    Ok(state.finalize())
+}
+
+/// Split same-size files on their first and last block before hashing them
+/// properly. Files that differ near either end are separated with one or two
+/// small reads, which saves a lot of I/O on large groups such as videos of
+/// identical length. Singletons are dropped and unreadable files recorded.
+pub fn partition_by_edge_blocks<'a>(
+    size: u64,
+    paths: &[&'a Path],
+    errors: &FileErrors,
+    gate: &OpenFileGate,
+) -> Vec<Vec<&'a Path>> {
+    let digests: Vec<Option<(Digest, &Path)>> = paths
+        .par_iter()
+        .filter(|_| !gate.is_cancelled())
+        .map(|path| match edge_digest(size, path, gate) {
+            Ok(digest) => {
+                gate.throttle(min(size, 2 * EDGE_BLOCK_SIZE));
+                Some((digest, *path))
+            }
+            Err(e) => {
+                errors.record(path, e);
+                None
+            }
+        })
+        .collect();
+
+    let mut partitions: HashMap<Digest, Vec<&Path>> = HashMap::new();
+    for (digest, path) in digests.into_iter().flatten() {
+        partitions.entry(digest).or_default().push(path);
+    }
+
+    partitions.retain(|_, v| v.len() > 1);
+    partitions.into_values().collect()
+}
+
+/// Digest of a file listed with `size`, with its reads throttled by `gate`.
+/// Doesn't take a permit from it, that's up to the caller.
+fn calculate_digest(
+    size: u64,
+    path: &Path,
+    comparison: &Comparison,
+    algorithm: HashAlgorithm,
+    mmap_threshold: u64,
+    gate: &OpenFileGate,
+) -> io::Result<Digest> {
+    match comparison {
+        Comparison::Fuzzy => calculate_fuzzy_hash(size, path, algorithm, mmap_threshold, gate),
+        Comparison::Strict => hash_whole_file(
+            open_listed(path, size)?,
+            size,
+            algorithm,
+            mmap_threshold,
+            gate,
+        ),
+    }
+}
+
+/// Split a group of same-size files into groups of files sharing a digest,
+/// dropping groups with a single member. Files that could not be read are
+/// recorded in `errors` and left out.
+#[allow(clippy::too_many_arguments)]
+fn group_by_hash<'a>(
+    size: u64,
+    paths: &[&'a Path],
+    comparison: &Comparison,
+    algorithm: HashAlgorithm,
+    mmap_threshold: u64,
+    errors: &FileErrors,
+    hashed: &ByteCounter,
+    gate: &OpenFileGate,
+) -> Vec<Vec<&'a Path>> {
+    if paths.len() < 2 {
+        return Vec::new();
+    }
+
+    // Collect hashes in parallel
+    let hashes: Vec<Option<(Digest, &Path)>> = paths
+        .par_iter()
+        .filter(|_| !gate.is_cancelled())
+        .map(|path| {
+            let digest = {
+                let _permit = gate.acquire();
+                calculate_digest(size, path, comparison, algorithm, mmap_threshold, gate)
+            };
+            match digest {
+                Ok(digest) => {
+                    hashed.add(size);
+                    Some((digest, *path))
+                }
+                Err(e) => {
+                    errors.record(path, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    // Group by hash locally (sequential aggregation is fast enough for reduced set)
+    let mut reduced_map: HashMap<Digest, Vec<&Path>> = HashMap::new();
+    for (digest, path) in hashes.into_iter().flatten() {
+        reduced_map.entry(digest).or_default().push(path);
+    }
+
+    reduced_map.retain(|_, v| v.len() > 1);
+    reduced_map.into_values().collect()
+}
+
+const VERIFY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Split a candidate group into subgroups of byte-for-byte identical files.
+///
+/// Files are streamed in chunks side by side, so a group is split as soon as
+/// its members diverge. Files that cannot be opened or read are dropped and
+/// recorded in `errors`, the rest of the group is still verified. Files whose
+/// length changed since grouping end up in a subgroup of their own.
+///
+/// A group that fits the open file limit of `gate` is held open until it is
+/// resolved. A larger one is read a chunk at a time with one file open at a
+/// time, until it splits into subgroups that fit. Once `gate` is cancelled,
+/// returns what it verified so far.
+pub fn verify_identical<'a>(
+    paths: &[&'a Path],
+    errors: &FileErrors,
+    gate: &OpenFileGate,
+) -> Vec<Vec<&'a Path>> {
+    let mut pending = vec![(0, paths.to_vec())];
+    let mut verified = Vec::new();
+
+    while let Some((offset, group)) = pending.pop() {
+        if gate.is_cancelled() {
+            break;
+        }
+        if group.len() < 2 {
+            continue;
+        }
+        if group.len() <= gate.limit() {
+            verified.extend(verify_open_together(&group, offset, errors, gate));
+            continue;
+        }
+
+        // Too many to hold open, so partition by the next chunk reading one
+        // file at a time
+        let mut buckets: Vec<(Vec<u8>, Vec<&Path>)> = Vec::new();
+        for path in group {
+            let chunk = {
+                let _permit = gate.acquire();
+                read_chunk_at(path, offset)
+            };
+            match chunk {
+                Ok(chunk) => {
+                    gate.throttle(chunk.len() as u64);
+                    match buckets.iter_mut().find(|(c, _)| *c == chunk) {
+                        Some((_, members)) => members.push(path),
+                        None => buckets.push((chunk, vec![path])),
+                    }
+                }
+                Err(e) => errors.record(path, e),
+            }
+        }
+
+        for (chunk, members) in buckets {
+            if chunk.is_empty() {
+                if members.len() > 1 {
+                    verified.push(members);
+                }
+            } else {
+                pending.push((offset + chunk.len() as u64, members));
+            }
+        }
+    }
+
+    verified
+}
+
+/// The verification chunk of `path` at `offset`, opening it just for that
+fn read_chunk_at(path: &Path, offset: u64) -> io::Result<Vec<u8>> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut chunk = Vec::with_capacity(VERIFY_CHUNK_SIZE);
+    file.take(VERIFY_CHUNK_SIZE as u64)
+        .read_to_end(&mut chunk)?;
+    Ok(chunk)
+}
+
+/// `verify_identical` from `offset` on for a group within the open file
+/// limit, with every member held open until the group is resolved
+fn verify_open_together<'a>(
+    paths: &[&'a Path],
+    offset: u64,
+    errors: &FileErrors,
+    gate: &OpenFileGate,
+) -> Vec<Vec<&'a Path>> {
+    let _permit = gate.acquire_many(paths.len());
+    let mut readers = Vec::with_capacity(paths.len());
+    for path in paths {
+        let file = fs::File::open(path).and_then(|mut file| {
+            file.seek(SeekFrom::Start(offset))?;
+            Ok(file)
+        });
+        match file {
+            Ok(file) => readers.push((*path, io::BufReader::new(file))),
+            Err(e) => errors.record(path, e),
+        }
+    }
+
+    let mut pending = vec![readers];
+    let mut verified = Vec::new();
+
+    while let Some(group) = pending.pop() {
+        if gate.is_cancelled() {
+            break;
+        }
+        if group.len() < 2 {
+            continue;
+        }
+
+        // Partition the group by the contents of the next chunk
+        let mut buckets: Vec<(Vec<u8>, Vec<_>)> = Vec::new();
+        for (path, mut reader) in group {
+            let mut chunk = Vec::with_capacity(VERIFY_CHUNK_SIZE);
+            if let Err(e) = (&mut reader)
+                .take(VERIFY_CHUNK_SIZE as u64)
+                .read_to_end(&mut chunk)
+            {
+                errors.record(path, e);
+                continue;
+            }
+            gate.throttle(chunk.len() as u64);
+
+            match buckets.iter_mut().find(|(c, _)| *c == chunk) {
+                Some((_, members)) => members.push((path, reader)),
+                None => buckets.push((chunk, vec![(path, reader)])),
+            }
+        }
+
+        for (chunk, members) in buckets {
+            if chunk.is_empty() {
+                // Every member reached EOF at the same offset
+                if members.len() > 1 {
+                    verified.push(members.into_iter().map(|(path, _)| path).collect());
+                }
+            } else {
+                pending.push(members);
+            }
+        }
+    }
+
+    verified
+}
+
+/// Check imported groups against the files as they are now, such as before
+/// acting on an old export. Each group is hashed strictly with `algorithm`
+/// and verified byte by byte, so files edited since are split off into
+/// groups of their own or dropped if they match nothing else. Files that
+/// can't be read or changed size are left out and recorded in `errors`.
+pub fn reverify(
+    groups: Vec<DuplicateGroup>,
+    algorithm: HashAlgorithm,
+    errors: &FileErrors,
+    progress: &ProgressReporter,
+    gate: &OpenFileGate,
+) -> Vec<DuplicateGroup> {
+    let total_bytes = groups
+        .iter()
+        .map(|group| group.size * group.paths.len() as u64)
+        .sum();
+    let hashed = ByteCounter::new(progress.bytes_bar(total_bytes));
+
+    let mut verified: Vec<DuplicateGroup> = groups
+        .par_iter()
+        .flat_map_iter(|group| {
+            let paths: Vec<&Path> = group.paths.iter().map(Path::new).collect();
+            // A hand-edited or stale export may list fewer times than paths,
+            // which can't be told apart anymore, like in `sort_paths`
+            let mtimes: Option<HashMap<&Path, u64>> = group
+                .mtimes
+                .as_ref()
+                .filter(|mtimes| mtimes.len() == paths.len())
+                .map(|mtimes| paths.iter().copied().zip(mtimes.iter().copied()).collect());
+
+            let candidates = group_by_hash(
+                group.size,
+                &paths,
+                &Comparison::Strict,
+                algorithm,
+                DEFAULT_MMAP_THRESHOLD,
+                errors,
+                &hashed,
+                gate,
+            );
+            candidates
+                .into_iter()
+                .flat_map(|candidate| verify_identical(&candidate, errors, gate))
+                .map(|same| DuplicateGroup {
+                    size: group.size,
+                    mtimes: mtimes.as_ref().and_then(|mtimes| {
+                        same.iter().map(|path| mtimes.get(path).copied()).collect()
+                    }),
+                    paths: same
+                        .iter()
+                        .map(|path| path.to_string_lossy().to_string())
+                        .collect(),
+                    confidence: Confidence::Exact,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    hashed.finish();
+
+    for group in &mut verified {
+        group.sort_paths();
+    }
+    sort_groups(&mut verified, SortOrder::default());
+    verified
+}
+
+/// Files whose content mostly overlaps, found by `similarity`. Unlike a
+/// `DuplicateGroup` these files differ, so they are never linked.
+#[derive(SerJson, DeJson, Debug, Clone)]
+pub struct SimilarGroup {
+    /// Lowest share of chunks two files joined into the group have in common,
+    /// from 0 to 1
+    pub similarity: f64,
+    pub paths: Vec<String>,
+}
+
+/// Content-defined chunking parameters: chunks average about 4 KiB
+const CHUNK_MIN_SIZE: usize = 1024;
+const CHUNK_MAX_SIZE: usize = 16 * 1024;
+const CHUNK_BOUNDARY_MASK: u64 = 0xFFF0_0000_0000_0000;
+
+/// Chunks found in more files than this are too common (zero runs, shared
+/// headers) to say two files are related, so they don't pair files up
+const CHUNK_MAX_SHARED: usize = 64;
+
+/// Random values the gear hash adds per byte, from a fixed splitmix64 seed
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Split the content at positions picked by a rolling gear hash and return
+/// the sorted, distinct hashes of the chunks. Boundaries depend only on
+/// nearby bytes, so an edit only changes the chunks around it.
+fn chunk_fingerprint<R: Read>(mut reader: R, gate: &OpenFileGate) -> io::Result<Vec<u64>> {
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::with_capacity(CHUNK_MAX_SIZE);
+    let mut gear = 0u64;
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        gate.throttle(read as u64);
+        for &byte in &buffer[..read] {
+            gear = (gear << 1).wrapping_add(GEAR[byte as usize]);
+            chunk.push(byte);
+            if (chunk.len() >= CHUNK_MIN_SIZE && gear & CHUNK_BOUNDARY_MASK == 0)
+                || chunk.len() >= CHUNK_MAX_SIZE
+            {
+                chunks.push(rapidhash::v3::rapidhash_v3(&chunk));
+                chunk.clear();
+                gear = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(rapidhash::v3::rapidhash_v3(&chunk));
+    }
+
+    chunks.sort_unstable();
+    chunks.dedup();
+    Ok(chunks)
+}
+
+/// Share of chunks two sorted fingerprints have in common (Jaccard index)
+fn fingerprint_similarity(a: &[u64], b: &[u64]) -> f64 {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                shared += 1;
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    let union = a.len() + b.len() - shared;
+    if union == 0 {
+        0.0
+    } else {
+        shared as f64 / union as f64
     }
+}
 
-    let file = fs::File::open(path)?;
-    let mmap = unsafe { memmap2::Mmap::map(&file)? };
-    let mut hasher = RapidHasher::default();
-    let mut offset: u64 = 0;
-    let chunk_size: u64 = 4096;
+fn find_root(parents: &mut [usize], mut i: usize) -> usize {
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
 
-    // Digest with exponentially decreasing density
-    while offset + chunk_size < size {
-        let chunk = &mmap[offset as usize..(offset + chunk_size) as usize];
-        hasher.write(chunk);
-        offset += chunk_size;
-        offset *= 2;
+/// Group files whose chunk sets overlap by at least `threshold` (0 to 1),
+/// which finds edited copies that exact hashing can't. Every file is read in
+/// full. Identical files show up too, with a similarity of 1.
+pub fn similarity(
+    paths: &[&Path],
+    threshold: f64,
+    errors: &FileErrors,
+    gate: &OpenFileGate,
+) -> Vec<SimilarGroup> {
+    let fingerprints: Vec<(&Path, Vec<u64>)> = paths
+        .par_iter()
+        .filter_map(|path| {
+            let fingerprint = {
+                let _permit = gate.acquire();
+                fs::File::open(path)
+                    .and_then(|file| chunk_fingerprint(io::BufReader::new(file), gate))
+            };
+            match fingerprint {
+                Ok(fingerprint) if !fingerprint.is_empty() => Some((*path, fingerprint)),
+                Ok(_) => None,
+                Err(e) => {
+                    errors.record(path, e);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    // Only files sharing at least one chunk can be similar
+    let mut files_by_chunk: HashMap<u64, Vec<usize>> = HashMap::new();
+    for (i, (_, fingerprint)) in fingerprints.iter().enumerate() {
+        for chunk in fingerprint {
+            files_by_chunk.entry(*chunk).or_default().push(i);
+        }
+    }
+    let mut candidates = HashSet::new();
+    for files in files_by_chunk.values() {
+        if files.len() > CHUNK_MAX_SHARED {
+            continue;
+        }
+        for (n, &a) in files.iter().enumerate() {
+            for &b in &files[n + 1..] {
+                candidates.insert((a, b));
+            }
+        }
     }
 
-    // Digest the last chunk
-    let read_size = min(size, chunk_size) as usize;
-    if read_size > 0 {
-        let start = (size as usize).saturating_sub(read_size);
-        let chunk = &mmap[start..size as usize];
-        hasher.write(chunk);
+    let edges: Vec<(usize, usize, f64)> = candidates
+        .into_par_iter()
+        .filter_map(|(a, b)| {
+            let score = fingerprint_similarity(&fingerprints[a].1, &fingerprints[b].1);
+            (score >= threshold).then_some((a, b, score))
+        })
+        .collect();
+
+    let mut parents: Vec<usize> = (0..fingerprints.len()).collect();
+    for &(a, b, _) in &edges {
+        let (a, b) = (find_root(&mut parents, a), find_root(&mut parents, b));
+        parents[a] = b;
+    }
+    let mut groups: HashMap<usize, SimilarGroup> = HashMap::new();
+    for &(a, b, score) in &edges {
+        let root = find_root(&mut parents, a);
+        let group = groups.entry(root).or_insert_with(|| SimilarGroup {
+            similarity: 1.0,
+            paths: Vec::new(),
+        });
+        group.similarity = group.similarity.min(score);
+        group.paths.extend(
+            [a, b]
+                .iter()
+                .map(|&i| fingerprints[i].0.to_string_lossy().to_string()),
+        );
+    }
+
+    let mut groups: Vec<SimilarGroup> = groups
+        .into_values()
+        .map(|mut group| {
+            group.paths.sort();
+            group.paths.dedup();
+            group
+        })
+        .collect();
+    groups.sort_by(|a, b| {
+        b.similarity
+            .total_cmp(&a.similarity)
+            .then_with(|| a.paths.cmp(&b.paths))
+    });
+    groups
+}
+
+/// Drop entries naming a file already listed under another casing. NTFS
+/// paths are case-insensitive, so `C:\Foo\bar.txt` and `C:\foo\Bar.txt` are
+/// one file, and grouping both would report it as its own duplicate.
+/// Returns how many were dropped.
+fn dedup_case_variants<P: AsRef<Path>>(entries: &mut Vec<P>) -> usize {
+    let mut seen = AHashSet::with_capacity(entries.len());
+    let before = entries.len();
+    entries.retain(|entry| seen.insert(entry.as_ref().to_string_lossy().to_lowercase()));
+    before - entries.len()
+}
+
+/// Group entries by size, skipping files smaller than `min_size` and sizes
+/// that only occur once. Entries are taken by value, so the map owns their
+/// paths rather than borrowing them from the listing.
+fn group_by_size(
+    entries: impl IntoIterator<Item = FileEntry>,
+    min_size: u64,
+    progress: &ItemCounter,
+) -> HashMap<u64, Vec<FileEntry>> {
+    let mut map: HashMap<u64, Vec<FileEntry>> = HashMap::new();
+
+    for entry in entries {
+        progress.inc(1);
+        if entry.size >= min_size {
+            map.entry(entry.size).or_default().push(entry);
+        }
     }
 
-    Ok(hasher.finish())
+    // Filter out single occurrences
+    map.retain(|_, v| v.len() > 1);
+    map
+}
+
+/// How the files of a size group are compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashOptions {
+    pub comparison: Comparison,
+    /// For strict comparison, only read whole the files whose fuzzy hashes
+    /// match. Ignored for fuzzy comparison.
+    pub prefilter: bool,
+    pub algorithm: HashAlgorithm,
+    /// Files larger than this are memory-mapped
+    pub mmap_threshold: u64,
 }
 
-fn calculate_full_hash(path: &Path) -> io::Result<blake3::Hash> {
-    let mut hasher = blake3::Hasher::new();
-    hasher.update_mmap(path)?;
-    Ok(hasher.finalize())
+impl HashOptions {
+    /// `comparison` with its default algorithm, prefiltered if strict
+    pub fn new(comparison: Comparison) -> Self {
+        HashOptions {
+            comparison,
+            prefilter: true,
+            algorithm: HashAlgorithm::default_for(&comparison),
+            mmap_threshold: DEFAULT_MMAP_THRESHOLD,
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn run(
     drive: &str,
-    matcher: Option<&str>,
+    matchers: &[glob::Pattern],
+    excludes: &[glob::Pattern],
+    options: glob::MatchOptions,
+    hashing: HashOptions,
+    min_size: u64,
+    filter: EntryFilter,
+    backend: crate::dirlist::Backend,
+    cache: Option<&Path>,
+    resume: Option<&Path>,
+    progress: &ProgressReporter,
+    gate: &OpenFileGate,
+) -> Result<DedupReport> {
+    let instant = Instant::now();
+    let (groups, stats) = run_with_stats(
+        drive, matchers, excludes, options, hashing, min_size, filter, backend, cache, resume,
+        progress, gate,
+    )?;
+    Ok(DedupReport {
+        scanned: stats.files_scanned,
+        errors: stats.errors,
+        elapsed: instant.elapsed(),
+        groups,
+        stats,
+    })
+}
+
+/// Like `run`, but only returns the duplicate groups
+#[allow(clippy::too_many_arguments)]
+pub fn run_groups(
+    drive: &str,
+    matchers: &[glob::Pattern],
+    excludes: &[glob::Pattern],
     options: glob::MatchOptions,
-    comparison: Comparison,
+    hashing: HashOptions,
+    min_size: u64,
+    filter: EntryFilter,
     backend: crate::dirlist::Backend,
+    cache: Option<&Path>,
+    resume: Option<&Path>,
+    progress: &ProgressReporter,
+    gate: &OpenFileGate,
 ) -> Result<Vec<DuplicateGroup>> {
+    run(
+        drive, matchers, excludes, options, hashing, min_size, filter, backend, cache, resume,
+        progress, gate,
+    )
+    .map(|report| report.groups)
+}
+
+/// Like `run`, but also reports counts and per-phase timings
+#[allow(clippy::too_many_arguments)]
+pub fn run_with_stats(
+    drive: &str,
+    matchers: &[glob::Pattern],
+    excludes: &[glob::Pattern],
+    options: glob::MatchOptions,
+    hashing: HashOptions,
+    min_size: u64,
+    filter: EntryFilter,
+    backend: crate::dirlist::Backend,
+    cache: Option<&Path>,
+    resume: Option<&Path>,
+    progress: &ProgressReporter,
+    gate: &OpenFileGate,
+) -> Result<(Vec<DuplicateGroup>, RunStats)> {
+    let HashOptions {
+        comparison,
+        prefilter,
+        algorithm,
+        mmap_threshold,
+    } = hashing;
     let instant = Instant::now();
 
     log::info!("[1/3] Generating recursive dirlist");
+    progress.phase(Phase::Listing);
 
-    let dirlist = DirList::new(drive, matcher, options, backend)?;
+    let mut dirlist = match cache {
+        // The cache holds a journal's path map, so it settles the auto probe
+        Some(cache)
+            if matches!(
+                backend,
+                crate::dirlist::Backend::USN | crate::dirlist::Backend::Auto(_)
+            ) =>
+        {
+            DirList::from_cache_with_delta(
+                drive, cache, matchers, excludes, options, filter, progress,
+            )?
+        }
+        _ => DirList::new(
+            drive, matchers, excludes, options, min_size, filter, backend, progress,
+        )?,
+    };
+
+    if let Some(cutoff) = filter.modified_since {
+        let dropped = dirlist.retain_modified_since(cutoff);
+        log::info!("Skipped {} files not modified since the cutoff", dropped);
+    }
 
-    log::info!("Finished in {} seconds", instant.elapsed().as_secs_f32());
+    let mut stats = RunStats {
+        listing_seconds: instant.elapsed().as_secs_f64(),
+        ..Default::default()
+    };
+    log::info!("Finished in {} seconds", stats.listing_seconds);
 
     let instant = Instant::now();
 
     log::info!("[2/3] Grouping by file size");
+    progress.phase(Phase::GroupingBySize);
 
-    // Group files by size
-    let entries: Vec<&(PathBuf, u64)> = dirlist.iter().collect();
-    let mut map: HashMap<u64, Vec<&Path>> = HashMap::with_capacity(entries.len());
-    let progress = ProgressBar::new(entries.len() as u64);
+    // Group files by size, taking them out of the listing as it goes
+    let listing_errors = dirlist.take_errors();
+    let entries = dirlist.entries_stream();
+    stats.files_scanned = entries.len();
+    let size_progress = progress.counter(entries.len() as u64);
+    let mut map = group_by_size(entries, min_size, &size_progress);
+    size_progress.finish();
 
-    for (path, file_size) in entries.into_iter() {
-        progress.inc(1);
-        map.entry(*file_size).or_default().push(path);
+    // A file listed under two casings has one size, so it's enough to look
+    // within the groups left, rather than at every path listed
+    if cfg!(windows) {
+        let dropped: usize = map.values_mut().map(dedup_case_variants).sum();
+        if dropped > 0 {
+            log::debug!(
+                "Dropped {} paths listed twice under different casing",
+                dropped
+            );
+            stats.files_scanned -= dropped;
+            map.retain(|_, v| v.len() > 1);
+        }
     }
-    progress.finish();
-
-    // Filter out single occurrences
-    map.retain(|_, v| v.len() > 1);
+    stats.size_groups = map.len();
 
-    log::info!("Finished in {} seconds", instant.elapsed().as_secs_f32());
+    stats.size_grouping_seconds = instant.elapsed().as_secs_f64();
+    log::info!("Finished in {} seconds", stats.size_grouping_seconds);
 
     let instant = Instant::now();
 
     log::info!("[3/3] Grouping by hash in thread pool");
+    progress.phase(Phase::Hashing);
+
+    // Pick up size groups finished by an interrupted run of the same scan
+    let checkpoint = resume.map(|path| {
+        let scan = checkpoint::scan_key(drive, matchers, excludes, &comparison, &algorithm);
+        let resumed = Checkpoint::resume(path, &scan, checkpoint::fingerprint(&map));
+        if !resumed.completed.is_empty() {
+            log::info!(
+                "Resuming with {} size groups already hashed",
+                resumed.completed.len()
+            );
+        }
+        resumed
+    });
+    let completed: HashSet<u64> = checkpoint
+        .iter()
+        .flat_map(|c| c.completed.iter().copied())
+        .collect();
 
     // Print all duplicates and collect them
-    let duplicates = Mutex::new(Vec::new());
-    let keys: Vec<u64> = map.keys().cloned().collect();
+    let duplicates = Mutex::new(
+        checkpoint
+            .as_ref()
+            .map(|c| c.groups.clone())
+            .unwrap_or_default(),
+    );
+    let keys: Vec<u64> = map
+        .keys()
+        .filter(|size| !completed.contains(size))
+        .cloned()
+        .collect();
+    let checkpoint = checkpoint
+        .zip(resume)
+        .map(|(checkpoint, path)| CheckpointWriter::new(path, checkpoint));
 
-    let progress = ProgressBar::new(keys.len() as u64);
+    let reporter = progress;
+    // One huge size group can take minutes, so also show bytes hashed
+    let total_bytes: u64 = keys.iter().map(|size| size * map[size].len() as u64).sum();
+    let multi = progress.multi();
+    let hashed = ByteCounter::new(multi.add(progress.bytes_bar(total_bytes)));
+    let progress = progress.counter_on(
+        multi.add(progress.bar(keys.len() as u64)),
+        keys.len() as u64,
+    );
+    let errors = FileErrors::default();
+    let hash_groups = AtomicUsize::new(0);
 
     // Iterate through size groups simultaneously
     keys.par_iter().for_each(|size: &u64| {
+        // Skip what's left, the groups already hashed are still reported
+        if reporter.is_cancelled() {
+            return;
+        }
         progress.inc(1);
-        let same_size_paths = &map[size];
+        let paths: Vec<&Path> = map[size].iter().map(|entry| entry.path.as_path()).collect();
+        let same_size_paths = collapse_hardlinks(&paths);
+        if same_size_paths.len() < 2 {
+            if let Some(checkpoint) = &checkpoint {
+                checkpoint.complete(*size, &[]);
+            }
+            return;
+        }
+
+        // Fuzzy hashes read blocks across the whole file, and so does the
+        // strict prefilter before reading files whole, so first rule out
+        // files that already differ at either end
+        let prefilter = prefilter && comparison == Comparison::Strict && worth_prefiltering(*size);
+        let mut candidates = match comparison {
+            _ if prefilter => partition_by_edge_blocks(*size, &same_size_paths, &errors, gate),
+            Comparison::Fuzzy if *size > EDGE_BLOCK_SIZE => {
+                partition_by_edge_blocks(*size, &same_size_paths, &errors, gate)
+            }
+            _ => vec![same_size_paths],
+        };
+
+        // Most same-size files differ somewhere in the fuzzy hash's samples,
+        // so only the files still matching there are read whole. The samples
+        // aren't counted, the bytes bar is for the full reads.
+        if prefilter {
+            let fuzzy = Comparison::Fuzzy;
+            candidates = candidates
+                .iter()
+                .flat_map(|paths| {
+                    group_by_hash(
+                        *size,
+                        paths,
+                        &fuzzy,
+                        HashAlgorithm::default_for(&fuzzy),
+                        mmap_threshold,
+                        &errors,
+                        &ByteCounter::default(),
+                        gate,
+                    )
+                })
+                .collect();
+        }
 
         // Parallelize the hashing of files within the same size group
-        let reduced_groups: Vec<Vec<&Path>> = if same_size_paths.len() > 1 {
-            // Group by hash locally
-            let mut reduced_map: HashMap<String, Vec<&Path>> = HashMap::new();
+        let mut reduced_groups: Vec<Vec<&Path>> = candidates
+            .iter()
+            .flat_map(|paths| {
+                group_by_hash(
+                    *size,
+                    paths,
+                    &comparison,
+                    algorithm,
+                    mmap_threshold,
+                    &errors,
+                    &hashed,
+                    gate,
+                )
+            })
+            .collect();
+        hash_groups.fetch_add(reduced_groups.len(), Ordering::Relaxed);
 
-            // Collect hashes in parallel
-            let hashes: Vec<Option<(String, &Path)>> = same_size_paths
-                .par_iter()
-                .map(|path| {
-                    let hash_result = match comparison {
-                        Comparison::Fuzzy => {
-                            calculate_fuzzy_hash(*size, path).map(|h| h.to_string())
-                        }
-                        Comparison::Strict => calculate_full_hash(path).map(|h| h.to_string()),
-                    };
+        // Confirm strong hash matches byte by byte before reporting them
+        if let Comparison::Strict = comparison {
+            reduced_groups = reduced_groups
+                .into_iter()
+                .flat_map(|group| verify_identical(&group, &errors, gate))
+                .collect();
+        }
+
+        // Files skipped after a Ctrl-C leave the group incomplete, it is
+        // neither reported nor checkpointed
+        if reporter.is_cancelled() {
+            return;
+        }
+
+        // Keep the modification times the backend listed, if it had all
+        let mtimes: HashMap<&Path, Option<u64>> = map[size]
+            .iter()
+            .map(|entry| (entry.path.as_path(), entry.mtime))
+            .collect();
+        let groups: Vec<DuplicateGroup> = reduced_groups
+            .into_iter()
+            .map(|same_hash_paths| DuplicateGroup {
+                size: *size,
+                mtimes: same_hash_paths.iter().map(|p| mtimes[p]).collect(),
+                paths: same_hash_paths
+                    .into_iter()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect(),
+                confidence: Confidence::of(&comparison),
+            })
+            .collect();
+        if let Some(checkpoint) = &checkpoint {
+            checkpoint.complete(*size, &groups);
+        }
+        duplicates.lock().unwrap().extend(groups);
+    });
+
+    progress.finish();
+    hashed.finish();
+    stats.interrupted = reporter.is_cancelled();
+    if let Some(checkpoint) = checkpoint {
+        if stats.interrupted {
+            checkpoint.save();
+        } else {
+            checkpoint.finish();
+        }
+    }
+    if stats.interrupted {
+        log::warn!("Interrupted, reporting the duplicates found so far");
+    }
+
+    stats.hash_groups = hash_groups.into_inner();
+    stats.hashing_seconds = instant.elapsed().as_secs_f64();
+    log::info!("Finished in {} seconds", stats.hashing_seconds);
+    listing_errors.log_summary("listing");
+    errors.log_summary("hashing");
+    stats.errors = listing_errors.count() + errors.count();
+    stats.changed_files = listing_errors.changed() + errors.changed();
+    let mut duplicates =
+        duplicates
+            .into_inner()
+            .map_err(|_| crate::error::AppError::LockPoison {
+                message: "Duplicate groups mutex was poisoned".to_string(),
+            })?;
+    sort_groups(&mut duplicates, SortOrder::default());
+
+    let summary = summarize(&duplicates);
+    stats.duplicate_groups = summary.groups;
+    stats.redundant_files = summary.redundant_files;
+    stats.reclaimable_bytes = summary.reclaimable_bytes;
+    Ok((duplicates, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(file: &fs::File) {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::ioapiset::DeviceIoControl;
+        use winapi::um::winioctl::FSCTL_SET_COMPRESSION;
+        use winapi::um::winnt::COMPRESSION_FORMAT_DEFAULT;
+
+        let format = COMPRESSION_FORMAT_DEFAULT;
+        let mut returned_bytes: u32 = 0;
+        let res = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle() as _,
+                FSCTL_SET_COMPRESSION,
+                &format as *const u16 as _,
+                std::mem::size_of_val(&format) as u32,
+                std::ptr::null_mut(),
+                0,
+                &mut returned_bytes,
+                std::ptr::null_mut(),
+            )
+        };
+        assert_ne!(res, 0, "{}", io::Error::last_os_error());
+    }
+
+    /// Hash a whole file at whatever size it has now
+    fn calculate_full_hash(
+        path: &Path,
+        algorithm: HashAlgorithm,
+        mmap_threshold: u64,
+    ) -> io::Result<Digest> {
+        let file = fs::File::open(path)?;
+        let size = file.metadata()?.len();
+        hash_whole_file(
+            file,
+            size,
+            algorithm,
+            mmap_threshold,
+            &OpenFileGate::default(),
+        )
+    }
+
+    fn noise(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compressed_copies_count_at_their_size_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("log.txt");
+        let copy = dir.path().join("log (1).txt");
+        let text = b"2026-10-16 09:00:00 INFO nothing happened\r\n".repeat(20_000);
+        fs::write(&original, &text).unwrap();
+        let file = fs::File::create(&copy).unwrap();
+        compress(&file);
+        let mut file = file;
+        io::Write::write_all(&mut file, &text).unwrap();
+        drop(file);
+
+        let groups = vec![DuplicateGroup {
+            size: text.len() as u64,
+            paths: vec![
+                original.to_string_lossy().to_string(),
+                copy.to_string_lossy().to_string(),
+            ],
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+        assert_eq!(summarize(&groups).reclaimable_bytes, text.len() as u64);
+
+        let usage = allocated_usage(&groups);
+        assert_eq!(usage.compressed_files, 1);
+        assert_eq!(usage.sparse_files, 0);
+        assert_eq!(usage.bytes, allocated_size(&copy).unwrap());
+        assert!(usage.bytes < text.len() as u64 / 4, "{:?}", usage);
+
+        // Only the redundant copies count, a compressed leader stays
+        let leader_first = vec![DuplicateGroup {
+            paths: groups[0].paths.iter().rev().cloned().collect(),
+            ..groups[0].clone()
+        }];
+        assert_eq!(
+            allocated_usage(&leader_first),
+            AllocatedUsage {
+                bytes: text.len() as u64,
+                ..AllocatedUsage::default()
+            }
+        );
+    }
+
+    #[test]
+    fn edited_copy_lands_in_similar_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = noise(1, 400 * 1024);
+        // Rewrite 10% in the middle, the chunks around it stay the same
+        let mut edited = original.clone();
+        edited[180 * 1024..220 * 1024].copy_from_slice(&noise(2, 40 * 1024));
+        let unrelated = noise(3, 400 * 1024);
+
+        let paths: Vec<PathBuf> = ["report.doc", "report (edited).doc", "other.doc"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        fs::write(&paths[0], &original).unwrap();
+        fs::write(&paths[1], &edited).unwrap();
+        fs::write(&paths[2], &unrelated).unwrap();
+
+        let gate = OpenFileGate::default();
+        let first = chunk_fingerprint(&original[..], &gate).unwrap();
+        let second = chunk_fingerprint(&edited[..], &gate).unwrap();
+        let shared = first.iter().filter(|c| second.contains(c)).count();
+        assert!(
+            shared * 10 >= first.len() * 8,
+            "{} of {}",
+            shared,
+            first.len()
+        );
+
+        let refs: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let groups = similarity(&refs, 0.7, &FileErrors::default(), &OpenFileGate::default());
+
+        assert_eq!(groups.len(), 1);
+        let mut expected = vec![
+            paths[0].to_string_lossy().to_string(),
+            paths[1].to_string_lossy().to_string(),
+        ];
+        expected.sort();
+        assert_eq!(groups[0].paths, expected);
+        assert!(groups[0].similarity >= 0.7 && groups[0].similarity < 1.0);
+    }
+
+    #[test]
+    fn strict_separates_crc32_collision() {
+        // "plumless" and "buckeroo" share the same CRC-32 (IEEE) checksum
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        let c = dir.path().join("c.txt");
+        fs::write(&a, b"plumless").unwrap();
+        fs::write(&b, b"buckeroo").unwrap();
+        fs::write(&c, b"plumless").unwrap();
+
+        let paths = [a.as_path(), b.as_path(), c.as_path()];
+        let groups = group_by_hash(
+            8,
+            &paths,
+            &Comparison::Strict,
+            HashAlgorithm::Blake3,
+            DEFAULT_MMAP_THRESHOLD,
+            &FileErrors::default(),
+            &ByteCounter::default(),
+            &OpenFileGate::default(),
+        );
+
+        assert_eq!(groups.len(), 1);
+        let mut group = groups[0].clone();
+        group.sort();
+        assert_eq!(group, vec![a.as_path(), c.as_path()]);
+    }
+
+    #[test]
+    fn unreadable_file_is_counted_as_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = dir.path().join("a.txt");
+        let b = dir.path().join("b.txt");
+        let missing = dir.path().join("missing.txt");
+        fs::write(&a, b"same").unwrap();
+        fs::write(&b, b"same").unwrap();
+
+        let errors = FileErrors::default();
+        let hashed = ByteCounter::default();
+        let paths = [a.as_path(), b.as_path(), missing.as_path()];
+        let groups = group_by_hash(
+            4,
+            &paths,
+            &Comparison::Strict,
+            HashAlgorithm::Blake3,
+            DEFAULT_MMAP_THRESHOLD,
+            &errors,
+            &hashed,
+            &OpenFileGate::default(),
+        );
+
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].len(), 2);
+        assert_eq!(errors.count(), 1);
+        // Only the files that were actually hashed count towards progress
+        assert_eq!(hashed.total(), 2 * 4);
+    }
+
+    #[test]
+    fn edge_blocks_separate_files_differing_at_the_start() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = vec![0u8; 64 * 1024];
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        let c = dir.path().join("c.bin");
+        fs::write(&a, &content).unwrap();
+        fs::write(&c, &content).unwrap();
+        content[0] = 1;
+        fs::write(&b, &content).unwrap();
+
+        let errors = FileErrors::default();
+        let paths = [a.as_path(), b.as_path(), c.as_path()];
+        let partitions = partition_by_edge_blocks(
+            content.len() as u64,
+            &paths,
+            &errors,
+            &OpenFileGate::default(),
+        );
+
+        assert_eq!(partitions.len(), 1);
+        let mut partition = partitions[0].clone();
+        partition.sort();
+        assert_eq!(partition, vec![a.as_path(), c.as_path()]);
+        assert_eq!(errors.count(), 0);
+    }
+
+    #[test]
+    fn hardlinks_collapse_to_one_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = dir.path().join("a.bin");
+        let link = dir.path().join("b.bin");
+        let copy = dir.path().join("c.bin");
+        fs::write(&original, b"shared").unwrap();
+        fs::hard_link(&original, &link).unwrap();
+        fs::write(&copy, b"shared").unwrap();
+
+        let paths = [link.as_path(), copy.as_path(), original.as_path()];
+        let collapsed = collapse_hardlinks(&paths);
 
-                    hash_result.ok().map(|hash| (hash, *path))
+        assert_eq!(collapsed, [original.as_path(), copy.as_path()]);
+    }
+
+    #[test]
+    fn digests_are_stable_per_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("check.txt");
+        fs::write(&path, b"123456789").unwrap();
+
+        for name in HashAlgorithm::NAMES {
+            let algorithm: HashAlgorithm = name.parse().unwrap();
+            let first = calculate_full_hash(&path, algorithm, DEFAULT_MMAP_THRESHOLD).unwrap();
+            let second = calculate_full_hash(&path, algorithm, DEFAULT_MMAP_THRESHOLD).unwrap();
+            assert_eq!(first, second, "{} digest is not stable", name);
+        }
+
+        // Standard check values for the "123456789" test vector
+        assert_eq!(
+            calculate_full_hash(&path, HashAlgorithm::Crc32, DEFAULT_MMAP_THRESHOLD).unwrap(),
+            Digest::U32(0xCBF4_3926)
+        );
+        assert_eq!(
+            calculate_full_hash(&path, HashAlgorithm::XxHash64, DEFAULT_MMAP_THRESHOLD).unwrap(),
+            Digest::U64(0x8CB8_41DB_40E6_AE83)
+        );
+        assert_eq!(
+            calculate_full_hash(&path, HashAlgorithm::Xxh3, DEFAULT_MMAP_THRESHOLD).unwrap(),
+            Digest::U64(0x72DC_B18B_67A1_7DFF)
+        );
+    }
+
+    #[test]
+    #[ignore = "benchmark, `ddup bench` compares the algorithms on real files"]
+    fn xxh3_outpaces_crc32() {
+        let buffer: Vec<u8> = (0..50 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+        // Best of a few runs, so a busy machine doesn't decide the result
+        let fastest = |algorithm: HashAlgorithm| {
+            (0..3)
+                .map(|_| {
+                    let instant = Instant::now();
+                    let mut state = DigestState::new(algorithm);
+                    for chunk in buffer.chunks(READ_BUFFER_SIZE) {
+                        state.update(chunk);
+                    }
+                    std::hint::black_box(state.finalize());
+                    instant.elapsed()
                 })
-                .collect();
+                .min()
+                .unwrap()
+        };
+
+        let xxh3 = fastest(HashAlgorithm::Xxh3);
+        let crc32 = fastest(HashAlgorithm::Crc32);
+        assert!(xxh3 < crc32, "xxh3 took {:?}, crc32 {:?}", xxh3, crc32);
+    }
+
+    /// Hands out at most a few bytes per read, like a file with sparse
+    /// regions or a slow network share
+    struct ShortReads<R>(R);
+
+    impl<R: Read> Read for ShortReads<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(7);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    impl<R: Seek> Seek for ShortReads<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.0.seek(pos)
+        }
+    }
 
-            // Group by hash locally (sequential aggregation is fast enough for reduced set)
-            for (hash, path) in hashes.into_iter().flatten() {
-                reduced_map.entry(hash).or_default().push(path);
+    #[test]
+    fn fuzzy_hash_ignores_short_reads() {
+        for size in [1u64, 4095, 4096, 4097, 100_000, 3 * 1024 * 1024 + 17] {
+            let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+            let chunks = fuzzy_chunks(size);
+
+            let mut whole = DigestState::new(HashAlgorithm::Xxh3);
+            for &(offset, len) in &chunks {
+                whole.update(&content[offset as usize..offset as usize + len]);
             }
 
-            reduced_map.retain(|_, v| v.len() > 1);
-            reduced_map.into_values().collect()
-        } else {
-            Vec::new()
+            let mut short = DigestState::new(HashAlgorithm::Xxh3);
+            let mut reader = ShortReads(io::Cursor::new(&content));
+            read_chunks(&mut reader, &chunks, &mut short, &OpenFileGate::default()).unwrap();
+
+            assert_eq!(short.finalize(), whole.finalize(), "size {}", size);
+        }
+    }
+
+    #[test]
+    fn mmap_and_buffered_hashes_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.bin");
+        let size = 100 * 1024 * 1024;
+        let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        fs::write(&path, &content).unwrap();
+
+        let gate = OpenFileGate::default();
+        for comparison in [Comparison::Fuzzy, Comparison::Strict] {
+            let algorithm = HashAlgorithm::default_for(&comparison);
+            let digest = |threshold| {
+                calculate_digest(size as u64, &path, &comparison, algorithm, threshold, &gate)
+            };
+            let mapped = digest(0).unwrap();
+            let buffered = digest(u64::MAX).unwrap();
+            assert_eq!(mapped, buffered, "{:?} digests differ", comparison);
+        }
+    }
+
+    #[test]
+    fn empty_file_hashes_without_mapping() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.bin");
+        fs::write(&path, b"").unwrap();
+
+        assert!(calculate_full_hash(&path, HashAlgorithm::Blake3, 0).is_ok());
+    }
+
+    #[test]
+    fn file_changed_since_listing_is_not_hashed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("grown.bin");
+        fs::write(&path, vec![7u8; 10_000]).unwrap();
+
+        let gate = OpenFileGate::default();
+        for comparison in [Comparison::Fuzzy, Comparison::Strict] {
+            let digest =
+                |size| calculate_digest(size, &path, &comparison, HashAlgorithm::Xxh3, 0, &gate);
+            assert!(digest(10_000).is_ok());
+            let err = digest(8_000).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData, "{:?}", comparison);
+            assert!(FileChanged::is(&err), "{:?}", comparison);
+        }
+        let err = edge_digest(8_000, &path, &gate).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // Grown or shrunk after it was opened, buffered or mapped
+        for threshold in [0, u64::MAX] {
+            let file = fs::File::open(&path).unwrap();
+            let err =
+                hash_whole_file(file, 8_000, HashAlgorithm::Xxh3, threshold, &gate).unwrap_err();
+            assert!(FileChanged::is(&err), "{}", threshold);
+        }
+    }
+
+    #[test]
+    fn bench_hashes_a_tree_with_every_algorithm() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("nested")).unwrap();
+        fs::write(dir.path().join("a.bin"), vec![1u8; 300_000]).unwrap();
+        fs::write(dir.path().join("nested").join("b.bin"), vec![2u8; 5_000]).unwrap();
+        fs::write(dir.path().join("empty.bin"), b"").unwrap();
+        let listing = DirList::from_walkdir(
+            dir.path().to_str().unwrap(),
+            &[],
+            &[],
+            glob::MatchOptions::new(),
+            EntryFilter::default(),
+        )
+        .unwrap();
+        let files: Vec<FileEntry> = listing.into_iter().collect();
+        let algorithms: Vec<HashAlgorithm> = HashAlgorithm::NAMES
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect();
+
+        let strict = bench(&files, &Comparison::Strict, &algorithms, 0);
+        assert_eq!(strict.len(), algorithms.len());
+        for result in &strict {
+            assert_eq!(result.files, 3, "{:?}", result.algorithm);
+            assert_eq!(result.bytes, 305_000);
+            assert!(result.megabytes_per_second() > 0.0);
+        }
+
+        let fuzzy = bench(&files, &Comparison::Fuzzy, &[HashAlgorithm::Xxh3], 0);
+        assert_eq!(
+            fuzzy[0].bytes,
+            hashed_bytes(300_000, &Comparison::Fuzzy) + hashed_bytes(5_000, &Comparison::Fuzzy)
+        );
+        assert!(fuzzy[0].bytes < 305_000);
+    }
+
+    #[test]
+    fn summary_counts_reclaimable_bytes() {
+        let group = |size, count: usize| DuplicateGroup {
+            size,
+            paths: (0..count).map(|i| format!(r"C:\{}.bin", i)).collect(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        };
+        let duplicates = [group(100, 2), group(1000, 4)];
+
+        let summary = summarize(&duplicates);
+
+        assert_eq!(
+            summary,
+            Summary {
+                groups: 2,
+                redundant_files: 4,
+                reclaimable_bytes: 100 + 3 * 1000,
+            }
+        );
+        assert_eq!(summarize(&[]), Summary::default());
+    }
+
+    #[test]
+    fn breakdown_buckets_redundant_copies_by_extension() {
+        let group = |size, paths: &[&str]| DuplicateGroup {
+            size,
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        };
+        let duplicates = [
+            group(1000, &[r"C:\a.mp4", r"C:\b.MP4", r"C:\c.mp4"]),
+            group(300, &[r"C:\a.jpg", r"C:\b.jpg"]),
+            // A copy under another name counts where that copy is
+            group(500, &[r"C:\a.mp4.bak", r"C:\b.mp4"]),
+            group(50, &[r"C:\README", r"C:\docs\README"]),
+            group(10, &[r"C:\x.txt", r"C:\y.txt"]),
+        ];
+
+        let shares = breakdown_by_extension(&duplicates, 3);
+
+        let share = |extension: &str, redundant_files, reclaimable_bytes| ExtensionShare {
+            extension: extension.to_string(),
+            redundant_files,
+            reclaimable_bytes,
+        };
+        assert_eq!(
+            shares,
+            [
+                share("mp4", 3, 2500),
+                share("jpg", 1, 300),
+                share("", 1, 50)
+            ]
+        );
+
+        let all = breakdown_by_extension(&duplicates, usize::MAX);
+        assert_eq!(all.len(), 4);
+        assert_eq!(
+            all.iter().map(|s| s.reclaimable_bytes).sum::<u64>(),
+            summarize(&duplicates).reclaimable_bytes
+        );
+        assert!(breakdown_by_extension(&[], 10).is_empty());
+    }
+
+    #[test]
+    fn directory_breakdown_splits_copies_by_backup_generation() {
+        let group = |size, paths: &[&str]| DuplicateGroup {
+            size,
+            paths: paths.iter().map(|p| p.to_string()).collect(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        };
+        let duplicates = [
+            group(
+                1000,
+                &[
+                    r"D:\Backup\2024-01\Photos\a.jpg",
+                    r"D:\Backup\2024-02\Photos\a.jpg",
+                ],
+            ),
+            group(
+                300,
+                &[
+                    r"D:\Backup\2024-01\notes.txt",
+                    r"D:\Backup\2024-02\notes.txt",
+                    r"D:\Backup\2024-02\notes (copy).txt",
+                ],
+            ),
+            // Only duplicated within the newer generation
+            group(
+                50,
+                &[r"D:\Backup\2024-02\x.bin", r"D:\Backup\2024-02\Old\x.bin"],
+            ),
+            group(10, &[r"D:\Backup\log.txt", r"D:\Backup\2024-01\log.txt"]),
+        ];
+
+        let (root, shares) = breakdown_by_directory(&duplicates);
+
+        assert_eq!(root, Path::new(r"D:\Backup"));
+        let share = |directory: &str, files, bytes, elsewhere_bytes| DirectoryShare {
+            directory: directory.to_string(),
+            files,
+            bytes,
+            elsewhere_bytes,
+        };
+        assert_eq!(
+            shares,
+            [
+                share("2024-02", 5, 1000 + 600 + 100, 1000 + 600),
+                share("2024-01", 3, 1000 + 300 + 10, 1000 + 300 + 10),
+                share("", 1, 10, 10),
+            ]
+        );
+        assert_eq!(breakdown_by_directory(&[]).1, []);
+    }
+
+    #[test]
+    fn export_round_trips_through_import() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+        for name in ["a.bin", "b.bin", "c.bin"] {
+            fs::write(path(name), b"same").unwrap();
+        }
+        let groups = vec![
+            DuplicateGroup {
+                size: 4,
+                paths: vec![path("a.bin"), path("b.bin"), path("c.bin")],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+            DuplicateGroup {
+                size: 9,
+                paths: vec![path("gone1.bin"), path("gone2.bin")],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+        ];
+
+        let export = dir.path().join("export.json");
+        crate::export::write_json(&groups, fs::File::create(&export).unwrap()).unwrap();
+        let mut imported = DuplicateGroup::load_json(&export).unwrap();
+        assert_eq!(imported.serialize_json(), groups.serialize_json());
+
+        fs::remove_file(path("c.bin")).unwrap();
+        assert_eq!(drop_stale_paths(&mut imported), 3);
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].paths, [path("a.bin"), path("b.bin")]);
+    }
+
+    #[test]
+    fn groups_sort_by_requested_order() {
+        let group = |size, names: &[&str]| DuplicateGroup {
+            size,
+            paths: names.iter().map(|n| n.to_string()).collect(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        };
+        let mut groups = vec![
+            group(10, &["z", "y", "x", "w"]),
+            group(100, &["b", "a"]),
+            group(20, &["d", "c"]),
+        ];
+
+        sort_groups(&mut groups, SortOrder::Size);
+        assert_eq!(groups[0].paths, ["a", "b"]);
+        assert_eq!(groups[1].paths, ["w", "x", "y", "z"]);
+
+        sort_groups(&mut groups, SortOrder::Count);
+        assert_eq!(groups[0].size, 10);
+
+        sort_groups(&mut groups, SortOrder::Path);
+        let sizes: Vec<u64> = groups.iter().map(|g| g.size).collect();
+        assert_eq!(sizes, [100, 20, 10]);
+    }
+
+    #[test]
+    fn limit_keeps_the_largest_groups() {
+        let group = |size, copies| DuplicateGroup {
+            size,
+            paths: (0..copies).map(|i| format!("{}-{}", size, i)).collect(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        };
+        // Reclaimable: 10, 90, 40, 300, 20
+        let mut groups = vec![
+            group(10, 2),
+            group(30, 4),
+            group(40, 2),
+            group(100, 4),
+            group(5, 5),
+        ];
+
+        let rest = split_off_largest(&mut groups, 3);
+        let kept: Vec<u64> = groups.iter().map(reclaimable).collect();
+        assert_eq!(kept, [300, 90, 40]);
+        let mut left_out: Vec<u64> = rest.iter().map(reclaimable).collect();
+        left_out.sort_unstable();
+        assert_eq!(left_out, [10, 20]);
+
+        assert!(split_off_largest(&mut groups, 5).is_empty());
+        assert_eq!(groups.len(), 3);
+    }
+
+    #[test]
+    fn case_variants_collapse_to_one_entry() {
+        let mut paths = vec![
+            PathBuf::from(r"C:\Foo\bar.txt"),
+            PathBuf::from(r"C:\foo\Bar.txt"),
+            PathBuf::from(r"C:\foo\other.txt"),
+        ];
+
+        assert_eq!(dedup_case_variants(&mut paths), 1);
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from(r"C:\Foo\bar.txt"),
+                PathBuf::from(r"C:\foo\other.txt")
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_gate_leaves_files_unread() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = (0..4)
+            .map(|i| dir.path().join(format!("{}.bin", i)))
+            .collect();
+        for path in &paths {
+            fs::write(path, b"same").unwrap();
+        }
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+
+        let cancel = crate::CancelFlag::new();
+        let gate = OpenFileGate::default().with_cancel(cancel.clone());
+        let errors = FileErrors::default();
+        let hash = |gate: &OpenFileGate| {
+            group_by_hash(
+                4,
+                &paths,
+                &Comparison::Strict,
+                HashAlgorithm::default_for(&Comparison::Strict),
+                DEFAULT_MMAP_THRESHOLD,
+                &errors,
+                &ByteCounter::default(),
+                gate,
+            )
         };
+        assert_eq!(hash(&gate).len(), 1);
+
+        cancel.cancel();
+        assert!(hash(&gate).is_empty());
+        assert!(partition_by_edge_blocks(4, &paths, &errors, &gate).is_empty());
+        assert!(verify_identical(&paths, &errors, &gate).is_empty());
+        assert_eq!(errors.count(), 0);
+    }
+
+    #[test]
+    fn grouping_is_the_same_when_fed_lazily() {
+        let entry =
+            |i: u64| FileEntry::new(PathBuf::from(format!(r"C:\files\{}.bin", i)), i % 7 * 100);
+        let listed: Vec<FileEntry> = (0..1000).map(entry).collect();
+        let progress = ProgressReporter::hidden().counter(1000);
+
+        let from_list = group_by_size(listed, 200, &progress);
+        // Each entry is made only when grouping asks for it, the way
+        // `DirList::entries_stream` hands them out
+        let from_iterator = group_by_size((0..1000).map(entry), 200, &progress);
+
+        assert_eq!(from_iterator, from_list);
+        assert_eq!(from_iterator.len(), 5);
+        assert_eq!(from_iterator[&300].len(), 143);
+        assert!(!from_iterator.contains_key(&100));
+    }
 
-        for same_hash_paths in reduced_groups {
-            let paths: Vec<String> = same_hash_paths
+    #[test]
+    fn min_size_excludes_small_files() {
+        let entries = [
+            (PathBuf::from(r"C:\a.txt"), 512),
+            (PathBuf::from(r"C:\b.txt"), 512),
+            (PathBuf::from(r"C:\c.bin"), 4096),
+            (PathBuf::from(r"C:\d.bin"), 4096),
+        ];
+        let progress = ProgressReporter::hidden().counter(entries.len() as u64);
+
+        let all = group_by_size(entries.clone().map(FileEntry::from), 0, &progress);
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[&512].len(), 2);
+
+        let min_size = crate::utils::parse_size("1K").unwrap();
+        let large = group_by_size(entries.map(FileEntry::from), min_size, &progress);
+        assert_eq!(large.len(), 1);
+        assert!(!large.contains_key(&512));
+    }
+
+    #[test]
+    fn scoped_pool_produces_same_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = (0..8)
+            .map(|i| {
+                let path = dir.path().join(format!("{}.bin", i));
+                fs::write(&path, [(i % 3) as u8; 64]).unwrap();
+                path
+            })
+            .collect();
+        let paths: Vec<&Path> = paths.iter().map(|p| p.as_path()).collect();
+
+        let normalize = |groups: Vec<Vec<&Path>>| {
+            let mut groups: Vec<Vec<PathBuf>> = groups
                 .into_iter()
-                .map(|p| p.to_string_lossy().to_string())
+                .map(|g| g.into_iter().map(Path::to_path_buf).collect())
                 .collect();
+            groups.iter_mut().for_each(|g| g.sort());
+            groups.sort();
+            groups
+        };
+        let group = || {
+            group_by_hash(
+                64,
+                &paths,
+                &Comparison::Strict,
+                HashAlgorithm::Blake3,
+                DEFAULT_MMAP_THRESHOLD,
+                &FileErrors::default(),
+                &ByteCounter::default(),
+                &OpenFileGate::default(),
+            )
+        };
 
-            {
-                let mut guard = duplicates.lock().unwrap();
-                guard.push(DuplicateGroup {
-                    size: *size,
-                    paths: paths.clone(),
-                });
-            }
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .build()
+            .unwrap();
+        let scoped = normalize(pool.install(group));
+        let global = normalize(group());
+
+        assert_eq!(scoped.len(), 3);
+        assert_eq!(scoped, global);
+    }
+
+    #[test]
+    fn reverify_splits_off_a_file_edited_since_the_export() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        for name in ["a.bin", "b.bin", "c.bin", "d.bin"] {
+            fs::write(path(name), b"exported bytes").unwrap();
         }
-    });
+        for name in ["x.bin", "y.bin"] {
+            fs::write(path(name), b"other group").unwrap();
+        }
+        let groups = vec![
+            DuplicateGroup {
+                size: 14,
+                paths: vec![path("a.bin"), path("b.bin"), path("c.bin"), path("d.bin")],
+                mtimes: Some(vec![1, 2, 3, 4]),
+                confidence: Confidence::Exact,
+            },
+            DuplicateGroup {
+                size: 11,
+                paths: vec![path("x.bin"), path("y.bin")],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+        ];
+        // Edited in place since the export, keeping its size
+        fs::write(path("c.bin"), b"EXPORTED BYTES").unwrap();
 
-    progress.finish();
+        let errors = FileErrors::default();
+        let reverified = reverify(
+            groups,
+            HashAlgorithm::Blake3,
+            &errors,
+            &ProgressReporter::hidden(),
+            &OpenFileGate::default(),
+        );
 
-    log::info!("Finished in {} seconds", instant.elapsed().as_secs_f32());
-    duplicates
-        .into_inner()
-        .map_err(|_| crate::error::AppError::LockPoison {
-            message: "Duplicate groups mutex was poisoned".to_string(),
-        })
+        assert_eq!(errors.count(), 0);
+        assert_eq!(reverified.len(), 2);
+        assert_eq!(
+            reverified[0].paths,
+            vec![path("a.bin"), path("b.bin"), path("d.bin")]
+        );
+        assert_eq!(reverified[0].mtimes, Some(vec![1, 2, 4]));
+        assert_eq!(reverified[1].paths, vec![path("x.bin"), path("y.bin")]);
+    }
+
+    #[test]
+    fn reverify_drops_times_not_matching_the_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().to_string();
+        for name in ["a.bin", "b.bin", "c.bin"] {
+            fs::write(path(name), b"exported bytes").unwrap();
+        }
+        // One time short, as left by hand-editing the export
+        let export = dir.path().join("edited.jsonl");
+        let group = DuplicateGroup {
+            size: 14,
+            paths: vec![path("a.bin"), path("b.bin"), path("c.bin")],
+            mtimes: Some(vec![1, 2]),
+            confidence: Confidence::Exact,
+        };
+        fs::write(&export, group.serialize_json()).unwrap();
+        let groups = DuplicateGroup::load_json(&export).unwrap();
+
+        let errors = FileErrors::default();
+        let reverified = reverify(
+            groups,
+            HashAlgorithm::Blake3,
+            &errors,
+            &ProgressReporter::hidden(),
+            &OpenFileGate::default(),
+        );
+
+        assert_eq!(reverified.len(), 1);
+        assert_eq!(reverified[0].paths.len(), 3);
+        assert_eq!(reverified[0].mtimes, None);
+    }
+
+    #[test]
+    fn verify_splits_files_differing_in_the_middle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = vec![0xABu8; 3 * VERIFY_CHUNK_SIZE];
+        let a = dir.path().join("a.bin");
+        let b = dir.path().join("b.bin");
+        let c = dir.path().join("c.bin");
+        fs::write(&a, &content).unwrap();
+        fs::write(&c, &content).unwrap();
+        content[VERIFY_CHUNK_SIZE + 17] = 0;
+        fs::write(&b, &content).unwrap();
+
+        let groups = verify_identical(
+            &[a.as_path(), b.as_path(), c.as_path()],
+            &FileErrors::default(),
+            &OpenFileGate::default(),
+        );
+
+        assert_eq!(groups.len(), 1);
+        let mut group = groups[0].clone();
+        group.sort();
+        assert_eq!(group, vec![a.as_path(), c.as_path()]);
+    }
+
+    #[test]
+    fn unreadable_member_leaves_the_rest_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = ["a.bin", "b.bin", "locked.bin"]
+            .iter()
+            .map(|name| dir.path().join(name))
+            .collect();
+        for path in &paths {
+            fs::write(path, vec![0x5Au8; 2 * VERIFY_CHUNK_SIZE]).unwrap();
+        }
+        // Opens fine, but another handle's lock fails every read of it
+        let lock = fs::File::open(&paths[2]).unwrap();
+        lock.lock().unwrap();
+
+        let errors = FileErrors::default();
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let groups = verify_identical(&paths, &errors, &OpenFileGate::default());
+
+        assert_eq!(groups, vec![vec![paths[0], paths[1]]]);
+        assert_eq!(errors.count(), 1);
+    }
+
+    #[test]
+    fn group_above_the_open_file_limit_is_verified_within_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut content = vec![0x3Cu8; 2 * VERIFY_CHUNK_SIZE + 100];
+        let paths: Vec<PathBuf> = (0..10)
+            .map(|i| dir.path().join(format!("{}.bin", i)))
+            .collect();
+        for path in &paths[..8] {
+            fs::write(path, &content).unwrap();
+        }
+        // Differing past the first chunk, from the others and each other
+        for (i, path) in paths[8..].iter().enumerate() {
+            content[VERIFY_CHUNK_SIZE + 5] = i as u8;
+            fs::write(path, &content).unwrap();
+        }
+
+        let gate = OpenFileGate::new(3);
+        let errors = FileErrors::default();
+        let paths: Vec<&Path> = paths.iter().map(PathBuf::as_path).collect();
+        let groups = verify_identical(&paths, &errors, &gate);
+
+        assert_eq!(groups, vec![paths[..8].to_vec()]);
+        assert_eq!(errors.count(), 0);
+        assert!(gate.peak() <= 3, "{} files open at once", gate.peak());
+    }
 }
@@ -2,14 +2,18 @@ use std::time::Instant;
 
 use clap::{Arg, ArgAction, ArgMatches, Command};
 
-use glob::MatchOptions;
-
-use ddup::algorithm::{self, Comparison};
+use ddup::algorithm::{
+    self, Comparison, Confidence, DedupReport, DuplicateGroup, HashAlgorithm, SortOrder,
+};
+use ddup::dirlist::EntryFilter;
+use ddup::{export, link, CancelFlag, DedupBuilder, ProgressReporter};
 use nanoserde::SerJson;
-use rayon::prelude::*;
 use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::ExitCode;
 
-fn parse_args() -> ArgMatches {
+fn cli() -> Command {
     Command::new("ddup")
         .about("This tool identifies duplicated files in Windows NTFS Volumes")
         .arg(
@@ -17,12 +21,63 @@ fn parse_args() -> ArgMatches {
                 .short('v')
                 .long("verbose")
                 .help("Enable verbose logging")
+                .global(true)
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("quiet")
+                .short('q')
+                .long("quiet")
+                .help("Only print duplicate paths, one per line with a blank line between groups, and errors")
+                .conflicts_with("verbose")
+                .global(true)
+                .action(ArgAction::SetTrue),
+        )
+        .args_conflicts_with_subcommands(true)
+        .subcommand_negates_reqs(true)
+        .subcommand(
+            Command::new("bench")
+                .about("Time the hashing of every file under a folder, without grouping or progress output")
+                .arg(
+                    Arg::new("path")
+                        .help("The folder whose files are hashed")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("hash")
+                        .long("hash")
+                        .value_name("ALGORITHM")
+                        .help("Algorithm to time, can be repeated (default: all of them)")
+                        .value_parser(HashAlgorithm::NAMES)
+                        .action(ArgAction::Append),
+                )
+                .arg(
+                    Arg::new("strict")
+                        .short('s')
+                        .long("strict")
+                        .help("Hash whole files instead of the sampled chunks of a fuzzy comparison")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("no-prefilter")
+                        .long("no-prefilter")
+                        .help("With --strict, hash every file whole instead of only those whose sampled chunks match another file's")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("mmap-threshold")
+                        .long("mmap-threshold")
+                        .value_name("BYTES")
+                        .help("Memory-map files larger than this many bytes when hashing")
+                        .value_parser(clap::value_parser!(u64))
+                        .num_args(1),
+                ),
+        )
         .arg(
             Arg::new("drive")
                 .help("The drive letter to scan (example `C:`)")
-                .required_unless_present("wiztree")
+                .required_unless_present_any(["wiztree", "file-list", "undo", "import"])
                 .index(1),
         )
         .arg(
@@ -30,13 +85,42 @@ fn parse_args() -> ArgMatches {
                 .short('m')
                 .long("match")
                 .value_name("PATTERN")
-                .help("Scan only paths that match the glob pattern (example `**.dmp`)")
-                .num_args(1),
+                .help("Scan only paths that match any of the glob patterns (example `**.dmp`)")
+                .value_parser(|s: &str| glob::Pattern::new(s))
+                .num_args(1..)
+                .action(ArgAction::Append),
+        )
+        .arg(
+            Arg::new("exclude")
+                .short('x')
+                .long("exclude")
+                .value_name("PATTERN")
+                .help("Skip paths that match the glob pattern, can be repeated (example `**\\node_modules\\**`)")
+                .value_parser(|s: &str| glob::Pattern::new(s))
+                .action(ArgAction::Append),
         )
         .arg(
             Arg::new("i")
                 .short('i')
-                .help("Treat the matcher as case-insensitive")
+                .help("Treat --match and --exclude patterns as case-insensitive")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("literal-separator")
+                .long("literal-separator")
+                .help("Keep `*` and `?` in --match and --exclude patterns from matching `\\`, so `D:\\*.jpg` skips subfolders")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("match-full-path")
+                .long("match-full-path")
+                .help("Match relative --match and --exclude patterns against the absolute path instead of the path below the scanned folder")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("literal-leading-dot")
+                .long("literal-leading-dot")
+                .help("Only match names starting with `.` by a pattern with a literal `.` there")
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -50,7 +134,46 @@ fn parse_args() -> ArgMatches {
             Arg::new("everything")
                 .short('E')
                 .long("everything")
-                .help("Use Everything search backend (instead of default USN journal)")
+                .help("Use Everything search backend (instead of probing for one)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("everything-instance")
+                .long("everything-instance")
+                .value_name("NAME")
+                .help("Connect to a named Everything instance, e.g. a portable install")
+                .requires("everything")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("walkdir")
+                .short('W')
+                .long("walkdir")
+                .help("Walk the given directory recursively (instead of probing for a backend)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("usn")
+                .long("usn")
+                .help("Read the volume's USN journal (instead of probing for a backend)")
+                .conflicts_with_all(["everything", "walkdir", "mft", "wiztree", "file-list"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("probe-order")
+                .long("probe-order")
+                .value_name("BACKENDS")
+                .help("Backends to try in turn when none is chosen, by default everything,usn,walkdir")
+                .value_parser(["everything", "usn", "mft", "walkdir"])
+                .value_delimiter(',')
+                .conflicts_with_all(["everything", "walkdir", "mft", "usn", "wiztree", "file-list"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("mft")
+                .long("mft")
+                .help("Read file records and sizes straight from the NTFS $MFT (instead of probing for a backend)")
+                .conflicts_with_all(["everything", "walkdir", "wiztree", "file-list", "cache"])
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -58,7 +181,78 @@ fn parse_args() -> ArgMatches {
                 .short('e')
                 .long("export")
                 .value_name("FILE")
-                .help("Export the duplicated file list to a JSON file")
+                .help("Export the duplicated file list to a file")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("report")
+                .long("report")
+                .value_name("FILE")
+                .help("Write a browsable HTML report of the duplicate groups, largest first")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("format")
+                .short('f')
+                .long("format")
+                .value_name("FORMAT")
+                .help("Format of the exported file list. `jsonl` writes a JSON object per group and line, `paths` one path per line. Both are printed instead of the groups without --export")
+                .value_parser(["json", "jsonl", "csv", "paths"])
+                .default_value("json")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("exclude-leader")
+                .long("exclude-leader")
+                .help("With --format paths, leave out the first file of each group so only the redundant copies are listed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("sort")
+                .long("sort")
+                .value_name("ORDER")
+                .help("Order of the reported groups: largest reclaimable size, most copies, or path")
+                .value_parser(SortOrder::NAMES)
+                .default_value("size")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("allocated-size")
+                .long("allocated-size")
+                .help("Also report the bytes the redundant copies occupy on disk, which is less than their size for sparse and compressed files")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("limit")
+                .long("limit")
+                .value_name("N")
+                .help("Only print and export the N groups with the most reclaimable space, the summary still counts all")
+                .value_parser(clap::value_parser!(usize))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("breakdown")
+                .long("breakdown")
+                .value_name("N")
+                .help("Also print the N file extensions (default 10, set with --breakdown=N) holding the most reclaimable space")
+                .value_parser(clap::value_parser!(usize))
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("10"),
+        )
+        .arg(
+            Arg::new("group-by-dir")
+                .long("group-by-dir")
+                .help("Also print how much duplicated data each folder below the common root of all duplicates holds, such as dated backup folders")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("min-copies")
+                .long("min-copies")
+                .value_name("N")
+                .help("Only report files with at least N copies, pairs with `--sort count` to find the most duplicated files")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("2")
                 .num_args(1),
         )
         .arg(
@@ -68,6 +262,109 @@ fn parse_args() -> ArgMatches {
                 .help("Replace duplicates with hardlinks")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("symlink")
+                .long("symlink")
+                .help("With --link, replace duplicates with symbolic links instead of hardlinks (works across volumes, may require elevation or Developer Mode)")
+                .requires("link")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("reflink")
+                .long("reflink")
+                .help("With --link, replace duplicates with copy-on-write clones of the leader (requires ReFS or Dev Drive)")
+                .requires("link")
+                .conflicts_with("symlink")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .help("With --link, link without asking for confirmation first")
+                .requires("link")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force-fuzzy-link")
+                .long("force-fuzzy-link")
+                .help("With --link, keep fuzzy comparison instead of switching to --strict. Files that only match on sampled blocks get linked too and their differences are LOST")
+                .requires("link")
+                .conflicts_with("strict")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("link-readonly")
+                .long("link-readonly")
+                .help("With --link, also replace read-only, system and hidden files, which are skipped by default")
+                .requires("link")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("keep")
+                .long("keep")
+                .value_name("POLICY")
+                .help("With --link, which file each group keeps as the link target: first path, oldest or newest modification time, shortest path, or lowest file id")
+                .value_parser(link::KeepPolicy::NAMES)
+                .default_value("first")
+                .requires("link")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("same-dir-only")
+                .long("same-dir-only")
+                .help("With --link, only link copies in the same folder to each other and leave copies in other folders alone")
+                .requires("link")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("organize")
+                .long("organize")
+                .value_name("DIR")
+                .help("With --link, move the kept file of each group into DIR, under its path below the folder common to all duplicates, and link the other copies to it there")
+                .requires("link")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("stable-leader")
+                .long("stable-leader")
+                .help("With --link, keep the file with the lowest NTFS file id, which doesn't change when files are renamed, so rerunning doesn't relink (same as --keep lowest-id)")
+                .requires("link")
+                .conflicts_with("keep")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("undo-log")
+                .long("undo-log")
+                .value_name("FILE")
+                .help("With --link, append every link made to a log that --undo can revert")
+                .requires("link")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("undo")
+                .long("undo")
+                .value_name("FILE")
+                .help("Turn the links recorded in an undo log back into independent copies")
+                .conflicts_with("link")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("recover")
+                .long("recover")
+                .help("Restore files left as *.ddup_tmp under the drive by an interrupted --link run")
+                .requires("drive")
+                .conflicts_with_all(["link", "undo", "import"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .short('n')
+                .long("dry-run")
+                .help("With --link, only log what would be linked without touching any file")
+                .requires("link")
+                .action(ArgAction::SetTrue),
+        )
         .arg(
             Arg::new("wiztree")
                 .short('w')
@@ -76,157 +373,1355 @@ fn parse_args() -> ArgMatches {
                 .help("Use a WizTree CSV file as the source")
                 .num_args(1),
         )
-        .get_matches()
+        .arg(
+            Arg::new("file-list")
+                .long("file-list")
+                .value_name("FILE")
+                .help("Compare exactly the files listed one per line in FILE, or on stdin for `-`")
+                .conflicts_with_all(["wiztree", "everything", "walkdir"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("cache")
+                .long("cache")
+                .value_name("FILE")
+                .help("Keep the USN path map in FILE and only read journal changes on later runs")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["wiztree", "everything", "walkdir", "file-list"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("resume")
+                .long("resume")
+                .value_name("FILE")
+                .help("Save hashing progress to FILE and skip size groups it already finished")
+                .value_parser(clap::value_parser!(PathBuf))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("import")
+                .long("import")
+                .value_name("FILE")
+                .help("Load duplicate groups from a previous JSON export instead of scanning")
+                .conflicts_with_all([
+                    "wiztree",
+                    "everything",
+                    "walkdir",
+                    "file-list",
+                    "cache",
+                    "resume",
+                ])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("reverify")
+                .long("reverify")
+                .help("With --import, hash and compare the imported files again and split off any that no longer match, always done before --link")
+                .requires("import")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stats")
+                .long("stats")
+                .value_name("FILE")
+                .help("Write counts and phase timings of the scan to FILE as JSON")
+                .conflicts_with("import")
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("similar")
+                .long("similar")
+                .value_name("THRESHOLD")
+                .help("List files sharing at least THRESHOLD of their content (example `0.8` or `80%`) instead of exact duplicates. Similar files are never linked")
+                .value_parser(parse_threshold)
+                .conflicts_with_all(["link", "import", "resume", "stats", "format"])
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("with-times")
+                .long("with-times")
+                .help("Record the modification time of every duplicate in the export")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("hash")
+                .long("hash")
+                .value_name("ALGORITHM")
+                .help("Hash algorithm to compare files with (default: xxh3, or blake3 with --strict)")
+                .value_parser(HashAlgorithm::NAMES)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("mmap-threshold")
+                .long("mmap-threshold")
+                .value_name("BYTES")
+                .help("Memory-map files larger than this many bytes when hashing")
+                .value_parser(clap::value_parser!(u64))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("min-size")
+                .long("min-size")
+                .value_name("SIZE")
+                .help("Skip files smaller than this size (example `4K`, `1M`)")
+                .value_parser(ddup::utils::parse_size)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("since")
+                .long("since")
+                .value_name("AGE")
+                .help("Only consider files modified within this long before now (example `7d`, `24h`; units s, m, h, d, w)")
+                .value_parser(ddup::utils::parse_age)
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("max-depth")
+                .long("max-depth")
+                .value_name("N")
+                .help("Only list files up to N directories below the scanned folder, 0 keeps just its own files (not for --wiztree or --file-list)")
+                .value_parser(clap::value_parser!(usize))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("exclude-hidden")
+                .long("exclude-hidden")
+                .help("Skip hidden and system files")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-default-excludes")
+                .long("no-default-excludes")
+                .help("Also scan the recycle bin, System Volume Information, WinSxS and the page and hibernation files")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include-empty")
+                .long("include-empty")
+                .help("Also group empty files, which are skipped by default since linking them frees nothing")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("include-ads")
+                .long("include-ads")
+                .help("Count the alternate data streams of redundant copies as reclaimable, and warn before linking drops them")
+                .conflicts_with("similar")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("create-journal")
+                .long("create-journal")
+                .help("Create the USN journal if the volume has none active, instead of failing")
+                .conflicts_with_all(["wiztree", "everything", "walkdir", "file-list"])
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("follow-reparse")
+                .long("follow-reparse")
+                .help("List files behind symlinks and junctions, which are skipped by default so nothing is counted twice")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no-progress")
+                .long("no-progress")
+                .help("Never draw progress bars (they are already hidden when stderr is not a terminal)")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("throttle")
+                .long("throttle")
+                .value_name("MB/S")
+                .help("Read at most this many megabytes per second in total while hashing")
+                .value_parser(clap::value_parser!(u64).range(1..))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("io-nice")
+                .long("io-nice")
+                .help("Run with background I/O and CPU priority, so other programs get the disk first")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max-open-files")
+                .long("max-open-files")
+                .value_name("N")
+                .help("Maximum number of files held open at once while hashing (default: 256)")
+                .value_parser(clap::value_parser!(usize))
+                .num_args(1),
+        )
+        .arg(
+            Arg::new("threads")
+                .short('j')
+                .long("threads")
+                .value_name("N")
+                .help("Number of worker threads, 0 uses all cores (hashing is I/O bound, so fewer threads may be faster on spinning disks)")
+                .value_parser(clap::value_parser!(usize))
+                .global(true)
+                .num_args(1),
+        )
+}
+
+fn parse_args() -> ArgMatches {
+    cli().get_matches()
+}
+
+/// How a run that didn't fail ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    /// The scan found duplicates
+    Duplicates,
+    /// The scan found none
+    NoDuplicates,
+    /// Ctrl-C stopped the scan, what it reported is partial
+    Interrupted,
+    /// A command that doesn't look for duplicates, such as `--undo`, succeeded
+    Done,
+}
+
+impl Outcome {
+    fn of(groups: usize, interrupted: bool) -> Self {
+        if interrupted {
+            Outcome::Interrupted
+        } else if groups > 0 {
+            Outcome::Duplicates
+        } else {
+            Outcome::NoDuplicates
+        }
+    }
+}
+
+/// Why a run failed, with the message to log for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Failure {
+    /// The arguments don't describe something that can be scanned
+    Usage(String),
+    /// A backend failed to list files, or reading or writing one failed
+    Io(String),
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Usage(message) | Failure::Io(message) => f.write_str(message),
+        }
+    }
 }
 
-fn main() {
+/// Exit code for a Ctrl-C, also used when a second one quits right away
+const EXIT_INTERRUPTED: u8 = 4;
+
+/// The documented exit code of a run: 0 when it found duplicates, 1 when it
+/// found none, 2 for usage errors (as clap uses for the ones it catches), 3
+/// when a backend or file operation failed and 4 when it was interrupted
+fn exit_code(result: &Result<Outcome, Failure>) -> u8 {
+    match result {
+        Ok(Outcome::Duplicates) | Ok(Outcome::Done) => 0,
+        Ok(Outcome::NoDuplicates) => 1,
+        Ok(Outcome::Interrupted) => EXIT_INTERRUPTED,
+        Err(Failure::Usage(_)) => 2,
+        Err(Failure::Io(_)) => 3,
+    }
+}
+
+fn main() -> ExitCode {
     let args = parse_args();
 
     if args.get_flag("verbose") {
         std::env::set_var("RUST_LOG", "debug");
+    } else if args.get_flag("quiet") {
+        std::env::set_var("RUST_LOG", "error");
     } else {
         std::env::set_var("RUST_LOG", "info");
     }
     env_logger::init();
 
-    let instant = Instant::now();
-
-    // Determine the comparison method
-    let comparison = if args.get_flag("strict") || args.get_flag("link") {
-        if args.get_flag("link") && !args.get_flag("strict") {
-            log::warn!("Hardlink option enabled: Forcing strict comparison to prevent data loss.");
+    let threads = args.get_one::<usize>("threads").copied().unwrap_or(0);
+    let mut pool = rayon::ThreadPoolBuilder::new().num_threads(threads);
+    if args.get_flag("io-nice") {
+        if let Err(e) = ddup::throttle::background_priority() {
+            log::warn!("Failed to lower the I/O priority: {}", e);
         }
+        pool = pool.start_handler(|_| {
+            if let Err(e) = ddup::throttle::background_priority() {
+                log::debug!("Failed to lower the I/O priority of a worker: {}", e);
+            }
+        });
+    }
+    let result = pool
+        .build()
+        .map_err(|e| Failure::Io(format!("Failed to build thread pool: {}", e)))
+        .and_then(|pool| {
+            pool.install(|| match args.subcommand() {
+                Some(("bench", bench_args)) => bench(bench_args),
+                _ => execute(&args),
+            })
+        });
+    if let Err(failure) = &result {
+        log::error!("{}", failure);
+    }
+    ExitCode::from(exit_code(&result))
+}
+
+/// List the files under a folder and report how fast each algorithm hashes
+/// them
+fn bench(args: &ArgMatches) -> Result<Outcome, Failure> {
+    let path = args.get_one::<String>("path").unwrap();
+    let listing = ddup::DirList::from_walkdir(
+        path,
+        &[],
+        &[],
+        glob::MatchOptions::new(),
+        EntryFilter::default(),
+    )
+    .map_err(|e| Failure::Io(format!("Failed to list {}: {}", path, e)))?;
+    let files: Vec<_> = listing.into_iter().collect();
+
+    let algorithms: Vec<HashAlgorithm> = match args.get_many::<String>("hash") {
+        Some(names) => names.map(|name| name.parse().unwrap()).collect(),
+        None => HashAlgorithm::NAMES
+            .iter()
+            .map(|name| name.parse().unwrap())
+            .collect(),
+    };
+    let comparison = if args.get_flag("strict") {
         Comparison::Strict
     } else {
         Comparison::Fuzzy
     };
+    let mmap_threshold = args
+        .get_one::<u64>("mmap-threshold")
+        .copied()
+        .unwrap_or(algorithm::DEFAULT_MMAP_THRESHOLD);
 
-    // Determine the backend preference
-    let (backend, source) = if let Some(wiztree_path) = args.get_one::<String>("wiztree") {
+    log::info!(
+        "Hashing {} files under {} [{:?} comparison]",
+        files.len(),
+        path,
+        comparison
+    );
+    for result in algorithm::bench(&files, &comparison, &algorithms, mmap_threshold) {
+        println!(
+            "{:<10} {:>8} files {:>14} bytes {:>9.3}s {:>10.1} MB/s",
+            format!("{:?}", result.algorithm).to_lowercase(),
+            result.files,
+            result.bytes,
+            result.elapsed.as_secs_f64(),
+            result.megabytes_per_second()
+        );
+    }
+    Ok(Outcome::Done)
+}
+
+/// Determine the backend preference and what it should scan
+fn select_backend(args: &ArgMatches) -> (ddup::Backend, &str) {
+    if let Some(wiztree_path) = args.get_one::<String>("wiztree") {
         (ddup::Backend::WizTree, wiztree_path.as_str())
+    } else if let Some(list_path) = args.get_one::<String>("file-list") {
+        (ddup::Backend::FileList, list_path.as_str())
     } else if args.get_flag("everything") {
+        let instance = args.get_one::<String>("everything-instance").cloned();
         (
-            ddup::Backend::Everything,
+            ddup::Backend::Everything(instance),
             args.get_one::<String>("drive").unwrap().as_str(),
         )
-    } else {
+    } else if args.get_flag("walkdir") {
+        (
+            ddup::Backend::WalkDir,
+            args.get_one::<String>("drive").unwrap().as_str(),
+        )
+    } else if args.get_flag("mft") {
+        (
+            ddup::Backend::Mft,
+            args.get_one::<String>("drive").unwrap().as_str(),
+        )
+    } else if args.get_flag("usn") || args.get_flag("create-journal") {
         (
             ddup::Backend::USN,
             args.get_one::<String>("drive").unwrap().as_str(),
         )
-    };
+    } else {
+        let backend = match args.get_many::<String>("probe-order") {
+            Some(names) => ddup::Backend::Auto(
+                names
+                    .map(|name| match name.as_str() {
+                        "everything" => ddup::Backend::Everything(None),
+                        "usn" => ddup::Backend::USN,
+                        "mft" => ddup::Backend::Mft,
+                        _ => ddup::Backend::WalkDir,
+                    })
+                    .collect(),
+            ),
+            None => ddup::Backend::auto(),
+        };
+        (backend, args.get_one::<String>("drive").unwrap().as_str())
+    }
+}
+
+/// Whether `s` is a bare drive letter with its colon, such as `C:`
+fn is_drive(s: &str) -> bool {
+    matches!(s.as_bytes(), [letter, b':'] if letter.is_ascii_alphabetic())
+}
+
+/// Check the scan target before any backend gets to fail on it with a less
+/// helpful error. The volume backends need a drive or a folder on one, the
+/// walker needs an existing folder, the probe takes either. WizTree exports and file lists are
+/// checked when they are read.
+fn validate_source(backend: &ddup::Backend, source: &str) -> Result<(), String> {
+    match backend {
+        ddup::Backend::WizTree | ddup::Backend::FileList => Ok(()),
+        ddup::Backend::WalkDir if std::path::Path::new(source).is_dir() => Ok(()),
+        ddup::Backend::WalkDir => Err(format!("`{}` is not an existing folder", source)),
+        ddup::Backend::Auto(_) if std::path::Path::new(source).is_dir() => Ok(()),
+        _ => {
+            let (volume, _) = ddup::utils::split_scan_root(source);
+            let drives = ddup::utils::logical_drives();
+            if !is_drive(&volume) {
+                let hint = match volume.as_bytes() {
+                    [letter] if letter.is_ascii_alphabetic() => {
+                        format!(", did you mean `{}:`?", volume)
+                    }
+                    _ => String::new(),
+                };
+                Err(format!(
+                    "`{}` is not a drive such as `C:`{} (use --walkdir or --file-list for other paths)",
+                    source, hint
+                ))
+            } else if !drives
+                .iter()
+                .any(|drive| drive.eq_ignore_ascii_case(&volume))
+            {
+                Err(format!(
+                    "Drive {} doesn't exist, available drives: {}",
+                    volume.to_ascii_uppercase(),
+                    drives.join(" ")
+                ))
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Copies a group needs to be reported, `--min-copies` or a plain pair
+fn min_copies(args: &ArgMatches) -> usize {
+    args.get_one::<usize>("min-copies").copied().unwrap_or(2)
+}
+
+/// Pick the comparison from `--strict`, `--link` and `--force-fuzzy-link`.
+/// Reporting and linking are otherwise independent of it:
+///
+/// | `--strict` | `--link` | `--force-fuzzy-link` | comparison            |
+/// |------------|----------|----------------------|-----------------------|
+/// | no         | no       | -                    | fuzzy                 |
+/// | yes        | no       | -                    | strict                |
+/// | yes        | yes      | -                    | strict                |
+/// | no         | yes      | no                   | strict, with a notice |
+/// | no         | yes      | yes                  | fuzzy, with a warning |
+///
+/// The hash algorithm is chosen separately with `--hash`.
+fn resolve_comparison(strict: bool, link: bool, force_fuzzy_link: bool) -> Comparison {
+    if strict {
+        Comparison::Strict
+    } else if link && force_fuzzy_link {
+        log::warn!(
+            "--force-fuzzy-link: linking files compared on sampled blocks only, files that differ elsewhere WILL LOSE DATA"
+        );
+        Comparison::Fuzzy
+    } else if link {
+        log::warn!("Hardlink option enabled: Forcing strict comparison to prevent data loss.");
+        Comparison::Strict
+    } else {
+        Comparison::Fuzzy
+    }
+}
 
-    let result = if let Some(pattern) = args.get_one::<String>("match") {
-        let is_sensitive = !args.get_flag("i");
+/// Configure a scan from the command line
+fn builder(args: &ArgMatches) -> Result<DedupBuilder, Failure> {
+    let comparison = resolve_comparison(
+        args.get_flag("strict"),
+        args.get_flag("link"),
+        args.get_flag("force-fuzzy-link"),
+    );
+
+    let hash_algorithm = args
+        .get_one::<String>("hash")
+        .map(|name| name.parse().unwrap())
+        .unwrap_or_else(|| HashAlgorithm::default_for(&comparison));
+
+    let (backend, source) = select_backend(args);
+    validate_source(&backend, source).map_err(Failure::Usage)?;
+
+    let mut builder = DedupBuilder::new(source)
+        .backend(backend.clone())
+        .comparison(comparison)
+        .prefilter(!args.get_flag("no-prefilter"))
+        .hash_algorithm(hash_algorithm)
+        .min_size(args.get_one::<u64>("min-size").copied().unwrap_or(0))
+        .include_empty(args.get_flag("include-empty"))
+        .follow_reparse(args.get_flag("follow-reparse"))
+        .exclude_hidden(args.get_flag("exclude-hidden"))
+        .default_excludes(!args.get_flag("no-default-excludes"))
+        .create_journal(args.get_flag("create-journal"))
+        .progress(
+            ProgressReporter::new(!args.get_flag("no-progress") && !args.get_flag("quiet"))
+                .with_cancel(cancel_on_ctrl_c()),
+        )
+        .with_times(args.get_flag("with-times"))
+        .min_copies(min_copies(args));
+    if let Some(threshold) = args.get_one::<u64>("mmap-threshold") {
+        builder = builder.mmap_threshold(*threshold);
+    }
+    if let Some(depth) = args.get_one::<usize>("max-depth") {
+        builder = builder.max_depth(*depth);
+    }
+    if let Some(age) = args.get_one::<std::time::Duration>("since") {
+        let cutoff = std::time::SystemTime::now()
+            .checked_sub(*age)
+            .unwrap_or(std::time::UNIX_EPOCH);
+        builder = builder.modified_since(cutoff);
+    }
+    if let Some(limit) = args.get_one::<usize>("max-open-files") {
+        builder = builder.max_open_files(*limit);
+    }
+    if let Some(megabytes) = args.get_one::<u64>("throttle") {
+        builder = builder.throttle(megabytes.saturating_mul(1_000_000));
+    }
+    if let Some(cache) = args.get_one::<PathBuf>("cache") {
+        builder = builder.cache(cache);
+    }
+    if let Some(resume) = args.get_one::<PathBuf>("resume") {
+        builder = builder.resume(resume);
+    }
+    for exclude in args
+        .get_many::<glob::Pattern>("exclude")
+        .unwrap_or_default()
+    {
+        builder = builder.exclude(exclude.clone());
+    }
+
+    let matchers: Vec<glob::Pattern> = args
+        .get_many::<glob::Pattern>("match")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    // Excludes follow the same glob rules as matchers
+    let is_sensitive = !args.get_flag("i");
+    builder = builder
+        .case_sensitive(is_sensitive)
+        .literal_separator(args.get_flag("literal-separator"))
+        .literal_leading_dot(args.get_flag("literal-leading-dot"))
+        .match_full_path(args.get_flag("match-full-path"));
+
+    if !matchers.is_empty() {
+        let patterns: Vec<&str> = matchers.iter().map(|m| m.as_str()).collect();
         log::info!(
-            "Scanning {} with matcher `{}` ({}) [{:?} comparison, preference: {:?}]",
+            "Scanning {} with matcher `{}` ({}) [{:?} comparison, {:?} hash, preference: {:?}]",
             source,
-            pattern,
+            patterns.join("`, `"),
             if is_sensitive {
                 "case-sensitive"
             } else {
                 "case-insensitive"
             },
             comparison,
+            hash_algorithm,
             backend
         );
 
-        let options = MatchOptions {
-            case_sensitive: is_sensitive,
-            require_literal_leading_dot: false,
-            require_literal_separator: false,
-        };
-
-        algorithm::run(source, Some(pattern), options, comparison, backend)
+        for matcher in matchers {
+            builder = builder.matcher(matcher);
+        }
     } else {
         log::info!(
-            "Scanning {} [{:?} comparison, preference: {:?}]",
+            "Scanning {} [{:?} comparison, {:?} hash, preference: {:?}]",
             source,
             comparison,
+            hash_algorithm,
             backend
         );
-        let options = MatchOptions {
-            case_sensitive: false,
-            require_literal_leading_dot: false,
-            require_literal_separator: false,
+    }
+
+    // Threads are set up by the pool `main` runs everything in
+    Ok(builder)
+}
+
+/// A flag set by the first Ctrl-C, so the scan can stop and still report
+/// what it found. A second Ctrl-C quits right away.
+fn cancel_on_ctrl_c() -> CancelFlag {
+    let cancel = CancelFlag::new();
+    let handler = cancel.clone();
+    let installed = ctrlc::set_handler(move || {
+        if handler.is_cancelled() {
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+        log::warn!("Stopping after the files in progress, press Ctrl-C again to quit now");
+        handler.cancel();
+    });
+    if let Err(e) = installed {
+        log::debug!("Failed to install Ctrl-C handler: {}", e);
+    }
+    cancel
+}
+
+/// Run the scan and hashing pipeline selected by the arguments
+fn scan(args: &ArgMatches) -> Result<DedupReport, Failure> {
+    builder(args)?
+        .run()
+        .map_err(|e| Failure::Io(format!("Failed to run duplicate detection: {}", e)))
+}
+
+/// Accept a share either as a fraction (`0.8`) or a percentage (`80%`)
+fn parse_threshold(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let value = match s.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f64>().map(|p| p / 100.0),
+        None => s.parse::<f64>(),
+    }
+    .map_err(|_| format!("Invalid threshold `{}`", s))?;
+    if value > 0.0 && value <= 1.0 {
+        Ok(value)
+    } else {
+        Err(format!(
+            "Threshold `{}` must be above 0 and at most 1 (100%)",
+            s
+        ))
+    }
+}
+
+/// Report groups of similar files, which are only ever listed, never linked
+fn find_similar(args: &ArgMatches, threshold: f64) -> Result<Outcome, Failure> {
+    let groups = builder(args)?
+        .run_similar(threshold)
+        .map_err(|e| Failure::Io(format!("Failed to find similar files: {}", e)))?;
+
+    if let Some(export_path) = args.get_one::<String>("export") {
+        fs::write(export_path, groups.serialize_json()).map_err(|e| {
+            Failure::Io(format!(
+                "Failed to write export file {}: {}",
+                export_path, e
+            ))
+        })?;
+        log::info!("Exported {} groups to {}", groups.len(), export_path);
+    }
+
+    for group in &groups {
+        println!("Similar files [{:.1}% shared]", group.similarity * 100.0);
+        for path in &group.paths {
+            println!("\t{}", path);
+        }
+    }
+    log::info!(
+        "Found {} groups of files sharing at least {:.0}% of their content",
+        groups.len(),
+        threshold * 100.0
+    );
+    Ok(Outcome::of(groups.len(), false))
+}
+
+/// Print the extensions holding the most reclaimable space, to stderr so
+/// it stays out of `--quiet` output
+fn print_breakdown(duplicates: &[DuplicateGroup], top: usize) {
+    let total = algorithm::summarize(duplicates).reclaimable_bytes.max(1);
+    eprintln!("Reclaimable space by extension:");
+    for share in algorithm::breakdown_by_extension(duplicates, top) {
+        let extension = if share.extension.is_empty() {
+            "(none)".to_string()
+        } else {
+            format!(".{}", share.extension)
         };
-        algorithm::run(source, None, options, comparison, backend)
-    };
+        eprintln!(
+            "\t{:<12} {:>5.1}%  {} bytes in {} files",
+            extension,
+            share.reclaimable_bytes as f64 * 100.0 / total as f64,
+            share.reclaimable_bytes,
+            share.redundant_files
+        );
+    }
+}
+
+/// Print which folders below the common root hold the duplicates, to stderr
+/// like `print_breakdown`
+fn print_directory_breakdown(duplicates: &[DuplicateGroup]) {
+    let (root, shares) = algorithm::breakdown_by_directory(duplicates);
+    eprintln!("Duplicated data by folder under {}:", root.display());
+    for share in shares {
+        let directory = if share.directory.is_empty() {
+            "(root)"
+        } else {
+            share.directory.as_str()
+        };
+        eprintln!(
+            "\t{:<24} {} bytes in {} files, {} bytes also in other folders",
+            directory, share.bytes, share.files, share.elsewhere_bytes
+        );
+    }
+}
+
+/// Check imported groups against the files on disk, see `algorithm::reverify`
+fn reverify(args: &ArgMatches, groups: Vec<DuplicateGroup>) -> Vec<DuplicateGroup> {
+    let algorithm = args
+        .get_one::<String>("hash")
+        .map(|name| name.parse().unwrap())
+        .unwrap_or_else(|| HashAlgorithm::default_for(&Comparison::Strict));
+    let gate = ddup::gate::OpenFileGate::new(
+        args.get_one::<usize>("max-open-files")
+            .copied()
+            .unwrap_or(ddup::gate::DEFAULT_MAX_OPEN_FILES),
+    );
+    let progress = ProgressReporter::new(!args.get_flag("no-progress") && !args.get_flag("quiet"));
+    let errors = ddup::error::FileErrors::default();
+
+    let files =
+        |groups: &[DuplicateGroup]| -> usize { groups.iter().map(|group| group.paths.len()).sum() };
+    let before = files(&groups);
+    let verified = algorithm::reverify(groups, algorithm, &errors, &progress, &gate);
+    errors.log_summary("reverifying");
+    log::info!(
+        "Reverified {} groups, {} of {} files no longer match a copy",
+        verified.len(),
+        before - files(&verified),
+        before
+    );
+    verified
+}
+
+/// Cut the groups down to the `--limit` largest, returning the ones left out
+fn apply_limit(args: &ArgMatches, duplicates: &mut Vec<DuplicateGroup>) -> Vec<DuplicateGroup> {
+    match args.get_one::<usize>("limit") {
+        Some(&limit) => algorithm::split_off_largest(duplicates, limit),
+        None => Vec::new(),
+    }
+}
+
+/// Whether an answer to the confirmation prompt means go ahead
+fn is_confirmation(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+/// Show what linking will do and wait for the user to agree on stdin
+fn confirm_linking(duplicates: &[DuplicateGroup], kind: link::LinkKind) -> bool {
+    let summary = algorithm::summarize(duplicates);
+    eprint!(
+        "About to replace {} files in {} groups with {:?} links, freeing about {} bytes. Type `yes` to continue: ",
+        summary.redundant_files, summary.groups, kind, summary.reclaimable_bytes
+    );
+    let _ = std::io::stderr().flush();
 
-    let duplicates = match result {
-        Ok(d) => d,
+    let mut answer = String::new();
+    match std::io::stdin().read_line(&mut answer) {
+        Ok(_) => is_confirmation(&answer),
         Err(e) => {
-            log::error!("Failed to run duplicate detection: {}", e);
-            std::process::exit(1);
+            log::error!("Failed to read confirmation: {}", e);
+            false
+        }
+    }
+}
+
+fn execute(args: &ArgMatches) -> Result<Outcome, Failure> {
+    let instant = Instant::now();
+
+    if args.get_flag("recover") {
+        let drive = args.get_one::<String>("drive").unwrap();
+        // `C:` alone is the current directory on C:, scan from its root
+        let root = if drive.ends_with(':') {
+            format!("{}\\", drive)
+        } else {
+            drive.clone()
+        };
+        let restored = link::recover(std::path::Path::new(&root));
+        log::info!("Restored {} files under {}", restored, root);
+        return Ok(Outcome::Done);
+    }
+
+    if let Some(undo_path) = args.get_one::<String>("undo") {
+        let restored = link::undo(std::path::Path::new(undo_path))
+            .map_err(|e| Failure::Io(format!("Failed to undo links from {}: {}", undo_path, e)))?;
+        log::info!("Restored {} files from {}", restored, undo_path);
+        return Ok(Outcome::Done);
+    }
+
+    if let Some(threshold) = args.get_one::<f64>("similar") {
+        return find_similar(args, *threshold);
+    }
+
+    let imported = args.get_one::<String>("import");
+    let mut interrupted = false;
+    let mut duplicates = match imported {
+        Some(import_path) => match DuplicateGroup::load_json(std::path::Path::new(import_path)) {
+            Ok(mut groups) => {
+                log::info!("Imported {} groups from {}", groups.len(), import_path);
+                // The export may be stale, edited by hand or fuzzy, so nothing
+                // in it is linked before its files are compared again
+                if args.get_flag("reverify") || args.get_flag("link") {
+                    groups = reverify(args, groups);
+                }
+                algorithm::retain_min_copies(&mut groups, min_copies(args));
+                if args.get_flag("with-times") {
+                    algorithm::fill_mtimes(&mut groups);
+                }
+                groups
+            }
+            Err(e) => {
+                return Err(Failure::Io(format!(
+                    "Failed to import {}: {}",
+                    import_path, e
+                )))
+            }
+        },
+        None => {
+            let report = scan(args)?;
+            interrupted = report.stats.interrupted;
+            log::info!(
+                "Scanned {} files in {:.1} seconds ({} errors)",
+                report.scanned,
+                report.elapsed.as_secs_f64(),
+                report.errors
+            );
+            if let Some(stats_path) = args.get_one::<String>("stats") {
+                fs::write(stats_path, report.stats.serialize_json()).map_err(|e| {
+                    Failure::Io(format!(
+                        "Failed to write statistics to {}: {}",
+                        stats_path, e
+                    ))
+                })?;
+                log::info!("Wrote run statistics to {}", stats_path);
+            }
+            report.groups
         }
     };
 
+    // Measured before linking turns the copies into links to their leader,
+    // and before `--limit` so the summary covers every group
+    let allocated = args
+        .get_flag("allocated-size")
+        .then(|| algorithm::allocated_usage(&duplicates));
+
+    // The largest groups are picked before they are put in the requested order
+    let mut omitted = apply_limit(args, &mut duplicates);
+    let sort_order: SortOrder = args
+        .get_one::<String>("sort")
+        .map(|name| name.parse().unwrap())
+        .unwrap_or_default();
+    if sort_order != SortOrder::default() {
+        algorithm::sort_groups(&mut duplicates, sort_order);
+    }
+
     let export_path = args.get_one::<String>("export");
+    let format = args.get_one::<String>("format").map(String::as_str);
+    let exclude_leader = args.get_flag("exclude-leader");
+    if exclude_leader && format != Some("paths") {
+        log::warn!("--exclude-leader only applies to --format paths");
+    }
     if let Some(export_path) = export_path {
-        let json = duplicates.serialize_json();
-        fs::write(export_path, json).expect("Failed to write export file");
+        fs::File::create(export_path)
+            .and_then(|file| {
+                let file = std::io::BufWriter::new(file);
+                match format {
+                    Some("csv") => export::write_csv(&duplicates, file),
+                    Some("jsonl") => export::write_jsonl(&duplicates, file),
+                    Some("paths") => export::write_path_list(&duplicates, exclude_leader, file),
+                    _ => export::write_json(&duplicates, file),
+                }
+            })
+            .map_err(|e| {
+                Failure::Io(format!(
+                    "Failed to write export file {}: {}",
+                    export_path, e
+                ))
+            })?;
         log::info!("Exported {} groups to {}", duplicates.len(), export_path);
     }
 
-    if args.get_flag("link") {
-        let freed_space: u64 = duplicates
-            .par_iter()
-            .map(|group| {
-                let mut group_freed = 0;
-                if let Some(first) = group.paths.first() {
-                    for path in &group.paths[1..] {
-                        log::info!("Linking {} -> {}", path, first);
-                        let tmp_path = format!("{}.ddup_tmp", path);
-
-                        if let Err(e) = fs::rename(path, &tmp_path) {
-                            log::error!("Failed to prepare link for {} (move failed): {}", path, e);
-                            continue;
-                        }
-
-                        if let Err(e) = fs::hard_link(first, path) {
-                            log::error!(
-                                "Failed to link {} to {}: {}. Restoring original...",
-                                path,
-                                first,
-                                e
-                            );
-                            if let Err(restore_e) = fs::rename(&tmp_path, path) {
-                                log::error!(
-                                    "CRITICAL: Failed to restore {} from backup: {}",
-                                    path,
-                                    restore_e
-                                );
-                            }
-                        } else {
-                            if let Err(e) = fs::remove_file(&tmp_path) {
-                                log::warn!("Failed to remove backup file {}: {}", tmp_path, e);
-                            } else {
-                                group_freed += group.size;
-                            }
-                        }
-                    }
-                }
-                group_freed
-            })
-            .sum();
+    if let Some(report_path) = args.get_one::<String>("report") {
+        fs::File::create(report_path)
+            .and_then(|file| export::write_html(&duplicates, std::io::BufWriter::new(file)))
+            .map_err(|e| Failure::Io(format!("Failed to write report {}: {}", report_path, e)))?;
+        log::info!(
+            "Wrote report of {} groups to {}",
+            duplicates.len(),
+            report_path
+        );
+    }
+
+    // Measured before linking, which replaces the redundant copies
+    let mut streams = None;
+    if args.get_flag("link") && interrupted {
+        log::warn!("The scan was interrupted, not linking its partial results");
+    } else if args.get_flag("link") {
+        if args.get_flag("same-dir-only") {
+            let groups = duplicates.len();
+            duplicates = link::split_by_directory(duplicates);
+            log::info!(
+                "Linking within folders only, {} groups split into {}",
+                groups,
+                duplicates.len()
+            );
+        }
+        let keep: link::KeepPolicy = if args.get_flag("stable-leader") {
+            link::KeepPolicy::LowestId
+        } else {
+            args.get_one::<String>("keep")
+                .map(|name| name.parse().unwrap())
+                .unwrap_or_default()
+        };
+        link::apply_keep_policy(&mut duplicates, keep);
+        if args.get_flag("include-ads") {
+            let usage = algorithm::named_stream_usage(&duplicates);
+            if usage.files > 0 {
+                log::warn!(
+                    "{} files to be replaced carry {} bytes in alternate data streams, which were not compared and won't survive linking",
+                    usage.files,
+                    usage.bytes
+                );
+            }
+            streams = Some(usage);
+        }
+
+        let dry_run = args.get_flag("dry-run");
+        let kind = if args.get_flag("symlink") {
+            link::LinkKind::Symbolic
+        } else if args.get_flag("reflink") {
+            link::LinkKind::Reflink
+        } else {
+            link::LinkKind::Hard
+        };
+        let needs_confirmation = !dry_run && !duplicates.is_empty() && !args.get_flag("yes");
+        if needs_confirmation && !confirm_linking(&duplicates, kind) {
+            log::info!("Linking cancelled, no files were changed");
+            return Ok(Outcome::of(duplicates.len(), interrupted));
+        }
+
+        let undo_log = match args.get_one::<String>("undo-log") {
+            Some(path) => Some(
+                link::UndoLog::open(std::path::Path::new(path))
+                    .map_err(|e| Failure::Io(format!("Failed to open undo log {}: {}", path, e)))?,
+            ),
+            None => None,
+        };
+        let freed_space = match args.get_one::<String>("organize") {
+            Some(output_dir) => link::organize(
+                &duplicates,
+                std::path::Path::new(output_dir),
+                kind,
+                dry_run,
+                args.get_flag("link-readonly"),
+                undo_log.as_ref(),
+            ),
+            None => link::link_duplicates(
+                &duplicates,
+                kind,
+                dry_run,
+                args.get_flag("link-readonly"),
+                undo_log.as_ref(),
+            ),
+        };
 
         log::info!(
-            "Deduplication complete. Estimated space freed: {} bytes",
+            "Deduplication {}. Estimated space freed: {} bytes",
+            if dry_run {
+                "dry run complete"
+            } else {
+                "complete"
+            },
             freed_space
         );
     }
 
-    if export_path.is_none() || args.get_flag("verbose") {
+    if format == Some("paths") && export_path.is_none() {
+        let printed =
+            export::write_path_list(&duplicates, exclude_leader, std::io::stdout().lock());
+        if let Err(e) = printed {
+            log::error!("Failed to print duplicates: {}", e);
+        }
+    } else if format == Some("jsonl") && export_path.is_none() {
+        if let Err(e) = export::write_jsonl(&duplicates, std::io::stdout().lock()) {
+            log::error!("Failed to print duplicates: {}", e);
+        }
+    } else if args.get_flag("quiet") {
+        if let Err(e) = export::write_paths(&duplicates, std::io::stdout().lock()) {
+            log::error!("Failed to print duplicates: {}", e);
+        }
+    } else if export_path.is_none() || args.get_flag("verbose") {
         for group in &duplicates {
-            println!("Potential duplicates [{} bytes]", group.size);
+            let heading = match group.confidence {
+                Confidence::Exact => "Duplicates",
+                Confidence::Fuzzy => "Potential duplicates",
+            };
+            println!("{} [{} bytes, {}]", heading, group.size, group.confidence);
             for path in &group.paths {
                 println!("\t{}", path);
             }
         }
     }
 
+    // The summary counts what was found, not only what was shown. Groups
+    // left out weren't linked either, so their streams are still there.
+    let shown = duplicates.len();
+    if let Some(streams) = streams.as_mut().filter(|_| !omitted.is_empty()) {
+        let rest = algorithm::named_stream_usage(&omitted);
+        streams.files += rest.files;
+        streams.bytes += rest.bytes;
+    }
+    duplicates.append(&mut omitted);
+
+    let summary = algorithm::summarize(&duplicates);
+    log::info!(
+        "Found {} duplicate groups, {} redundant files, {} bytes reclaimable",
+        summary.groups,
+        summary.redundant_files,
+        summary.reclaimable_bytes
+    );
+    // Files are grouped by length, identical content has the same length
+    // however much of it is allocated, but only what's allocated is freed
+    if let Some(allocated) = allocated {
+        log::info!(
+            "The redundant files are {} bytes in size and occupy {} bytes on disk, {} of them are compressed and {} sparse",
+            summary.reclaimable_bytes,
+            allocated.bytes,
+            allocated.compressed_files,
+            allocated.sparse_files
+        );
+    }
+    if shown < duplicates.len() {
+        log::info!(
+            "Showed the {} groups with the most reclaimable space, raise --limit to see more",
+            shown
+        );
+    }
+    if args.get_flag("include-ads") {
+        let streams = streams.unwrap_or_else(|| algorithm::named_stream_usage(&duplicates));
+        log::info!(
+            "Another {} bytes in alternate data streams of {} redundant files, {} bytes reclaimable in total",
+            streams.bytes,
+            streams.files,
+            allocated.map_or(summary.reclaimable_bytes, |allocated| allocated.bytes)
+                + streams.bytes
+        );
+    }
+
+    if let Some(&top) = args.get_one::<usize>("breakdown") {
+        print_breakdown(&duplicates, top);
+    }
+    if args.get_flag("group-by-dir") {
+        print_directory_breakdown(&duplicates);
+    }
+
     log::info!(
         "Overall finished in {} seconds",
         instant.elapsed().as_secs_f32()
     );
+    Ok(Outcome::of(duplicates.len(), interrupted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarity_threshold_accepts_fraction_or_percent() {
+        assert_eq!(parse_threshold("0.8"), Ok(0.8));
+        assert_eq!(parse_threshold("80%"), Ok(0.8));
+        assert_eq!(parse_threshold("100%"), Ok(1.0));
+        assert!(parse_threshold("0").is_err());
+        assert!(parse_threshold("1.5").is_err());
+        assert!(parse_threshold("lots").is_err());
+        assert!(cli()
+            .try_get_matches_from(["ddup", "C:", "--similar", "0.9", "--link"])
+            .is_err());
+    }
+
+    #[test]
+    fn confirmation_accepts_only_yes() {
+        for answer in ["y", "yes", "YES", "Yes\r\n", " y\n"] {
+            assert!(is_confirmation(answer), "{:?}", answer);
+        }
+        for answer in ["", "\n", "n", "no", "yess", "ye", "sure", "y es"] {
+            assert!(!is_confirmation(answer), "{:?}", answer);
+        }
+    }
+
+    #[test]
+    fn stable_leader_replaces_keep_policy() {
+        let args = cli()
+            .try_get_matches_from(["ddup", "C:", "--link", "--stable-leader"])
+            .unwrap();
+        assert!(args.get_flag("stable-leader"));
+        assert!(cli()
+            .try_get_matches_from(["ddup", "C:", "--stable-leader"])
+            .is_err());
+        assert!(cli()
+            .try_get_matches_from([
+                "ddup",
+                "C:",
+                "--link",
+                "--stable-leader",
+                "--keep",
+                "oldest"
+            ])
+            .is_err());
+    }
+
+    #[test]
+    fn drive_must_be_a_letter_and_colon() {
+        assert!(is_drive("C:"));
+        assert!(is_drive("z:"));
+        for rejected in ["C", "CC:", "", ":", "1:", r"C:\"] {
+            assert!(!is_drive(rejected), "{:?}", rejected);
+        }
+
+        let error = validate_source(&ddup::Backend::USN, "C").unwrap_err();
+        assert!(error.contains("did you mean `C:`"), "{}", error);
+        assert!(validate_source(&ddup::Backend::USN, "CC:").is_err());
+        assert!(validate_source(&ddup::Backend::USN, "").is_err());
+        assert!(validate_source(&ddup::Backend::FileList, "-").is_ok());
+    }
+
+    #[test]
+    fn since_takes_an_age_with_unit() {
+        let args = cli()
+            .try_get_matches_from(["ddup", "C:", "--since", "7d"])
+            .unwrap();
+        assert_eq!(
+            args.get_one::<std::time::Duration>("since"),
+            Some(&std::time::Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert!(cli()
+            .try_get_matches_from(["ddup", "C:", "--since", "7"])
+            .is_err());
+    }
+
+    #[test]
+    fn hash_flag_accepts_known_algorithms() {
+        for name in HashAlgorithm::NAMES {
+            let args = cli()
+                .try_get_matches_from(["ddup", "C:", "--hash", name])
+                .unwrap();
+            assert_eq!(args.get_one::<String>("hash").unwrap(), name);
+        }
+    }
+
+    #[test]
+    fn min_size_flag_accepts_suffixes() {
+        let args = cli()
+            .try_get_matches_from(["ddup", "C:", "--min-size", "1K"])
+            .unwrap();
+        assert_eq!(args.get_one::<u64>("min-size"), Some(&1024));
+
+        let err = cli()
+            .try_get_matches_from(["ddup", "C:", "--min-size", "1Q"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    }
+
+    #[test]
+    fn link_implies_strict_unless_forced() {
+        let cases = [
+            (false, false, false, Comparison::Fuzzy),
+            (true, false, false, Comparison::Strict),
+            (true, true, false, Comparison::Strict),
+            (false, true, false, Comparison::Strict),
+            (false, true, true, Comparison::Fuzzy),
+        ];
+        for (strict, link, force_fuzzy_link, expected) in cases {
+            assert_eq!(
+                resolve_comparison(strict, link, force_fuzzy_link),
+                expected,
+                "strict: {}, link: {}, force-fuzzy-link: {}",
+                strict,
+                link,
+                force_fuzzy_link
+            );
+        }
+    }
+
+    #[test]
+    fn force_fuzzy_link_needs_link_and_no_strict() {
+        let args = cli()
+            .try_get_matches_from(["ddup", "C:", "--link", "--force-fuzzy-link"])
+            .unwrap();
+        assert!(args.get_flag("force-fuzzy-link"));
+
+        let err = cli()
+            .try_get_matches_from(["ddup", "C:", "--force-fuzzy-link"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+
+        let err = cli()
+            .try_get_matches_from(["ddup", "C:", "--link", "--strict", "--force-fuzzy-link"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn breakdown_flag_value_is_optional() {
+        let args = cli()
+            .try_get_matches_from(["ddup", "--breakdown", "C:"])
+            .unwrap();
+        assert_eq!(args.get_one::<usize>("breakdown"), Some(&10));
+        assert_eq!(args.get_one::<String>("drive").unwrap(), "C:");
+
+        let args = cli()
+            .try_get_matches_from(["ddup", "C:", "--breakdown=3"])
+            .unwrap();
+        assert_eq!(args.get_one::<usize>("breakdown"), Some(&3));
+
+        let args = cli().try_get_matches_from(["ddup", "C:"]).unwrap();
+        assert_eq!(args.get_one::<usize>("breakdown"), None);
+    }
+
+    #[test]
+    fn hash_flag_rejects_unknown_algorithm() {
+        let err = cli()
+            .try_get_matches_from(["ddup", "C:", "--hash", "md5"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidValue);
+    }
+
+    #[test]
+    fn everything_instance_selects_named_backend() {
+        let args = cli()
+            .try_get_matches_from(["ddup", "C:", "-E", "--everything-instance", "portable"])
+            .unwrap();
+        let (backend, source) = select_backend(&args);
+        assert_eq!(
+            backend,
+            ddup::Backend::Everything(Some("portable".to_string()))
+        );
+        assert_eq!(source, "C:");
+
+        let args = cli().try_get_matches_from(["ddup", "C:", "-E"]).unwrap();
+        assert_eq!(select_backend(&args).0, ddup::Backend::Everything(None));
+
+        let err = cli()
+            .try_get_matches_from(["ddup", "C:", "--everything-instance", "portable"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+    }
+
+    #[test]
+    fn bench_subcommand_takes_its_own_arguments() {
+        let args = cli()
+            .try_get_matches_from([
+                "ddup",
+                "bench",
+                r"D:\Photos",
+                "--hash",
+                "xxh3",
+                "--hash",
+                "crc32",
+                "-j",
+                "1",
+            ])
+            .unwrap();
+        let (name, bench_args) = args.subcommand().unwrap();
+        assert_eq!(name, "bench");
+        assert_eq!(bench_args.get_one::<String>("path").unwrap(), r"D:\Photos");
+        let hashes: Vec<&String> = bench_args.get_many::<String>("hash").unwrap().collect();
+        assert_eq!(hashes, ["xxh3", "crc32"]);
+        assert_eq!(args.get_one::<usize>("threads"), Some(&1));
+
+        // A scan still needs its drive
+        let err = cli()
+            .try_get_matches_from(["ddup", "--strict"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::MissingRequiredArgument);
+        assert!(cli()
+            .try_get_matches_from(["ddup", "C:", "bench", "D:"])
+            .is_err());
+    }
+
+    #[test]
+    fn mft_flag_selects_mft_backend() {
+        let args = cli().try_get_matches_from(["ddup", "D:", "--mft"]).unwrap();
+        assert_eq!(select_backend(&args), (ddup::Backend::Mft, "D:"));
+
+        let err = cli()
+            .try_get_matches_from(["ddup", "D:", "--mft", "-W"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn exit_codes_tell_outcomes_and_failures_apart() {
+        let code = |result: Result<Outcome, Failure>| exit_code(&result);
+        assert_eq!(code(Ok(Outcome::of(3, false))), 0);
+        assert_eq!(code(Ok(Outcome::of(0, false))), 1);
+        assert_eq!(code(Err(Failure::Usage("no such drive".into()))), 2);
+        assert_eq!(code(Err(Failure::Io("access denied".into()))), 3);
+        // Partial results of an interrupted scan don't count as found
+        assert_eq!(code(Ok(Outcome::of(3, true))), 4);
+        assert_eq!(code(Ok(Outcome::of(0, true))), 4);
+        assert_eq!(code(Ok(Outcome::Done)), 0);
+
+        // Not a drive, and `*` can't be in the name of a folder either
+        let args = cli().try_get_matches_from(["ddup", "C*"]).unwrap();
+        let failure = builder(&args).unwrap_err();
+        assert!(matches!(failure, Failure::Usage(_)), "{:?}", failure);
+        assert_eq!(
+            cli()
+                .try_get_matches_from(["ddup", "C:", "--mft", "-W"])
+                .unwrap_err()
+                .exit_code(),
+            2
+        );
+    }
+
+    #[test]
+    fn limit_reports_the_largest_groups_in_the_requested_order() {
+        let group = |size, name: &str| DuplicateGroup {
+            size,
+            paths: vec![format!("{}1", name), format!("{}2", name)],
+            mtimes: None,
+            confidence: Confidence::Exact,
+        };
+        let mut groups = vec![
+            group(10, "a"),
+            group(40, "b"),
+            group(20, "c"),
+            group(50, "d"),
+            group(30, "e"),
+        ];
+
+        let args = cli()
+            .try_get_matches_from(["ddup", "C:", "--limit", "3", "--sort", "path"])
+            .unwrap();
+        let omitted = apply_limit(&args, &mut groups);
+        assert_eq!(omitted.len(), 2);
+        algorithm::sort_groups(&mut groups, SortOrder::Path);
+        let sizes: Vec<u64> = groups.iter().map(|group| group.size).collect();
+        assert_eq!(sizes, [40, 50, 30]);
+
+        let args = cli().try_get_matches_from(["ddup", "C:"]).unwrap();
+        assert!(apply_limit(&args, &mut groups).is_empty());
+        assert_eq!(groups.len(), 3);
+    }
+
+    #[test]
+    fn backend_is_probed_unless_chosen() {
+        let args = cli().try_get_matches_from(["ddup", "D:"]).unwrap();
+        assert_eq!(select_backend(&args), (ddup::Backend::auto(), "D:"));
+
+        let args = cli()
+            .try_get_matches_from(["ddup", "D:", "--probe-order", "usn,walkdir"])
+            .unwrap();
+        assert_eq!(
+            select_backend(&args).0,
+            ddup::Backend::Auto(vec![ddup::Backend::USN, ddup::Backend::WalkDir])
+        );
+
+        for flag in ["--usn", "--create-journal"] {
+            let args = cli().try_get_matches_from(["ddup", "D:", flag]).unwrap();
+            assert_eq!(select_backend(&args).0, ddup::Backend::USN);
+        }
+
+        let err = cli()
+            .try_get_matches_from(["ddup", "D:", "--probe-order", "usn,wiztree"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::InvalidValue);
+        let err = cli()
+            .try_get_matches_from(["ddup", "D:", "--probe-order", "usn", "-W"])
+            .unwrap_err();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
 }
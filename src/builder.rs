@@ -0,0 +1,460 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::algorithm::{self, Comparison, DedupReport, HashAlgorithm, HashOptions, SimilarGroup};
+use crate::dirlist::{build_match_options, EntryFilter};
+use crate::error::{AppError, FileErrors, Result};
+use crate::gate::{OpenFileGate, DEFAULT_MAX_OPEN_FILES};
+use crate::{Backend, DirList, Ntfs, ProgressReporter, Volume};
+
+/// Configures a duplicate scan. Only the drive is required, everything else
+/// starts at the same defaults as the command line.
+///
+/// ```no_run
+/// use ddup::algorithm::Comparison;
+/// use ddup::{Backend, DedupBuilder};
+///
+/// let report = DedupBuilder::new("D:\\Photos")
+///     .backend(Backend::WalkDir)
+///     .comparison(Comparison::Strict)
+///     .min_size(4096)
+///     .run()?;
+/// println!("{} groups in {} files", report.groups.len(), report.scanned);
+/// # Ok::<(), ddup::error::AppError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct DedupBuilder {
+    drive: String,
+    backend: Backend,
+    matchers: Vec<glob::Pattern>,
+    excludes: Vec<glob::Pattern>,
+    case_sensitive: bool,
+    literal_separator: bool,
+    literal_leading_dot: bool,
+    match_full_path: bool,
+    comparison: Comparison,
+    prefilter: bool,
+    algorithm: Option<HashAlgorithm>,
+    mmap_threshold: u64,
+    min_size: u64,
+    include_empty: bool,
+    follow_reparse: bool,
+    exclude_hidden: bool,
+    default_excludes: bool,
+    max_depth: Option<usize>,
+    modified_since: Option<u64>,
+    create_journal: bool,
+    cache: Option<PathBuf>,
+    resume: Option<PathBuf>,
+    progress: ProgressReporter,
+    max_open_files: usize,
+    throttle: Option<u64>,
+    threads: Option<usize>,
+    with_times: bool,
+    min_copies: usize,
+}
+
+impl DedupBuilder {
+    pub fn new(drive: impl Into<String>) -> Self {
+        DedupBuilder {
+            drive: drive.into(),
+            backend: Backend::auto(),
+            matchers: Vec::new(),
+            excludes: Vec::new(),
+            case_sensitive: false,
+            literal_separator: false,
+            literal_leading_dot: false,
+            match_full_path: false,
+            comparison: Comparison::Fuzzy,
+            prefilter: true,
+            algorithm: None,
+            mmap_threshold: algorithm::DEFAULT_MMAP_THRESHOLD,
+            min_size: 0,
+            include_empty: false,
+            follow_reparse: false,
+            exclude_hidden: false,
+            default_excludes: true,
+            max_depth: None,
+            modified_since: None,
+            create_journal: false,
+            cache: None,
+            resume: None,
+            progress: ProgressReporter::hidden(),
+            max_open_files: DEFAULT_MAX_OPEN_FILES,
+            throttle: None,
+            threads: None,
+            with_times: false,
+            min_copies: 2,
+        }
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Only keep files matching this pattern, or any of several
+    pub fn matcher(mut self, pattern: glob::Pattern) -> Self {
+        self.matchers.push(pattern);
+        self
+    }
+
+    pub fn exclude(mut self, pattern: glob::Pattern) -> Self {
+        self.excludes.push(pattern);
+        self
+    }
+
+    pub fn case_sensitive(mut self, case_sensitive: bool) -> Self {
+        self.case_sensitive = case_sensitive;
+        self
+    }
+
+    /// Keep `*` and `?` from matching the path separator, so `D:\*.jpg`
+    /// only matches files directly in `D:\`
+    pub fn literal_separator(mut self, literal: bool) -> Self {
+        self.literal_separator = literal;
+        self
+    }
+
+    /// Only match a leading `.` in a file or directory name with a literal
+    /// `.` in the pattern
+    pub fn literal_leading_dot(mut self, literal: bool) -> Self {
+        self.literal_leading_dot = literal;
+        self
+    }
+
+    /// Match patterns against the absolute path, as older versions did.
+    /// Otherwise only absolute patterns are, and relative ones such as
+    /// `*.jpg` match the path below the scanned folder.
+    pub fn match_full_path(mut self, full_path: bool) -> Self {
+        self.match_full_path = full_path;
+        self
+    }
+
+    pub fn comparison(mut self, comparison: Comparison) -> Self {
+        self.comparison = comparison;
+        self
+    }
+
+    /// With `Comparison::Strict`, only read files whole once their fuzzy
+    /// hash matches another file's. On by default, the results are the same
+    /// either way.
+    pub fn prefilter(mut self, prefilter: bool) -> Self {
+        self.prefilter = prefilter;
+        self
+    }
+
+    /// Defaults to `HashAlgorithm::default_for` the comparison
+    pub fn hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = Some(algorithm);
+        self
+    }
+
+    pub fn mmap_threshold(mut self, bytes: u64) -> Self {
+        self.mmap_threshold = bytes;
+        self
+    }
+
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Group empty files too. Off by default, since every empty file is
+    /// "identical" and linking them frees nothing.
+    pub fn include_empty(mut self, include: bool) -> Self {
+        self.include_empty = include;
+        self
+    }
+
+    /// List files behind symlinks and junctions too. Off by default, since
+    /// a junction back into the scanned tree would list its files twice.
+    pub fn follow_reparse(mut self, follow: bool) -> Self {
+        self.follow_reparse = follow;
+        self
+    }
+
+    /// Leave out hidden and system files
+    pub fn exclude_hidden(mut self, exclude: bool) -> Self {
+        self.exclude_hidden = exclude;
+        self
+    }
+
+    /// Skip the recycle bin, `System Volume Information`, the page file and
+    /// other places where linking files can break Windows. On by default.
+    pub fn default_excludes(mut self, enabled: bool) -> Self {
+        self.default_excludes = enabled;
+        self
+    }
+
+    /// Only list files up to this many directories below the scanned folder,
+    /// 0 keeps just the files directly in it. Unlimited by default.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Only consider files last modified at or after `time`
+    pub fn modified_since(mut self, time: SystemTime) -> Self {
+        self.modified_since = Some(
+            time.duration_since(UNIX_EPOCH)
+                .map_or(0, |since_epoch| since_epoch.as_secs()),
+        );
+        self
+    }
+
+    /// Create the USN journal when the volume has none active, rather than
+    /// failing with `AppError::UsnJournalInactive`
+    pub fn create_journal(mut self, create: bool) -> Self {
+        self.create_journal = create;
+        self
+    }
+
+    /// Keep the USN path map in this file, see `DirList::from_cache_with_delta`.
+    /// Only the USN and auto backends read it, the latter then always uses USN.
+    pub fn cache(mut self, path: impl Into<PathBuf>) -> Self {
+        self.cache = Some(path.into());
+        self
+    }
+
+    /// Save hashing progress in this file and skip what it already finished
+    pub fn resume(mut self, path: impl Into<PathBuf>) -> Self {
+        self.resume = Some(path.into());
+        self
+    }
+
+    /// Progress is hidden unless set here
+    pub fn progress(mut self, progress: ProgressReporter) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn max_open_files(mut self, limit: usize) -> Self {
+        self.max_open_files = limit;
+        self
+    }
+
+    /// Read at most this many bytes per second in total while hashing, so a
+    /// scan leaves the disk usable for other programs. Unlimited by default.
+    pub fn throttle(mut self, bytes_per_second: u64) -> Self {
+        self.throttle = Some(bytes_per_second);
+        self
+    }
+
+    /// Run on a dedicated pool of this many threads, 0 uses all cores.
+    /// Without it the scan runs on the current rayon pool.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Record the modification time of every duplicate, see `algorithm::fill_mtimes`
+    pub fn with_times(mut self, with_times: bool) -> Self {
+        self.with_times = with_times;
+        self
+    }
+
+    /// Only report files duplicated at least this many times
+    pub fn min_copies(mut self, copies: usize) -> Self {
+        self.min_copies = copies;
+        self
+    }
+
+    pub fn run(&self) -> Result<DedupReport> {
+        let options = self.match_options();
+        let gate = self.gate();
+        let run = || {
+            let mut report = algorithm::run(
+                &self.drive,
+                &self.matchers,
+                &self.excludes,
+                options,
+                HashOptions {
+                    comparison: self.comparison,
+                    prefilter: self.prefilter,
+                    algorithm: self
+                        .algorithm
+                        .unwrap_or_else(|| HashAlgorithm::default_for(&self.comparison)),
+                    mmap_threshold: self.mmap_threshold,
+                },
+                self.effective_min_size(),
+                self.entry_filter(),
+                self.backend.clone(),
+                self.cache.as_deref(),
+                self.resume.as_deref(),
+                &self.progress,
+                &gate,
+            )?;
+            if self.min_copies > 2 {
+                report.retain_min_copies(self.min_copies);
+            }
+            if self.with_times {
+                algorithm::fill_mtimes(&mut report.groups);
+            }
+            Ok(report)
+        };
+
+        self.in_pool(|| self.with_journal(run))
+    }
+
+    /// Group files that mostly share content instead of exact duplicates,
+    /// see `algorithm::similarity`. The comparison and hash settings don't
+    /// apply here.
+    pub fn run_similar(&self, threshold: f64) -> Result<Vec<SimilarGroup>> {
+        let run = || {
+            let mut dirlist = DirList::new(
+                &self.drive,
+                &self.matchers,
+                &self.excludes,
+                self.match_options(),
+                self.effective_min_size(),
+                self.entry_filter(),
+                self.backend.clone(),
+                &self.progress,
+            )?;
+            if let Some(cutoff) = self.modified_since {
+                dirlist.retain_modified_since(cutoff);
+            }
+            dirlist.errors().log_summary("listing");
+
+            let paths: Vec<&Path> = dirlist
+                .iter()
+                .filter(|(_, size)| *size > 0 && *size >= self.min_size)
+                .map(|(path, _)| path)
+                .collect();
+            let errors = FileErrors::default();
+            let groups = algorithm::similarity(&paths, threshold, &errors, &self.gate());
+            errors.log_summary("fingerprinting");
+            Ok(groups)
+        };
+
+        self.in_pool(|| self.with_journal(run))
+    }
+
+    /// Skipping empty files is the same as a minimum size of one byte
+    fn effective_min_size(&self) -> u64 {
+        if self.include_empty {
+            self.min_size
+        } else {
+            self.min_size.max(1)
+        }
+    }
+
+    fn entry_filter(&self) -> EntryFilter {
+        EntryFilter {
+            follow_reparse: self.follow_reparse,
+            exclude_hidden: self.exclude_hidden,
+            include_system_paths: !self.default_excludes,
+            max_depth: self.max_depth,
+            match_full_path: self.match_full_path,
+            modified_since: self.modified_since,
+        }
+    }
+
+    fn match_options(&self) -> glob::MatchOptions {
+        glob::MatchOptions {
+            require_literal_separator: self.literal_separator,
+            require_literal_leading_dot: self.literal_leading_dot,
+            ..build_match_options(self.case_sensitive)
+        }
+    }
+
+    /// Retry `run` once after creating the journal it found missing, when
+    /// allowed to
+    fn with_journal<T>(&self, run: impl Fn() -> Result<T>) -> Result<T> {
+        match run() {
+            Err(AppError::UsnJournalInactive { volume, .. }) if self.create_journal => {
+                log::info!("Creating USN journal on {}", volume);
+                Volume::open(&(String::from(r"\\.\") + &volume))
+                    .and_then(|handle| handle.create_usn_journal())
+                    .map_err(|e| AppError::Other {
+                        message: format!("failed to create USN journal on {}: {}", volume, e),
+                    })?;
+                run()
+            }
+            result => result,
+        }
+    }
+
+    fn gate(&self) -> OpenFileGate {
+        let gate = OpenFileGate::new(self.max_open_files).with_cancel(self.progress.cancel_flag());
+        match self.throttle {
+            Some(bytes_per_second) => gate.with_throttle(bytes_per_second),
+            None => gate,
+        }
+    }
+
+    fn in_pool<T: Send>(&self, run: impl FnOnce() -> Result<T> + Send) -> Result<T> {
+        match self.threads {
+            Some(threads) => rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .map_err(|e| AppError::Other {
+                    message: format!("failed to build thread pool: {}", e),
+                })?
+                .install(run),
+            None => run(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn defaults_match_the_command_line() {
+        let builder = DedupBuilder::new("C:");
+        assert_eq!(builder.drive, "C:");
+        assert_eq!(builder.backend, Backend::auto());
+        assert!(builder.matchers.is_empty());
+        assert!(!builder.case_sensitive);
+        assert_eq!(builder.match_options(), build_match_options(false));
+        assert!(!builder.match_full_path);
+        assert_eq!(builder.comparison, Comparison::Fuzzy);
+        assert!(builder.prefilter);
+        assert_eq!(builder.algorithm, None);
+        assert_eq!(builder.mmap_threshold, algorithm::DEFAULT_MMAP_THRESHOLD);
+        assert_eq!(builder.min_size, 0);
+        assert!(!builder.include_empty);
+        assert_eq!(builder.effective_min_size(), 1);
+        assert!(!builder.follow_reparse);
+        assert!(!builder.exclude_hidden);
+        assert!(builder.default_excludes);
+        assert_eq!(builder.max_depth, None);
+        assert!(!builder.create_journal);
+        assert!(!builder.progress.is_visible());
+        assert_eq!(builder.max_open_files, DEFAULT_MAX_OPEN_FILES);
+        assert_eq!(builder.throttle, None);
+        assert_eq!(builder.threads, None);
+        assert!(!builder.with_times);
+        assert_eq!(builder.min_copies, 2);
+    }
+
+    #[test]
+    fn configured_builder_runs_with_its_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::write(root.join("a.jpg"), b"same content").unwrap();
+        fs::write(root.join("b.jpg"), b"same content").unwrap();
+        fs::write(root.join("c.txt"), b"same content").unwrap();
+        fs::write(root.join("d.jpg"), b"tiny").unwrap();
+        fs::write(root.join("e.jpg"), b"tiny").unwrap();
+
+        let builder = DedupBuilder::new(root.to_str().unwrap())
+            .backend(Backend::WalkDir)
+            .matcher(glob::Pattern::new("*.jpg").unwrap())
+            .comparison(Comparison::Strict)
+            .min_size(5)
+            .threads(2);
+        assert_eq!(builder.matchers.len(), 1);
+        assert_eq!(builder.threads, Some(2));
+
+        let report = builder.run().unwrap();
+        assert_eq!(report.scanned, 4);
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].size, 12);
+        assert_eq!(report.groups[0].paths.len(), 2);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use nanoserde::{DeJson, SerJson};
+
+use super::winioctl::USN_REASON_FILE_DELETE;
+use super::{UsnRecord, UsnRecordType};
+
+#[derive(SerJson, DeJson)]
+struct CachedRecord {
+    id: u64,
+    parent_id: u64,
+    directory: bool,
+    filename: String,
+}
+
+/// Path map of a volume together with the journal position it was taken at
+#[derive(SerJson, DeJson)]
+pub struct UsnCache {
+    pub volume: String,
+    pub journal_id: u64,
+    pub next_usn: i64,
+    records: Vec<CachedRecord>,
+}
+
+impl UsnCache {
+    pub fn new(
+        volume: &str,
+        journal_id: u64,
+        next_usn: i64,
+        map: &HashMap<u64, UsnRecord>,
+    ) -> Self {
+        let records = map
+            .values()
+            .map(|record| CachedRecord {
+                id: record.id,
+                parent_id: record.parent_id,
+                directory: matches!(record.record_type, UsnRecordType::Directory),
+                filename: record.filename.clone(),
+            })
+            .collect();
+
+        UsnCache {
+            volume: volume.to_string(),
+            journal_id,
+            next_usn,
+            records,
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        UsnCache::deserialize_json(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.serialize_json())
+    }
+
+    pub fn into_map(self) -> HashMap<u64, UsnRecord> {
+        self.records
+            .into_iter()
+            .map(|record| {
+                let record_type = if record.directory {
+                    UsnRecordType::Directory
+                } else {
+                    UsnRecordType::File
+                };
+                let record = UsnRecord {
+                    id: record.id,
+                    parent_id: record.parent_id,
+                    record_type,
+                    filename: record.filename,
+                    reason: 0,
+                };
+                (record.id, record)
+            })
+            .collect()
+    }
+}
+
+/// Patch a cached map with journal records, oldest first. Every change
+/// carries the current name and parent, so creates and renames overwrite
+/// the entry and deletes drop it. Returns the number of records applied.
+pub fn apply_delta(
+    map: &mut HashMap<u64, UsnRecord>,
+    changes: impl Iterator<Item = UsnRecord>,
+) -> usize {
+    let mut applied = 0;
+    for change in changes {
+        if change.reason & USN_REASON_FILE_DELETE != 0 {
+            map.remove(&change.id);
+        } else {
+            map.insert(change.id, change);
+        }
+        applied += 1;
+    }
+    applied
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::hash_map_to_paths;
+    use std::path::PathBuf;
+
+    const ROOT: u64 = 0x0005_0000_0000_0005;
+    const USN_REASON_RENAME_NEW_NAME: u32 = 0x0000_2000;
+
+    fn record(id: u64, parent_id: u64, record_type: UsnRecordType, filename: &str) -> UsnRecord {
+        UsnRecord {
+            id,
+            parent_id,
+            record_type,
+            filename: filename.to_string(),
+            reason: 0,
+        }
+    }
+
+    #[test]
+    fn delta_applies_delete_and_rename() {
+        let records = vec![
+            record(0x10, ROOT, UsnRecordType::Directory, "docs"),
+            record(0x11, ROOT, UsnRecordType::File, "a.txt"),
+            record(0x12, ROOT, UsnRecordType::File, "b.txt"),
+        ];
+        let map = records.into_iter().map(|r| (r.id, r)).collect();
+
+        // Round trip through the on-disk format first
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("usn.cache");
+        UsnCache::new("C:", 1, 100, &map).save(&path).unwrap();
+        let cache = UsnCache::load(&path).unwrap();
+        assert_eq!(cache.next_usn, 100);
+        let mut map = cache.into_map();
+
+        let delta = vec![
+            UsnRecord {
+                reason: USN_REASON_FILE_DELETE,
+                ..record(0x11, ROOT, UsnRecordType::File, "a.txt")
+            },
+            UsnRecord {
+                reason: USN_REASON_RENAME_NEW_NAME,
+                ..record(0x12, 0x10, UsnRecordType::File, "c.txt")
+            },
+        ];
+        assert_eq!(apply_delta(&mut map, delta.into_iter()), 2);
+
+        assert!(!map.contains_key(&0x11));
+        let paths = hash_map_to_paths(&map).paths;
+        assert_eq!(paths, [Path::new("docs").join("c.txt")]);
+        assert!(!paths.contains(&PathBuf::from("b.txt")));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use nanoserde::{DeJson, SerJson};
+use rapidhash::fast::RapidHasher;
+
+use super::algorithm::DuplicateGroup;
+
+/// How often progress is written out while hashing
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Size groups that were fully hashed by an interrupted run, with their
+/// results, so `--resume` can skip them
+#[derive(SerJson, DeJson, Debug, Default)]
+pub struct Checkpoint {
+    /// Settings the results depend on, see `scan_key`
+    pub scan: String,
+    /// Fingerprint of the files being hashed, see `fingerprint`
+    pub fingerprint: u64,
+    pub completed: Vec<u64>,
+    pub groups: Vec<DuplicateGroup>,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Checkpoint::deserialize_json(&json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+
+    /// Write through a temporary file so an interruption never leaves a
+    /// half-written checkpoint behind
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, self.serialize_json())?;
+        fs::rename(&tmp_path, path)
+    }
+
+    /// Load the checkpoint at `path` if it was made by the same scan of the
+    /// same files, otherwise start from an empty one
+    pub fn resume(path: &Path, scan: &str, fingerprint: u64) -> Self {
+        match Checkpoint::load(path) {
+            Ok(checkpoint) if checkpoint.scan == scan && checkpoint.fingerprint == fingerprint => {
+                checkpoint
+            }
+            Ok(_) => {
+                log::warn!(
+                    "Checkpoint {} is from a different scan or the files changed, starting over",
+                    path.display()
+                );
+                Checkpoint::fresh(scan, fingerprint)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Checkpoint::fresh(scan, fingerprint),
+            Err(e) => {
+                log::warn!("Ignoring unreadable checkpoint {}: {}", path.display(), e);
+                Checkpoint::fresh(scan, fingerprint)
+            }
+        }
+    }
+
+    fn fresh(scan: &str, fingerprint: u64) -> Self {
+        Checkpoint {
+            scan: scan.to_string(),
+            fingerprint,
+            ..Default::default()
+        }
+    }
+}
+
+/// Identify the settings that decide which groups a scan finds
+pub fn scan_key(
+    drive: &str,
+    matchers: &[glob::Pattern],
+    excludes: &[glob::Pattern],
+    comparison: &impl std::fmt::Debug,
+    algorithm: &impl std::fmt::Debug,
+) -> String {
+    let patterns = |patterns: &[glob::Pattern]| {
+        patterns
+            .iter()
+            .map(|p| p.as_str())
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+    format!(
+        "{};{};{};{:?};{:?}",
+        drive,
+        patterns(matchers),
+        patterns(excludes),
+        comparison,
+        algorithm
+    )
+}
+
+/// Order-independent fingerprint of the files to hash, so a checkpoint is
+/// dropped when files were added, removed or resized since it was written
+pub fn fingerprint<P: AsRef<Path>>(map: &HashMap<u64, Vec<P>>) -> u64 {
+    map.iter()
+        .flat_map(|(size, paths)| paths.iter().map(move |path| (*size, path)))
+        .map(|(size, path)| {
+            let mut hasher = RapidHasher::default();
+            hasher.write(path.as_ref().as_os_str().as_encoded_bytes());
+            hasher.write_u64(size);
+            hasher.finish()
+        })
+        .fold(0u64, u64::wrapping_add)
+}
+
+/// Records completed size groups from the hashing threads and saves the
+/// checkpoint every so often
+pub struct CheckpointWriter {
+    path: PathBuf,
+    state: Mutex<(Checkpoint, Instant)>,
+}
+
+impl CheckpointWriter {
+    pub fn new(path: &Path, checkpoint: Checkpoint) -> Self {
+        CheckpointWriter {
+            path: path.to_path_buf(),
+            state: Mutex::new((checkpoint, Instant::now())),
+        }
+    }
+
+    pub fn complete(&self, size: u64, groups: &[DuplicateGroup]) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let (checkpoint, last_saved) = &mut *state;
+        checkpoint.completed.push(size);
+        checkpoint.groups.extend_from_slice(groups);
+
+        if last_saved.elapsed() >= CHECKPOINT_INTERVAL {
+            if let Err(e) = checkpoint.save(&self.path) {
+                log::warn!("Failed to save checkpoint {}: {}", self.path.display(), e);
+            }
+            *last_saved = Instant::now();
+        }
+    }
+
+    /// The scan was interrupted, save what finished for the next run
+    pub fn save(self) {
+        if let Ok((checkpoint, _)) = self.state.into_inner() {
+            if let Err(e) = checkpoint.save(&self.path) {
+                log::warn!("Failed to save checkpoint {}: {}", self.path.display(), e);
+            }
+        }
+    }
+
+    /// The scan completed, so there is nothing left to resume
+    pub fn finish(self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("Failed to remove checkpoint {}: {}", self.path.display(), e);
+            }
+        }
+    }
+}
@@ -1,206 +1,1401 @@
-use crate::error::Result;
-use indicatif::ProgressBar;
+use crate::error::{usn_journal_query_error, AppError, FileErrors, Result};
+use crate::everything::{is_transient_error, EverythingSearch};
 use rayon::prelude::*;
 use snafu::ResultExt;
-use std::path::{Path, PathBuf};
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::os::windows::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use super::utils::{hash_map_to_paths, usn_records_to_hash_map};
+use super::cache::{apply_delta, UsnCache};
+use super::mft::{read_mft, resolve_files, MftFile, MftRecord};
+use super::progress::{ItemCounter, ProgressReporter};
+use super::utils::{
+    hash_map_to_paths, is_directory_attribute, is_hidden_attribute, is_reparse_attribute,
+    is_within, normalize_path, split_scan_root, usn_records_to_hash_map,
+};
 use super::Ntfs;
-use super::UsnRange;
 use super::Volume;
+use super::{UsnRange, UsnRecord};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Backend {
-    Everything,
+    /// Query an Everything instance, by name or the default one
+    Everything(Option<String>),
     USN,
+    /// Parse the volume's `$MFT` directly, which yields sizes without a stat
+    /// per file. Falls back to USN on volumes it can't read.
+    Mft,
     WizTree,
+    WalkDir,
+    /// Paths listed one per line in a file, or on stdin
+    FileList,
+    /// Try each backend in turn and list with the first that works, see
+    /// `Backend::auto` for the default order
+    Auto(Vec<Backend>),
+}
+
+impl Backend {
+    /// Everything if it's running, else the USN journal if it can be read,
+    /// else a walk of the folder, which works anywhere but stats every file
+    pub fn auto() -> Self {
+        Backend::Auto(vec![
+            Backend::Everything(None),
+            Backend::USN,
+            Backend::WalkDir,
+        ])
+    }
+
+    /// The backends `DirList::new` tries for this one, in order
+    fn candidates(&self) -> Vec<Backend> {
+        match self {
+            Backend::Auto(order) => order.clone(),
+            // A named Everything instance was asked for explicitly, so it
+            // doesn't quietly fall back to a different backend
+            Backend::Everything(None) | Backend::Mft => vec![self.clone(), Backend::USN],
+            _ => vec![self.clone()],
+        }
+    }
+}
+
+/// Call `list` with each backend in turn until one succeeds, logging why
+/// the others were passed over. Fails with the last backend's error.
+fn first_available<T>(
+    order: &[Backend],
+    mut list: impl FnMut(&Backend) -> Result<T>,
+) -> Result<(Backend, T)> {
+    let mut failed = None;
+    for (i, backend) in order.iter().enumerate() {
+        match list(backend) {
+            Ok(listed) => {
+                if order.len() > 1 {
+                    log::info!("Listing files with {:?}", backend);
+                }
+                return Ok((backend.clone(), listed));
+            }
+            Err(e) => {
+                if let Some(next) = order.get(i + 1) {
+                    log::warn!("[{:?}] {}, trying {:?}", backend, e, next);
+                }
+                failed = Some(e);
+            }
+        }
+    }
+    Err(failed.unwrap_or_else(|| AppError::Other {
+        message: "no backend to list files with".to_string(),
+    }))
+}
+
+/// A listed file and what the backend knows about it besides its size
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, `None` when the
+    /// backend doesn't report it
+    pub mtime: Option<u64>,
+    /// Win32 file attributes, 0 when the backend doesn't report them
+    pub attributes: u32,
+}
+
+impl FileEntry {
+    /// An entry of which only the size is known
+    pub fn new(path: PathBuf, size: u64) -> Self {
+        FileEntry {
+            path,
+            size,
+            mtime: None,
+            attributes: 0,
+        }
+    }
+
+    /// An entry with everything `metadata` knows about the file at `path`
+    fn from_metadata(path: PathBuf, metadata: &std::fs::Metadata) -> Self {
+        FileEntry {
+            path,
+            size: metadata.len(),
+            mtime: metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|age| age.as_secs()),
+            attributes: metadata.file_attributes(),
+        }
+    }
+}
+
+impl From<(PathBuf, u64)> for FileEntry {
+    fn from((path, size): (PathBuf, u64)) -> Self {
+        FileEntry::new(path, size)
+    }
+}
+
+impl AsRef<Path> for FileEntry {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Which entries to keep beyond what the glob patterns decide
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EntryFilter {
+    /// List files behind symlinks and junctions, which could list the same
+    /// files twice
+    pub follow_reparse: bool,
+    /// Drop hidden and system files
+    pub exclude_hidden: bool,
+    /// List files in the `SYSTEM_PATHS` too, which are skipped by default
+    pub include_system_paths: bool,
+    /// Directories to descend below the scan root, 0 lists only the files
+    /// directly in it. Ignored by backends without a scan root.
+    pub max_depth: Option<usize>,
+    /// Match every glob pattern against the absolute path, rather than
+    /// relative ones against the path below the scan root
+    pub match_full_path: bool,
+    /// Drop files last modified before this, in seconds since the Unix
+    /// epoch. Applied to the finished listing, see
+    /// `DirList::retain_modified_since`.
+    pub modified_since: Option<u64>,
+}
+
+impl EntryFilter {
+    /// Whether a file with these attributes is kept. Reparse points are
+    /// told apart by `keeps_reparse`.
+    pub fn keeps(&self, attributes: u32) -> bool {
+        !(self.exclude_hidden && is_hidden_attribute(attributes))
+    }
+
+    /// Whether the file at `path` is kept as far as reparse points go. As in
+    /// `stat_file` only symlinks and junctions are dropped, the others such
+    /// as dedup or cloud placeholders still hold data. Reads the reparse tag
+    /// of files with the reparse attribute only.
+    pub fn keeps_reparse(&self, path: &Path, attributes: u32) -> bool {
+        self.follow_reparse || !is_reparse_attribute(attributes) || !is_link(path)
+    }
+
+    /// Whether `path` lies within `max_depth` directories below `root`
+    fn within_depth(&self, path: &Path, root: &Path) -> bool {
+        self.max_depth
+            .is_none_or(|max_depth| depth_below(path, root) <= max_depth)
+    }
+}
+
+/// Directories between `root` and a file at `path` under it, counting only
+/// named components so `C:`, `C:\` and relative roots all work
+fn depth_below(path: &Path, root: &Path) -> usize {
+    let named = |path: &Path| {
+        path.components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .count()
+    };
+    named(path).saturating_sub(named(root) + 1)
+}
+
+/// Glob options shared by every backend. By default `*` and `?` match
+/// across directory separators and leading dots, see the builder's
+/// `literal_separator` and `literal_leading_dot` for stricter matching.
+pub fn build_match_options(case_sensitive: bool) -> glob::MatchOptions {
+    glob::MatchOptions {
+        case_sensitive,
+        require_literal_separator: false,
+        require_literal_leading_dot: false,
+    }
+}
+
+/// Patterns starting with a drive or a separator, such as `C:\Photos\**`,
+/// name absolute paths
+fn is_absolute_pattern(pattern: &glob::Pattern) -> bool {
+    matches!(
+        Path::new(pattern.as_str()).components().next(),
+        Some(Component::Prefix(_) | Component::RootDir)
+    )
+}
+
+/// `path` below the scan `root`, or all of it when it isn't under the root.
+/// Backends without a scan root pass an empty one.
+fn relative_to_root<'a>(path: &'a Path, root: &Path) -> &'a Path {
+    if !is_within(path, root) {
+        return path;
+    }
+    let mut components = path.components();
+    for _ in root.components() {
+        components.next();
+    }
+    // A root like `C:` has no separator of its own, the paths under it do
+    let mut rest = components.clone();
+    if let Some(Component::RootDir) = rest.next() {
+        components = rest;
+    }
+    components.as_path()
+}
+
+/// Relative patterns match the path below the scan root, so `*.jpg` or
+/// `Photos\*.jpg` work on any drive. Absolute patterns, or all of them with
+/// `match_full_path`, match the full path.
+fn pattern_matches(
+    pattern: &glob::Pattern,
+    path: &Path,
+    root: &Path,
+    options: glob::MatchOptions,
+    filter: EntryFilter,
+) -> bool {
+    let target = if filter.match_full_path || is_absolute_pattern(pattern) {
+        path
+    } else {
+        relative_to_root(path, root)
+    };
+    pattern.matches_path_with(target, options)
+}
+
+/// A path is included when there are no matchers or any of them matches
+fn is_included(
+    path: &Path,
+    root: &Path,
+    matchers: &[glob::Pattern],
+    options: glob::MatchOptions,
+    filter: EntryFilter,
+) -> bool {
+    matchers.is_empty()
+        || matchers
+            .iter()
+            .any(|pat| pattern_matches(pat, path, root, options, filter))
+}
+
+fn is_excluded(
+    path: &Path,
+    root: &Path,
+    excludes: &[glob::Pattern],
+    options: glob::MatchOptions,
+    filter: EntryFilter,
+) -> bool {
+    (!filter.include_system_paths && is_system_path(path))
+        || excludes
+            .iter()
+            .any(|pat| pattern_matches(pat, path, root, options, filter))
+}
+
+/// Places under the volume root where linking files can break Windows
+const SYSTEM_PATHS: [&[&str]; 6] = [
+    &["Windows", "WinSxS"],
+    &["$Recycle.Bin"],
+    &["System Volume Information"],
+    &["pagefile.sys"],
+    &["hiberfil.sys"],
+    &["swapfile.sys"],
+];
+
+/// Whether `path` is or lies under one of the `SYSTEM_PATHS`, compared
+/// case-insensitively by component so no stat is needed
+fn is_system_path(path: &Path) -> bool {
+    let components: Vec<&std::ffi::OsStr> = path
+        .components()
+        .filter(|c| !matches!(c, Component::Prefix(_) | Component::RootDir))
+        .take(2)
+        .map(|c| c.as_os_str())
+        .collect();
+    SYSTEM_PATHS.iter().any(|system| {
+        system.len() <= components.len()
+            && system
+                .iter()
+                .zip(&components)
+                .all(|(name, component)| component.eq_ignore_ascii_case(name))
+    })
+}
+
+/// Combine the drive and matchers into an Everything search query, OR-ing
+/// multiple matchers together. Everything matches whole paths, so relative
+/// patterns naming a folder are anchored at the drive. A non-zero `min_size`
+/// is passed on as a `size:` filter so the service doesn't return files we'd
+/// discard anyway.
+fn everything_query(drive: &str, matchers: &[glob::Pattern], min_size: u64) -> String {
+    let mut query = drive.to_string();
+    if !query.ends_with('\\') {
+        query.push('\\');
+    }
+
+    let quoted: Vec<String> = matchers
+        .iter()
+        .map(|m| {
+            if !is_absolute_pattern(m) && m.as_str().contains(['\\', '/']) {
+                format!("\"{}{}\"", query, m.as_str())
+            } else {
+                format!("\"{}\"", m.as_str())
+            }
+        })
+        .collect();
+    match quoted.len() {
+        0 => {}
+        1 => {
+            query.push(' ');
+            query.push_str(&quoted[0]);
+        }
+        _ => {
+            query.push_str(" <");
+            query.push_str(&quoted.join("|"));
+            query.push('>');
+        }
+    }
+
+    if min_size > 0 {
+        query.push_str(&format!(" size:>={}", min_size));
+    }
+
+    query
+}
+
+/// Accepted spellings of the WizTree columns we read, compared ignoring case
+const WIZTREE_NAME_COLUMNS: [&str; 3] = ["File Name", "Name", "Path"];
+const WIZTREE_SIZE_COLUMNS: [&str; 1] = ["Size"];
+const WIZTREE_ATTRIBUTES_COLUMNS: [&str; 1] = ["Attributes"];
+
+/// Win32 file attributes as WizTree writes them, in decimal or `0x` hex
+fn parse_attributes(value: &str) -> Option<u32> {
+    let value = value.trim();
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn find_column<'a>(headers: impl Iterator<Item = &'a [u8]>, names: &[&str]) -> Option<usize> {
+    let headers: Vec<String> = headers
+        .map(|h| {
+            String::from_utf8_lossy(h)
+                .trim_matches(|c: char| c == '\u{feff}' || c == '"' || c.is_whitespace())
+                .to_string()
+        })
+        .collect();
+    // Earlier names are preferred, so "File Name" wins over a plain "Name"
+    names
+        .iter()
+        .find_map(|name| headers.iter().position(|h| h.eq_ignore_ascii_case(name)))
+}
+
+/// Whether a line is the column header rather than WizTree's banner line
+fn is_wiztree_header(line: &str) -> bool {
+    let columns = || line.trim_end().split(',').map(str::as_bytes);
+    find_column(columns(), &WIZTREE_NAME_COLUMNS).is_some()
+        && find_column(columns(), &WIZTREE_SIZE_COLUMNS).is_some()
+}
+
+/// Bytes of a WizTree export parsed by one worker. Large enough that the
+/// thread hand-off is noise, small enough to spread a full-drive export.
+const WIZTREE_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+fn wiztree_csv_reader<R: std::io::Read>(
+    reader: R,
+    has_headers: bool,
+) -> simd_csv::ZeroCopyReader<R> {
+    simd_csv::ZeroCopyReaderBuilder::new()
+        .has_headers(has_headers)
+        .flexible(true)
+        .from_reader(reader)
+}
+
+/// Columns of a WizTree export and the filters its rows go through
+struct WiztreeRows<'a> {
+    file_name_index: usize,
+    size_index: usize,
+    attributes_index: Option<usize>,
+    matchers: &'a [glob::Pattern],
+    excludes: &'a [glob::Pattern],
+    options: glob::MatchOptions,
+    filter: EntryFilter,
+}
+
+impl<'a> WiztreeRows<'a> {
+    fn read_headers<R: std::io::Read>(
+        csv_reader: &mut simd_csv::ZeroCopyReader<R>,
+        matchers: &'a [glob::Pattern],
+        excludes: &'a [glob::Pattern],
+        options: glob::MatchOptions,
+        filter: EntryFilter,
+    ) -> Result<Self> {
+        let headers = csv_reader
+            .byte_headers()
+            .map_err(|e| crate::error::AppError::Other {
+                message: format!("Failed to read WizTree CSV headers: {}", e),
+            })?;
+
+        let missing = |what: &str, names: &[&str]| {
+            let found: Vec<String> = headers
+                .iter()
+                .map(|h| String::from_utf8_lossy(h).into_owned())
+                .collect();
+            crate::error::AppError::Other {
+                message: format!(
+                    "WizTree CSV has no {} column (expected one of {}), found: {}",
+                    what,
+                    names.join(", "),
+                    found.join(", ")
+                ),
+            }
+        };
+        Ok(WiztreeRows {
+            file_name_index: find_column(headers.iter(), &WIZTREE_NAME_COLUMNS)
+                .ok_or_else(|| missing("file name", &WIZTREE_NAME_COLUMNS))?,
+            size_index: find_column(headers.iter(), &WIZTREE_SIZE_COLUMNS)
+                .ok_or_else(|| missing("size", &WIZTREE_SIZE_COLUMNS))?,
+            attributes_index: find_column(headers.iter(), &WIZTREE_ATTRIBUTES_COLUMNS),
+            matchers,
+            excludes,
+            options,
+            filter,
+        })
+    }
+
+    /// The files among the remaining rows of `csv_reader`
+    fn read<R: std::io::Read>(
+        &self,
+        csv_reader: &mut simd_csv::ZeroCopyReader<R>,
+    ) -> Result<Vec<FileEntry>> {
+        let mut entries = Vec::new();
+
+        while let Some(record) =
+            csv_reader
+                .read_byte_record()
+                .map_err(|e| crate::error::AppError::Other {
+                    message: format!("WizTree CSV parsing error: {}", e),
+                })?
+        {
+            entries.extend(self.entry(&record));
+        }
+
+        Ok(entries)
+    }
+
+    fn entry(&self, record: &simd_csv::ZeroCopyByteRecord) -> Option<FileEntry> {
+        // WizTree CSV format:
+        // File Name,Size,Allocated,Modified,Attributes,Files,Folders,...
+        let path_bytes = record.unquote(self.file_name_index).unwrap_or_default();
+        let size_bytes = record.unquote(self.size_index).unwrap_or_default();
+
+        let path_str = String::from_utf8_lossy(path_bytes);
+        let size_str = String::from_utf8_lossy(size_bytes);
+
+        // The directory bit is authoritative where the export has it
+        let attributes = self
+            .attributes_index
+            .and_then(|index| record.unquote(index))
+            .and_then(|value| parse_attributes(&String::from_utf8_lossy(value)));
+        let is_directory = attributes.is_some_and(is_directory_attribute);
+        if attributes.is_some_and(|attributes| !self.filter.keeps(attributes)) {
+            return None;
+        }
+
+        let size = size_str
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .filter(|&size| size > 0)?;
+        let path = normalize_path(&path_str);
+        // Folders in WizTree CSV end with "\" and have size summary of children
+        // We only want files for deduplication
+        if !is_directory
+            && !path_str.ends_with('\\')
+            && attributes.is_none_or(|attributes| self.filter.keeps_reparse(&path, attributes))
+            && is_included(
+                &path,
+                Path::new(""),
+                self.matchers,
+                self.options,
+                self.filter,
+            )
+            && !is_excluded(
+                &path,
+                Path::new(""),
+                self.excludes,
+                self.options,
+                self.filter,
+            )
+        {
+            Some(FileEntry {
+                attributes: attributes.unwrap_or(0),
+                ..FileEntry::new(path, size)
+            })
+        } else {
+            None
+        }
+    }
+}
+
+fn parse_wiztree_csv<R: std::io::BufRead>(
+    mut reader: R,
+    matchers: &[glob::Pattern],
+    excludes: &[glob::Pattern],
+    options: glob::MatchOptions,
+    filter: EntryFilter,
+) -> Result<Vec<FileEntry>> {
+    // Most exports start with a "Generated by WizTree" banner, but not all
+    let mut first_line = String::new();
+    reader
+        .read_line(&mut first_line)
+        .context(crate::error::IoSnafu)?;
+    if !is_wiztree_header(&first_line) {
+        first_line.clear();
+    }
+
+    // We use the remaining reader with simd-csv, handing back the header if
+    // it was on the first line
+    let mut csv_reader =
+        wiztree_csv_reader(std::io::Read::chain(first_line.as_bytes(), reader), true);
+    let rows = WiztreeRows::read_headers(&mut csv_reader, matchers, excludes, options, filter)?;
+    rows.read(&mut csv_reader)
+}
+
+/// Split CSV `data` into pieces of at least `chunk_size` bytes that each end
+/// after a record. Only a newline outside quotes ends a record, WizTree quotes
+/// every path and a path may hold a newline; an escaped `""` toggles twice.
+fn split_csv_records(data: &[u8], chunk_size: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, &byte) in data.iter().enumerate() {
+        match byte {
+            b'"' => quoted = !quoted,
+            b'\n' if !quoted && i + 1 - start >= chunk_size => {
+                chunks.push(&data[start..=i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if start < data.len() {
+        chunks.push(&data[start..]);
+    }
+    chunks
+}
+
+/// `parse_wiztree_csv` on an export held in memory, with the rows split into
+/// chunks of `chunk_size` bytes that are parsed in parallel
+fn parse_wiztree_csv_parallel(
+    data: &[u8],
+    chunk_size: usize,
+    matchers: &[glob::Pattern],
+    excludes: &[glob::Pattern],
+    options: glob::MatchOptions,
+    filter: EntryFilter,
+) -> Result<Vec<FileEntry>> {
+    let line_end = |data: &[u8]| {
+        data.iter()
+            .position(|&byte| byte == b'\n')
+            .map_or(data.len(), |i| i + 1)
+    };
+    let (banner, rest) = data.split_at(line_end(data));
+    let data = if is_wiztree_header(&String::from_utf8_lossy(banner)) {
+        data
+    } else {
+        rest
+    };
+
+    // The header is a plain line of column names, the rows are what's big
+    let (header, body) = data.split_at(line_end(data));
+    let rows = WiztreeRows::read_headers(
+        &mut wiztree_csv_reader(header, true),
+        matchers,
+        excludes,
+        options,
+        filter,
+    )?;
+
+    let chunks = split_csv_records(body, chunk_size)
+        .into_par_iter()
+        .map(|chunk| rows.read(&mut wiztree_csv_reader(chunk, false)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(chunks.concat())
+}
+
+/// Whether `path` is a symlink or junction rather than a file of its own
+fn is_link(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink())
+}
+
+/// Entry of a regular file, or `None` for directories and files `filter`
+/// drops. Of the reparse points only symlinks and junctions are dropped, the
+/// others such as dedup or cloud placeholders still hold data.
+fn stat_file(path: &Path, filter: EntryFilter) -> std::io::Result<Option<FileEntry>> {
+    file_entry(path, std::fs::symlink_metadata(path)?, filter)
+}
+
+/// `stat_file` on metadata already at hand, which must not follow links
+fn file_entry(
+    path: &Path,
+    metadata: std::fs::Metadata,
+    filter: EntryFilter,
+) -> std::io::Result<Option<FileEntry>> {
+    if filter.exclude_hidden && is_hidden_attribute(metadata.file_attributes()) {
+        return Ok(None);
+    }
+    let metadata =
+        if is_reparse_attribute(metadata.file_attributes()) && metadata.file_type().is_symlink() {
+            if !filter.follow_reparse {
+                return Ok(None);
+            }
+            std::fs::metadata(path)?
+        } else {
+            metadata
+        };
+    Ok(metadata
+        .is_file()
+        .then(|| FileEntry::from_metadata(path.to_path_buf(), &metadata)))
+}
+
+/// Directories holding fewer of the wanted files than this are stat'ed file
+/// by file, listing a large directory for a single file costs more than it
+/// saves
+const BATCH_STAT_MIN_FILES: usize = 4;
+
+/// `stat_file` for many paths at once. Paths are grouped by directory and
+/// each directory is listed once, since on Windows the listing already
+/// carries every size and attribute, instead of one syscall per file.
+fn stat_files_batched(
+    mut paths: Vec<PathBuf>,
+    filter: EntryFilter,
+    errors: &FileErrors,
+    progress: &ItemCounter,
+) -> Vec<FileEntry> {
+    paths.par_sort_unstable_by(|a, b| a.parent().cmp(&b.parent()));
+    let directories: Vec<&[PathBuf]> = paths.chunk_by(|a, b| a.parent() == b.parent()).collect();
+
+    let stat_each = |paths: &[PathBuf]| -> Vec<FileEntry> {
+        paths
+            .iter()
+            .filter_map(|path| match stat_file(path, filter) {
+                Ok(entry) => entry,
+                Err(e) => {
+                    errors.record(path, e);
+                    None
+                }
+            })
+            .collect()
+    };
+
+    directories
+        .into_par_iter()
+        .flat_map_iter(|paths| {
+            progress.inc(paths.len() as u64);
+            let listing = match paths[0].parent() {
+                Some(parent) if paths.len() >= BATCH_STAT_MIN_FILES => std::fs::read_dir(parent),
+                _ => return stat_each(paths),
+            };
+            let mut listed: HashMap<std::ffi::OsString, std::fs::Metadata> = match listing {
+                Ok(listing) => listing
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| Some((entry.file_name(), entry.metadata().ok()?)))
+                    .collect(),
+                // Let each file report why it can't be read
+                Err(_) => return stat_each(paths),
+            };
+
+            paths
+                .iter()
+                .filter_map(|path| {
+                    let entry = match path.file_name().and_then(|name| listed.remove(name)) {
+                        Some(metadata) => file_entry(path, metadata, filter),
+                        // Gone since the journal was read, or named
+                        // differently in the listing
+                        None => stat_file(path, filter),
+                    };
+                    match entry {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            errors.record(path, e);
+                            None
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn write_wiztree_csv<W: Write>(entries: &[FileEntry], mut writer: W) -> std::io::Result<()> {
+    // WizTree puts a banner line before the header
+    writeln!(writer, "Generated by ddup {}", env!("CARGO_PKG_VERSION"))?;
+    writeln!(writer, "File Name,Size")?;
+    for FileEntry { path, size, .. } in entries {
+        // WizTree quotes every file name, doubling embedded quotes
+        let name = path.to_string_lossy().replace('"', "\"\"");
+        writeln!(writer, "\"{}\",{}", name, size)?;
+    }
+    writer.flush()
 }
 
 pub struct DirList {
-    entries: Vec<(PathBuf, u64)>,
+    entries: Vec<FileEntry>,
+    /// Journal records that could not be placed under the volume root
+    orphans: usize,
+    /// Files that were found but could not be stat'ed
+    errors: FileErrors,
 }
 
 impl DirList {
+    /// List the files under `drive` with `backend`, or with the first of its
+    /// fallbacks that works, see `Backend::candidates`
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         drive: &str,
-        matcher: Option<&str>,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
+        options: glob::MatchOptions,
+        min_size: u64,
+        filter: EntryFilter,
+        backend: Backend,
+        progress: &ProgressReporter,
+    ) -> Result<Self> {
+        // Walking `C:` would list the current folder of the drive
+        let bare_drive = matches!(backend, Backend::Auto(_))
+            && matches!(drive.as_bytes(), [letter, b':'] if letter.is_ascii_alphabetic());
+        first_available(&backend.candidates(), |candidate| {
+            let root = match candidate {
+                Backend::WalkDir if bare_drive => format!("{}\\", drive),
+                _ => drive.to_string(),
+            };
+            Self::list_with(
+                &root,
+                matchers,
+                excludes,
+                options,
+                min_size,
+                filter,
+                candidate.clone(),
+                progress,
+            )
+        })
+        .map(|(_, dirlist)| dirlist)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn list_with(
+        drive: &str,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
         options: glob::MatchOptions,
+        min_size: u64,
+        filter: EntryFilter,
         backend: Backend,
+        progress: &ProgressReporter,
     ) -> Result<Self> {
         match backend {
-            Backend::Everything => {
-                if let Some(everything) = super::everything::EverythingSearch::new() {
-                    // Combine drive and matcher for Everything search
-                    let mut query = drive.to_string();
-                    if !query.ends_with('\\') {
-                        query.push('\\');
-                    }
-                    if let Some(m) = matcher {
-                        query.push_str(" \"");
-                        query.push_str(m);
-                        query.push('"');
-                    }
+            Backend::Everything(instance) => {
+                let connect = || match &instance {
+                    Some(name) => EverythingSearch::with_instance(name),
+                    None => EverythingSearch::new(),
+                };
+                let everything = connect()?;
+                let query = everything_query(drive, matchers, min_size);
+                let search = |everything: &EverythingSearch| {
+                    everything.get_all_files(&query, options.case_sensitive, filter, progress)
+                };
 
-                    match everything.get_all_files(&query, options.case_sensitive) {
-                        Ok(entries) => {
-                            if !entries.is_empty() {
-                                return Ok(DirList { entries });
-                            }
-                            log::warn!(
-                                "[Everything] Warning: Search returned no results, falling back to USN"
-                            );
-                        }
-                        Err(e) => {
-                            log::warn!("[Everything] Error: {}, falling back to USN", e);
-                        }
+                let mut entries = match search(&everything) {
+                    // A dropped connection is worth one more try, other
+                    // errors won't go away by asking again
+                    Err(AppError::Everything { code, .. }) if is_transient_error(code) => {
+                        log::warn!("[Everything] Connection lost, reconnecting");
+                        drop(everything);
+                        connect().and_then(|everything| search(&everything))
                     }
-                } else {
-                    log::warn!("[Everything] Warning: Service not found, falling back to USN");
+                    result => result,
+                }?;
+                entries.retain(|FileEntry { path, size, .. }| {
+                    let root = Path::new(drive);
+                    *size >= min_size
+                        && filter.within_depth(path, root)
+                        && is_included(path, root, matchers, options, filter)
+                        && !is_excluded(path, root, excludes, options, filter)
+                });
+                // Most likely the drive isn't indexed, unless a named
+                // instance was asked for, which has no fallback anyway
+                if entries.is_empty() && instance.is_none() {
+                    return Err(AppError::Other {
+                        message: "Everything search returned no results".to_string(),
+                    });
                 }
-                // Fallback to USN
-                Self::new(drive, matcher, options, Backend::USN)
+                Ok(DirList {
+                    entries,
+                    orphans: 0,
+                    errors: FileErrors::default(),
+                })
             }
             Backend::USN => {
-                let volume = Volume::open(&(String::from(r"\\.\") + drive))
+                let (volume_name, subdir) = split_scan_root(drive);
+                let volume = Volume::open(&(String::from(r"\\.\") + &volume_name))
                     .context(crate::error::VolumeOpenSnafu { drive })?;
                 let journal = volume
                     .query_usn_journal()
-                    .context(crate::error::UsnJournalQuerySnafu)?;
+                    .map_err(|e| usn_journal_query_error(&volume_name, e))?;
                 let range = UsnRange {
                     low: journal.LowestValidUsn,
                     high: journal.NextUsn,
                 };
-                let usn_records = volume.usn_records(&range);
+                // Interrupted listings still resolve what was read so far
+                let usn_records = volume
+                    .usn_records(&range)
+                    .take_while(|_| !progress.is_cancelled());
                 let map = usn_records_to_hash_map(usn_records);
-                let paths = hash_map_to_paths(&map);
-
-                let pattern =
-                    matcher.map(|m| glob::Pattern::new(m).context(crate::error::GlobSnafu));
-                let pattern = match pattern {
-                    Some(Ok(p)) => Some(p),
-                    Some(Err(e)) => return Err(e),
-                    None => None,
-                };
 
-                log::info!("Processing {} paths from USN journal", paths.len());
-                let progress = ProgressBar::new(paths.len() as u64);
-                let entries: Vec<_> = paths
-                    .par_iter()
-                    .map(|p| {
-                        progress.inc(1);
-                        Path::new(drive).join(p)
-                    })
-                    .filter(|full_path| {
-                        pattern
-                            .as_ref()
-                            .is_none_or(|pat| pat.matches_path_with(full_path, options))
-                    })
-                    .filter_map(|full_path| {
-                        std::fs::metadata(&full_path)
-                            .ok()
-                            .filter(|m| m.is_file())
-                            .map(|m| (full_path, m.len()))
-                    })
-                    .collect();
-                progress.finish();
-
-                Ok(DirList { entries })
+                Ok(Self::from_usn_map(
+                    &volume_name,
+                    subdir.as_deref(),
+                    &map,
+                    matchers,
+                    excludes,
+                    options,
+                    filter,
+                    progress,
+                ))
+            }
+            Backend::Mft => {
+                let (volume_name, subdir) = split_scan_root(drive);
+                let volume = Volume::open(&(String::from(r"\\.\") + &volume_name))
+                    .context(crate::error::VolumeOpenSnafu { drive })?;
+                let records = read_mft(&volume).map_err(|e| AppError::Other {
+                    message: format!("failed to read the MFT of {}: {}", volume_name, e),
+                })?;
+                Ok(Self::from_mft_records(
+                    &volume_name,
+                    subdir.as_deref(),
+                    records,
+                    matchers,
+                    excludes,
+                    options,
+                    filter,
+                    progress,
+                ))
+            }
+            Backend::WizTree | Backend::FileList if filter.max_depth.is_some() => {
+                log::warn!(
+                    "--max-depth needs a scan root, ignoring it for {:?}",
+                    backend
+                );
+                let filter = EntryFilter {
+                    max_depth: None,
+                    ..filter
+                };
+                Self::list_with(
+                    drive, matchers, excludes, options, min_size, filter, backend, progress,
+                )
             }
             Backend::WizTree => {
                 // In case of WizTree, drive is actually the path to the CSV file
-                Self::from_wiztree_csv(drive, matcher, options)
+                Self::from_wiztree_csv(drive, matchers, excludes, options, filter)
             }
+            Backend::WalkDir => {
+                // In case of WalkDir, drive is the root directory to walk
+                Self::from_walkdir(drive, matchers, excludes, options, filter)
+            }
+            Backend::FileList => {
+                // In case of FileList, drive is the list file, `-` for stdin
+                Self::from_file_list(drive, matchers, excludes, options, filter)
+            }
+            Backend::Auto(_) => Self::new(
+                drive, matchers, excludes, options, min_size, filter, backend, progress,
+            ),
         }
     }
 
-    pub fn from_wiztree_csv(
-        csv_path: &str,
-        matcher: Option<&str>,
+    /// Reuse the path map saved in `cache` by a previous run, patched with
+    /// the journal changes made since, and save the updated map back. Falls
+    /// back to a full enumeration when the cache is missing or the journal
+    /// no longer covers its cursor.
+    pub fn from_cache_with_delta(
+        drive: &str,
+        cache: &Path,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
         options: glob::MatchOptions,
+        filter: EntryFilter,
+        progress: &ProgressReporter,
     ) -> Result<Self> {
-        let pattern = matcher.map(|m| glob::Pattern::new(m).context(crate::error::GlobSnafu));
-        let pattern = match pattern {
-            Some(Ok(p)) => Some(p),
-            Some(Err(e)) => return Err(e),
-            None => None,
+        let (volume_name, subdir) = split_scan_root(drive);
+        let volume = Volume::open(&(String::from(r"\\.\") + &volume_name))
+            .context(crate::error::VolumeOpenSnafu { drive })?;
+        let journal = volume
+            .query_usn_journal()
+            .map_err(|e| usn_journal_query_error(&volume_name, e))?;
+
+        let cached = match UsnCache::load(cache) {
+            Ok(cached)
+                if cached.volume.eq_ignore_ascii_case(&volume_name)
+                    && cached.journal_id == journal.UsnJournalID
+                    && cached.next_usn >= journal.LowestValidUsn =>
+            {
+                Some(cached)
+            }
+            Ok(_) => {
+                log::warn!("[USN] Cache is stale or the journal was reset, doing a full scan");
+                None
+            }
+            Err(e) => {
+                log::info!("[USN] No usable cache at {}: {}", cache.display(), e);
+                None
+            }
+        };
+
+        let map = match cached {
+            Some(cached) => {
+                let range = UsnRange {
+                    low: cached.next_usn,
+                    high: journal.NextUsn,
+                };
+                let mut map = cached.into_map();
+                let changes = volume.usn_journal_records(journal.UsnJournalID, &range);
+                let applied = apply_delta(&mut map, changes);
+                log::info!("[USN] Applied {} journal changes to the cache", applied);
+                map
+            }
+            None => {
+                let range = UsnRange {
+                    low: journal.LowestValidUsn,
+                    high: journal.NextUsn,
+                };
+                usn_records_to_hash_map(
+                    volume
+                        .usn_records(&range)
+                        .take_while(|_| !progress.is_cancelled()),
+                )
+            }
         };
 
+        // An interrupted enumeration is incomplete, don't let it stand in
+        // for the whole volume next time
+        if !progress.is_cancelled() {
+            let updated = UsnCache::new(&volume_name, journal.UsnJournalID, journal.NextUsn, &map);
+            if let Err(e) = updated.save(cache) {
+                log::warn!("[USN] Failed to save cache to {}: {}", cache.display(), e);
+            }
+        }
+
+        Ok(Self::from_usn_map(
+            &volume_name,
+            subdir.as_deref(),
+            &map,
+            matchers,
+            excludes,
+            options,
+            filter,
+            progress,
+        ))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_usn_map(
+        volume_name: &str,
+        subdir: Option<&str>,
+        map: &HashMap<u64, UsnRecord>,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
+        options: glob::MatchOptions,
+        filter: EntryFilter,
+        progress: &ProgressReporter,
+    ) -> Self {
+        let resolved = hash_map_to_paths(map);
+        let orphans = resolved.orphans.len();
+        if orphans > 0 {
+            log::warn!(
+                "{} files have a parent missing from the USN journal and were skipped, the listing is incomplete",
+                orphans
+            );
+            for orphan in resolved.orphans.iter().take(10) {
+                log::debug!("Orphaned record: {}", orphan.display());
+            }
+        }
+        let mut paths = resolved.paths;
+
+        // Narrow down to the requested subtree before stat'ing anything
+        let root = Path::new(subdir.unwrap_or(""));
+        if subdir.is_some() {
+            paths.retain(|p| is_within(p, root));
+        }
+        if filter.max_depth.is_some() {
+            paths.retain(|p| filter.within_depth(p, root));
+        }
+
+        // Enumerated USN records carry no file size, so unlike with
+        // `Backend::Mft` the sizes have to come from the file system
+        log::info!("Processing {} paths from USN journal", paths.len());
+        let scan_root = Path::new(volume_name).join(root);
+        let full_paths: Vec<PathBuf> = paths
+            .par_iter()
+            .map(|p| Path::new(volume_name).join(p))
+            .filter(|full_path| {
+                is_included(full_path, &scan_root, matchers, options, filter)
+                    && !is_excluded(full_path, &scan_root, excludes, options, filter)
+            })
+            .collect();
+
+        let errors = FileErrors::default();
+        let progress = progress.counter(full_paths.len() as u64);
+        let entries = stat_files_batched(full_paths, filter, &errors, &progress);
+        progress.finish();
+
+        DirList {
+            entries,
+            orphans,
+            errors,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_mft_records(
+        volume_name: &str,
+        subdir: Option<&str>,
+        records: Vec<MftRecord>,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
+        options: glob::MatchOptions,
+        filter: EntryFilter,
+        progress: &ProgressReporter,
+    ) -> Self {
+        let resolved = resolve_files(records);
+        if resolved.orphans > 0 {
+            log::warn!(
+                "{} files have a parent missing from the MFT and were skipped, the listing is incomplete",
+                resolved.orphans
+            );
+        }
+
+        let root = Path::new(subdir.unwrap_or(""));
+        let scan_root = Path::new(volume_name).join(root);
+        log::info!("Processing {} files from the MFT", resolved.files.len());
+        let (sized, to_stat): (Vec<MftFile>, Vec<MftFile>) = resolved
+            .files
+            .into_par_iter()
+            .filter(|file| {
+                (subdir.is_none() || is_within(&file.path, root))
+                    && filter.within_depth(&file.path, root)
+            })
+            .map(|file| MftFile {
+                path: Path::new(volume_name).join(&file.path),
+                ..file
+            })
+            .filter(|file| {
+                is_included(&file.path, &scan_root, matchers, options, filter)
+                    && !is_excluded(&file.path, &scan_root, excludes, options, filter)
+            })
+            // Reparse points are told apart by stat_file, which keeps all but
+            // links, and files whose data attribute is in an extension record
+            // have no size yet
+            .partition(|file| file.size.is_some() && !is_reparse_attribute(file.attributes));
+
+        let mut entries: Vec<FileEntry> = sized
+            .into_iter()
+            .filter(|file| filter.keeps(file.attributes))
+            .filter_map(|file| {
+                Some(FileEntry {
+                    size: file.size?,
+                    mtime: file.mtime,
+                    attributes: file.attributes,
+                    path: file.path,
+                })
+            })
+            .collect();
+
+        let errors = FileErrors::default();
+        let progress = progress.counter(to_stat.len() as u64);
+        let paths = to_stat.into_iter().map(|file| file.path).collect();
+        entries.extend(stat_files_batched(paths, filter, &errors, &progress));
+        progress.finish();
+
+        DirList {
+            entries,
+            orphans: resolved.orphans,
+            errors,
+        }
+    }
+
+    pub fn from_walkdir(
+        root: &str,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
+        options: glob::MatchOptions,
+        filter: EntryFilter,
+    ) -> Result<Self> {
+        let errors = FileErrors::default();
+        // Unfollowed symlinks and junctions come back as symlinks, which the
+        // file filter below drops along with anything beneath them
+        let mut walker = walkdir::WalkDir::new(root).follow_links(filter.follow_reparse);
+        if let Some(max_depth) = filter.max_depth {
+            // Files directly in the root are at walkdir depth 1
+            walker = walker.max_depth(max_depth + 1);
+        }
+        let entries = walker
+            .into_iter()
+            // Don't even descend into system directories
+            .filter_entry(|entry| filter.include_system_paths || !is_system_path(entry.path()))
+            .filter_map(|entry| match entry {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    match e.path().map(Path::to_path_buf) {
+                        Some(path) => errors.record(&path, e.into()),
+                        None => log::debug!("[WalkDir] Skipping entry: {}", e),
+                    }
+                    None
+                }
+            })
+            .filter(|entry| entry.file_type().is_file())
+            .filter(|entry| {
+                is_included(entry.path(), Path::new(root), matchers, options, filter)
+                    && !is_excluded(entry.path(), Path::new(root), excludes, options, filter)
+            })
+            .filter_map(|entry| match entry.metadata() {
+                Ok(metadata)
+                    if filter.exclude_hidden && is_hidden_attribute(metadata.file_attributes()) =>
+                {
+                    None
+                }
+                Ok(metadata) => Some(FileEntry::from_metadata(entry.into_path(), &metadata)),
+                Err(e) => {
+                    errors.record(entry.path(), e.into());
+                    None
+                }
+            })
+            .collect();
+
+        Ok(DirList {
+            entries,
+            orphans: 0,
+            errors,
+        })
+    }
+
+    /// Stat exactly the files named in a newline separated list, read from
+    /// `list_path` or from stdin when it is `-`
+    pub fn from_file_list(
+        list_path: &str,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
+        options: glob::MatchOptions,
+        filter: EntryFilter,
+    ) -> Result<Self> {
+        if list_path == "-" {
+            let stdin = std::io::stdin();
+            return Self::read_file_list(stdin.lock(), matchers, excludes, options, filter);
+        }
+        let file = std::fs::File::open(list_path).context(crate::error::VolumeOpenSnafu {
+            drive: list_path.to_string(),
+        })?;
+        Self::read_file_list(
+            std::io::BufReader::new(file),
+            matchers,
+            excludes,
+            options,
+            filter,
+        )
+    }
+
+    fn read_file_list<R: std::io::BufRead>(
+        reader: R,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
+        options: glob::MatchOptions,
+        filter: EntryFilter,
+    ) -> Result<Self> {
+        let mut paths = Vec::new();
+        let mut seen = HashSet::new();
+        let mut blank = 0;
+        for line in reader.lines() {
+            let line = line.context(crate::error::IoSnafu)?;
+            if line.trim().is_empty() {
+                blank += 1;
+                continue;
+            }
+            // Listing a file twice would report it as its own duplicate
+            let path = normalize_path(&line);
+            if seen.insert(path.clone()) {
+                paths.push(path);
+            }
+        }
+
+        let errors = FileErrors::default();
+        let missing = AtomicUsize::new(0);
+        let entries: Vec<FileEntry> = paths
+            .into_par_iter()
+            .filter(|path| {
+                is_included(path, Path::new(""), matchers, options, filter)
+                    && !is_excluded(path, Path::new(""), excludes, options, filter)
+            })
+            .filter_map(|path| match stat_file(&path, filter) {
+                Ok(entry) => entry,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    missing.fetch_add(1, Ordering::Relaxed);
+                    None
+                }
+                Err(e) => {
+                    errors.record(&path, e);
+                    None
+                }
+            })
+            .collect();
+
+        log::debug!("[FileList] Skipped {} blank lines", blank);
+        let missing = missing.into_inner();
+        if missing > 0 {
+            log::warn!("[FileList] Skipped {} paths that do not exist", missing);
+        }
+
+        Ok(DirList {
+            entries,
+            orphans: 0,
+            errors,
+        })
+    }
+
+    pub fn from_wiztree_csv(
+        csv_path: &str,
+        matchers: &[glob::Pattern],
+        excludes: &[glob::Pattern],
+        options: glob::MatchOptions,
+        filter: EntryFilter,
+    ) -> Result<Self> {
         let file = std::fs::File::open(csv_path).context(crate::error::VolumeOpenSnafu {
             drive: csv_path.to_string(),
         })?;
-        let mut reader = std::io::BufReader::new(file);
-        let mut first_line = String::new();
-        use std::io::BufRead;
-        reader
-            .read_line(&mut first_line)
-            .map_err(|e| crate::error::AppError::LockPoison {
-                message: format!("Failed to read WizTree CSV header: {}", e),
-            })?;
+        // Full-drive exports run to millions of rows, so they're mapped and
+        // parsed in parallel. Mapping fails for an empty file, among others.
+        let entries = match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(mmap) => parse_wiztree_csv_parallel(
+                &mmap,
+                WIZTREE_CHUNK_SIZE,
+                matchers,
+                excludes,
+                options,
+                filter,
+            )?,
+            Err(e) => {
+                log::debug!("Parsing {} sequentially, mmap failed: {}", csv_path, e);
+                parse_wiztree_csv(
+                    std::io::BufReader::new(file),
+                    matchers,
+                    excludes,
+                    options,
+                    filter,
+                )?
+            }
+        };
+
+        Ok(DirList {
+            entries,
+            orphans: 0,
+            errors: FileErrors::default(),
+        })
+    }
 
-        // We use the remaining reader with simd-csv
-        let mut csv_reader = simd_csv::ZeroCopyReaderBuilder::new()
-            .has_headers(true)
-            .flexible(true)
-            .from_reader(reader);
+    /// Write the entries as a WizTree-style CSV that `from_wiztree_csv` and
+    /// other WizTree-compatible tools can read back
+    pub fn to_wiztree_csv(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path).context(crate::error::IoSnafu)?;
+        write_wiztree_csv(&self.entries, std::io::BufWriter::new(file))
+            .context(crate::error::IoSnafu)
+    }
 
-        let headers =
-            csv_reader
-                .byte_headers()
-                .map_err(|e| crate::error::AppError::LockPoison {
-                    message: format!("Failed to read CSV headers: {}", e),
-                })?;
+    pub fn entries(&self) -> impl Iterator<Item = &FileEntry> {
+        self.entries.iter()
+    }
 
-        let file_name_index = headers
+    /// Paths and sizes alone, for callers with no use for the rest
+    pub fn iter(&self) -> impl Iterator<Item = (&Path, u64)> {
+        self.entries
             .iter()
-            .position(|h| h == b"File Name")
-            .ok_or_else(|| crate::error::AppError::LockPoison {
-                message: "Missing 'File Name' column".to_string(),
-            })?;
-        let size_index = headers.iter().position(|h| h == b"Size").ok_or_else(|| {
-            crate::error::AppError::LockPoison {
-                message: "Missing 'Size' column".to_string(),
-            }
-        })?;
+            .map(|entry| (entry.path.as_path(), entry.size))
+    }
 
-        let mut entries = Vec::new();
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
 
-        while let Some(record) =
-            csv_reader
-                .read_byte_record()
-                .map_err(|e| crate::error::AppError::LockPoison {
-                    message: format!("CSV parsing error: {}", e),
-                })?
-        {
-            // WizTree CSV format:
-            // File Name,Size,Allocated,Modified,Attributes,Files,Folders,...
-            let path_bytes = record.unquote(file_name_index).unwrap_or_default();
-            let size_bytes = record.unquote(size_index).unwrap_or_default();
-
-            let path_str = String::from_utf8_lossy(path_bytes);
-            let size_str = String::from_utf8_lossy(size_bytes);
-
-            if let Ok(size) = size_str.trim().parse::<u64>() {
-                if size > 0 {
-                    let path = PathBuf::from(path_str.to_string());
-                    // Folders in WizTree CSV end with "\" and have size summary of children
-                    // We only want files for deduplication
-                    if !path_str.ends_with('\\') {
-                        if pattern
-                            .as_ref()
-                            .is_none_or(|pat| pat.matches_path_with(&path, options))
-                        {
-                            entries.push((path, size));
-                        }
-                    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Number of files skipped because their USN parent chain was broken
+    pub fn orphan_count(&self) -> usize {
+        self.orphans
+    }
+
+    /// Files skipped while listing because they could not be read
+    pub fn errors(&self) -> &FileErrors {
+        &self.errors
+    }
+
+    /// Drop the files last modified before `cutoff`, in seconds since the
+    /// Unix epoch, returning how many went. Most backends list modification
+    /// times along with sizes, files listed without one are stat'ed.
+    pub fn retain_modified_since(&mut self, cutoff: u64) -> usize {
+        let errors = &self.errors;
+        self.entries.par_iter_mut().for_each(|entry| {
+            if entry.mtime.is_some() {
+                return;
+            }
+            match std::fs::metadata(&entry.path) {
+                Ok(metadata) => {
+                    entry.mtime = FileEntry::from_metadata(entry.path.clone(), &metadata).mtime
                 }
+                Err(e) => errors.record(&entry.path, e),
             }
+        });
+
+        let before = self.entries.len();
+        self.entries
+            .retain(|entry| entry.mtime.is_some_and(|mtime| mtime >= cutoff));
+        before - self.entries.len()
+    }
+
+    /// Move the listing errors out, to keep them once the entries are
+    /// consumed with `into_iter` or `entries_stream`
+    pub fn take_errors(&mut self) -> FileErrors {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Hand out the entries by value in listing order, giving back the
+    /// memory of the listing as they are taken. Whatever the entries are
+    /// gathered into grows while the listing shrinks, instead of both being
+    /// held in full at once.
+    pub fn entries_stream(self) -> EntriesStream {
+        let mut entries = self.entries;
+        entries.reverse();
+        EntriesStream { entries }
+    }
+}
+
+/// Entries of a `DirList` taken one by one, see `DirList::entries_stream`
+pub struct EntriesStream {
+    /// Entries not handed out yet, last one first
+    entries: Vec<FileEntry>,
+}
+
+impl Iterator for EntriesStream {
+    type Item = FileEntry;
+
+    fn next(&mut self) -> Option<FileEntry> {
+        let entry = self.entries.pop()?;
+        // Halving at a quarter keeps the copying to a fraction of the listing
+        if self.entries.len() < self.entries.capacity() / 4 {
+            self.entries.shrink_to(self.entries.capacity() / 2);
         }
+        Some(entry)
+    }
 
-        Ok(DirList { entries })
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.entries.len(), Some(self.entries.len()))
     }
+}
 
-    pub fn iter(&self) -> impl Iterator<Item = &(PathBuf, u64)> {
-        self.entries.iter()
+impl ExactSizeIterator for EntriesStream {}
+
+/// Hands out the entries by value, so their paths can be moved on rather
+/// than cloned
+impl IntoIterator for DirList {
+    type Item = FileEntry;
+    type IntoIter = std::vec::IntoIter<FileEntry>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
     }
 }
 
@@ -211,6 +1406,663 @@ mod tests {
     use std::time::Instant;
     use walkdir;
 
+    #[test]
+    fn system_paths_are_excluded_by_default() {
+        let options = glob::MatchOptions::new();
+        let filter = EntryFilter::default();
+        for path in [
+            r"C:\System Volume Information\tracking.log",
+            r"C:\$RECYCLE.BIN\S-1-5-21\$R1.bin",
+            r"D:\pagefile.sys",
+            r"C:\windows\winsxs\amd64_x\a.dll",
+        ] {
+            assert!(
+                is_excluded(Path::new(path), Path::new(""), &[], options, filter),
+                "{}",
+                path
+            );
+        }
+        for path in [
+            r"C:\Windows\System32\a.dll",
+            r"C:\Users\me\System Volume Information.txt",
+            r"C:\data\pagefile.sys",
+        ] {
+            assert!(
+                !is_excluded(Path::new(path), Path::new(""), &[], options, filter),
+                "{}",
+                path
+            );
+        }
+
+        let filter = EntryFilter {
+            include_system_paths: true,
+            ..Default::default()
+        };
+        assert!(!is_excluded(
+            Path::new(r"C:\System Volume Information\tracking.log"),
+            Path::new(""),
+            &[],
+            options,
+            filter
+        ));
+    }
+
+    #[test]
+    fn depth_is_counted_from_the_scan_root() {
+        let cases = [
+            (r"C:\a.txt", "C:", 0),
+            (r"C:\a.txt", r"C:\", 0),
+            (r"C:\Users\me\a.txt", r"C:\Users", 1),
+            (r"C:\Users\me\docs\a.txt", r"C:\Users\", 2),
+            (r"Users\me\a.txt", "", 2),
+            (r"Users\me\a.txt", "Users", 1),
+        ];
+        for (path, root, depth) in cases {
+            assert_eq!(
+                depth_below(Path::new(path), Path::new(root)),
+                depth,
+                "{}",
+                path
+            );
+        }
+
+        let filter = EntryFilter {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        assert!(filter.within_depth(Path::new(r"D:\Photos\2020\a.jpg"), Path::new(r"D:\Photos")));
+        assert!(!filter.within_depth(
+            Path::new(r"D:\Photos\2020\May\a.jpg"),
+            Path::new(r"D:\Photos")
+        ));
+        assert!(EntryFilter::default().within_depth(Path::new(r"D:\a\b\c\d.jpg"), Path::new("D:")));
+    }
+
+    #[test]
+    fn literal_separator_keeps_wildcards_within_a_directory() {
+        let txt = glob::Pattern::new("*.txt").unwrap();
+        let nested_txt = glob::Pattern::new("docs/*.txt").unwrap();
+        let top = Path::new("a.txt");
+        let nested = Path::new("docs/a.txt");
+        let deeper = Path::new("docs/old/a.txt");
+
+        let loose = build_match_options(true);
+        for path in [top, nested, deeper] {
+            assert!(is_included(
+                path,
+                Path::new(""),
+                std::slice::from_ref(&txt),
+                loose,
+                EntryFilter::default()
+            ));
+        }
+        assert!(is_included(
+            deeper,
+            Path::new(""),
+            std::slice::from_ref(&nested_txt),
+            loose,
+            EntryFilter::default()
+        ));
+
+        let strict = glob::MatchOptions {
+            require_literal_separator: true,
+            ..loose
+        };
+        assert!(is_included(
+            top,
+            Path::new(""),
+            std::slice::from_ref(&txt),
+            strict,
+            EntryFilter::default()
+        ));
+        assert!(!is_included(
+            nested,
+            Path::new(""),
+            std::slice::from_ref(&txt),
+            strict,
+            EntryFilter::default()
+        ));
+        assert!(is_included(
+            nested,
+            Path::new(""),
+            std::slice::from_ref(&nested_txt),
+            strict,
+            EntryFilter::default()
+        ));
+        assert!(!is_included(
+            deeper,
+            Path::new(""),
+            std::slice::from_ref(&nested_txt),
+            strict,
+            EntryFilter::default()
+        ));
+    }
+
+    #[test]
+    fn relative_patterns_match_below_the_scan_root() {
+        let jpg = [glob::Pattern::new("*.jpg").unwrap()];
+        let in_b = [glob::Pattern::new(r"b\*.jpg").unwrap()];
+        let absolute = [glob::Pattern::new(r"C:\a\**\*.jpg").unwrap()];
+        let path = Path::new(r"C:\a\b\c.jpg");
+        let options = build_match_options(false);
+        let filter = EntryFilter::default();
+
+        for root in ["", "C:", r"C:\", r"C:\a", r"c:\A\b"] {
+            let root = Path::new(root);
+            assert!(is_included(path, root, &jpg, options, filter), "{:?}", root);
+            assert!(is_included(path, root, &absolute, options, filter));
+        }
+        assert!(is_included(
+            path,
+            Path::new(r"C:\a"),
+            &in_b,
+            options,
+            filter
+        ));
+        assert!(!is_included(
+            path,
+            Path::new(r"C:\a\b"),
+            &in_b,
+            options,
+            filter
+        ));
+
+        let strict = glob::MatchOptions {
+            require_literal_separator: true,
+            ..options
+        };
+        assert!(is_included(
+            path,
+            Path::new(r"C:\a\b"),
+            &jpg,
+            strict,
+            filter
+        ));
+        assert!(!is_included(path, Path::new(r"C:\a"), &jpg, strict, filter));
+        assert!(is_included(path, Path::new(r"C:\a"), &in_b, strict, filter));
+
+        let full_path = EntryFilter {
+            match_full_path: true,
+            ..filter
+        };
+        assert!(!is_included(
+            path,
+            Path::new(r"C:\a"),
+            &in_b,
+            options,
+            full_path
+        ));
+        assert!(is_included(
+            path,
+            Path::new(r"C:\a"),
+            &absolute,
+            options,
+            full_path
+        ));
+        assert!(is_excluded(
+            path,
+            Path::new(r"C:\a"),
+            &in_b,
+            options,
+            filter
+        ));
+    }
+
+    #[test]
+    fn scan_root_is_stripped_case_insensitively() {
+        let path = Path::new(r"C:\Users\me\a.txt");
+        assert_eq!(
+            relative_to_root(path, Path::new("C:")),
+            Path::new(r"Users\me\a.txt")
+        );
+        assert_eq!(
+            relative_to_root(path, Path::new(r"c:\users")),
+            Path::new(r"me\a.txt")
+        );
+        assert_eq!(relative_to_root(path, Path::new(r"D:\Users")), path);
+        assert_eq!(relative_to_root(path, Path::new("")), path);
+    }
+
+    #[test]
+    fn literal_leading_dot_hides_dotfiles_from_wildcards() {
+        let all = [glob::Pattern::new("*").unwrap()];
+        let dotfile = Path::new(".gitignore");
+
+        let options = build_match_options(false);
+        assert!(is_included(
+            dotfile,
+            Path::new(""),
+            &all,
+            options,
+            EntryFilter::default()
+        ));
+        let options = glob::MatchOptions {
+            require_literal_leading_dot: true,
+            ..options
+        };
+        assert!(!is_included(
+            dotfile,
+            Path::new(""),
+            &all,
+            options,
+            EntryFilter::default()
+        ));
+        assert!(is_included(
+            dotfile,
+            Path::new(""),
+            &[glob::Pattern::new(".*").unwrap()],
+            options,
+            EntryFilter::default()
+        ));
+    }
+
+    /// Spread `count` files of distinct sizes over directories of `per_dir`
+    fn synthetic_tree(root: &Path, count: usize, per_dir: usize) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| {
+                let dir = root.join(format!("dir{}", i / per_dir));
+                std::fs::create_dir_all(&dir).unwrap();
+                let path = dir.join(format!("file{}.bin", i));
+                std::fs::write(&path, vec![0u8; i % 97]).unwrap();
+                path
+            })
+            .collect()
+    }
+
+    fn stat_one_by_one(paths: &[PathBuf], filter: EntryFilter) -> Vec<FileEntry> {
+        paths
+            .par_iter()
+            .filter_map(|path| stat_file(path, filter).ok()?)
+            .collect()
+    }
+
+    #[test]
+    fn batched_stat_matches_per_file_stat() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = synthetic_tree(dir.path(), 50, 10);
+        // A lone file, a directory, and a path that no longer exists
+        std::fs::write(dir.path().join("lone.bin"), b"lone").unwrap();
+        paths.push(dir.path().join("lone.bin"));
+        paths.push(dir.path().join("dir0"));
+        paths.push(dir.path().join("dir1").join("deleted.bin"));
+
+        let errors = FileErrors::default();
+        let progress = ProgressReporter::hidden().counter(paths.len() as u64);
+        let batched = stat_files_batched(paths.clone(), EntryFilter::default(), &errors, &progress);
+        let expected = stat_one_by_one(&paths, EntryFilter::default());
+        // Listed times may lag behind the file's own, so compare the rest
+        let sorted = |entries: Vec<FileEntry>| {
+            let mut entries: Vec<(PathBuf, u64, u32)> = entries
+                .into_iter()
+                .map(|entry| (entry.path, entry.size, entry.attributes))
+                .collect();
+            entries.sort();
+            entries
+        };
+
+        assert_eq!(batched.len(), 51);
+        assert!(batched.iter().all(|entry| entry.mtime.is_some()));
+        assert!(batched
+            .iter()
+            .all(|entry| !is_directory_attribute(entry.attributes)));
+        assert_eq!(sorted(batched), sorted(expected));
+        assert_eq!(errors.count(), 1);
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored`"]
+    fn batched_stat_outpaces_per_file_stat() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = synthetic_tree(dir.path(), 10_000, 100);
+        let filter = EntryFilter::default();
+
+        // Best of a few runs, so a busy machine doesn't decide the result
+        let fastest = |stat: &dyn Fn() -> usize| {
+            (0..3)
+                .map(|_| {
+                    let instant = Instant::now();
+                    assert_eq!(stat(), paths.len());
+                    instant.elapsed()
+                })
+                .min()
+                .unwrap()
+        };
+        let batched = fastest(&|| {
+            let progress = ProgressReporter::hidden().counter(paths.len() as u64);
+            stat_files_batched(paths.clone(), filter, &FileErrors::default(), &progress).len()
+        });
+        let per_file = fastest(&|| stat_one_by_one(&paths, filter).len());
+        assert!(
+            batched < per_file,
+            "batched took {:?}, per file {:?}",
+            batched,
+            per_file
+        );
+    }
+
+    #[test]
+    fn auto_picks_the_first_backend_that_lists() {
+        let probe = |available: &[Backend]| {
+            let mut tried = Vec::new();
+            let chosen = first_available(&Backend::auto().candidates(), |backend| {
+                tried.push(backend.clone());
+                if available.contains(backend) {
+                    Ok(())
+                } else {
+                    Err(AppError::Other {
+                        message: format!("{:?} is not available", backend),
+                    })
+                }
+            })
+            .map(|(backend, ())| backend);
+            (chosen, tried)
+        };
+
+        let everything = Backend::Everything(None);
+        let (chosen, tried) = probe(&[everything.clone(), Backend::USN]);
+        assert_eq!(chosen.unwrap(), everything);
+        assert_eq!(tried, std::slice::from_ref(&everything));
+
+        // Everything isn't running and the journal needs elevation
+        let (chosen, tried) = probe(&[Backend::WalkDir]);
+        assert_eq!(chosen.unwrap(), Backend::WalkDir);
+        assert_eq!(tried, Backend::auto().candidates());
+
+        let (chosen, _) = probe(&[]);
+        let message = chosen.unwrap_err().to_string();
+        assert!(message.contains("WalkDir is not available"), "{}", message);
+
+        // The order can be overridden, and explicit backends keep theirs
+        let order = Backend::Auto(vec![Backend::WalkDir, Backend::USN]);
+        assert_eq!(order.candidates(), [Backend::WalkDir, Backend::USN]);
+        assert_eq!(Backend::Mft.candidates(), [Backend::Mft, Backend::USN]);
+        let named = Backend::Everything(Some("portable".to_string()));
+        assert_eq!(named.candidates(), std::slice::from_ref(&named));
+        assert_eq!(Backend::WizTree.candidates(), [Backend::WizTree]);
+    }
+
+    #[test]
+    fn only_links_are_dropped_of_the_reparse_points() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("placeholder.bin");
+        std::fs::write(&file, b"data").unwrap();
+        let junction = dir.path().join("loop");
+        let status = std::process::Command::new("cmd")
+            .arg("/C")
+            .arg("mklink")
+            .arg("/J")
+            .arg(&junction)
+            .arg(dir.path())
+            .status()
+            .unwrap();
+        assert!(status.success(), "mklink /J failed");
+
+        // A reparse point that isn't a link, such as a dedup placeholder,
+        // is listed by its attributes like any other
+        let reparse = 0x420; // FILE_ATTRIBUTE_REPARSE_POINT | FILE_ATTRIBUTE_ARCHIVE
+        let filter = EntryFilter::default();
+        assert!(filter.keeps(reparse));
+        assert!(filter.keeps_reparse(&file, reparse));
+        assert!(!filter.keeps_reparse(&junction, reparse));
+
+        let filter = EntryFilter {
+            follow_reparse: true,
+            ..Default::default()
+        };
+        assert!(filter.keeps_reparse(&junction, reparse));
+    }
+
+    fn parse_entries(csv: &str) -> Result<Vec<FileEntry>> {
+        parse_wiztree_csv(
+            csv.as_bytes(),
+            &[],
+            &[],
+            glob::MatchOptions::new(),
+            EntryFilter::default(),
+        )
+    }
+
+    fn parse(csv: &str) -> Result<Vec<(PathBuf, u64)>> {
+        Ok(parse_entries(csv)?
+            .into_iter()
+            .map(|entry| (entry.path, entry.size))
+            .collect())
+    }
+
+    #[test]
+    fn wiztree_csv_with_banner_line() {
+        let csv = "Generated by WizTree 4.21 16/10/2026 09:12:44 (Supporter Edition)\r\n\
+File Name,Size,Allocated,Modified,Attributes,Files,Folders\r\n\
+\"C:\\\",300,8192,2026/10/16 09:00:00,22,2,1\r\n\
+\"C:\\Data\\\",200,4096,2026/10/16 09:00:00,16,1,0\r\n\
+\"C:\\Data\\a.bin\",200,4096,2026/10/16 09:00:00,32,0,0\r\n\
+\"C:\\b.bin\",100,4096,2026/10/16 09:00:00,32,0,0\r\n";
+
+        assert_eq!(
+            parse(csv).unwrap(),
+            [
+                (PathBuf::from(r"C:\Data\a.bin"), 200),
+                (PathBuf::from(r"C:\b.bin"), 100)
+            ]
+        );
+    }
+
+    #[test]
+    fn wiztree_csv_without_banner_and_reordered_columns() {
+        let csv = "\u{feff}\"size\",\"path\",\"modified\"\n\
+100,\"D:\\x.bin\",2026/10/16\n\
+0,\"D:\\empty.bin\",2026/10/16\n";
+
+        assert_eq!(parse(csv).unwrap(), [(PathBuf::from(r"D:\x.bin"), 100)]);
+    }
+
+    #[test]
+    fn wiztree_directory_attribute_skips_row_without_backslash() {
+        let csv = "Generated by WizTree\n\
+File Name,Size,Attributes\n\
+\"E:\\Projects\",500,16\n\
+\"E:\\Archive\",300,0x30\n\
+\"E:\\Projects\\main.rs\",500,0x20\n";
+
+        assert_eq!(
+            parse(csv).unwrap(),
+            [(PathBuf::from(r"E:\Projects\main.rs"), 500)]
+        );
+        assert_eq!(parse_attributes(" 0x10 "), Some(16));
+        assert_eq!(parse_attributes("bogus"), None);
+    }
+
+    #[test]
+    fn wiztree_rows_keep_their_attributes() {
+        let csv = "File Name,Size,Attributes\n\
+\"F:\\hidden.bin\",100,0x22\n\
+\"F:\\plain.bin\",200,\n";
+
+        assert_eq!(
+            parse_entries(csv).unwrap(),
+            [
+                FileEntry {
+                    attributes: 0x22,
+                    ..FileEntry::new(PathBuf::from(r"F:\hidden.bin"), 100)
+                },
+                FileEntry::new(PathBuf::from(r"F:\plain.bin"), 200),
+            ]
+        );
+    }
+
+    #[test]
+    fn wiztree_csv_missing_column_lists_headers() {
+        let err = parse("Generated by WizTree\nName,Allocated\n").unwrap_err();
+        let message = err.to_string();
+        assert!(message.contains("no size column"), "{}", message);
+        assert!(message.contains("found: Name, Allocated"), "{}", message);
+    }
+
+    #[test]
+    fn parallel_wiztree_parsing_matches_sequential() {
+        let mut csv = String::from(
+            "Generated by WizTree 4.21\r\nFile Name,Size,Allocated,Modified,Attributes\r\n",
+        );
+        for i in 0..200 {
+            let row = match i % 5 {
+                0 => format!("\"G:\\Dir {}\\\",{},0,2026/10/16,16\r\n", i, i * 10),
+                1 => format!("\"G:\\a, b\\{}.bin\",{},0,2026/10/16,32\r\n", i, i),
+                2 => format!("\"G:\\two\r\nlines {}.bin\",{},0,2026/10/16,32\r\n", i, i),
+                3 => format!(
+                    "\"G:\\\"\"quoted\"\" {}.bin\",{},0,2026/10/16,0x22\r\n",
+                    i, i
+                ),
+                _ => format!("\"G:\\empty {}.bin\",0,0,2026/10/16,32\r\n", i),
+            };
+            csv.push_str(&row);
+        }
+
+        let sequential = parse_entries(&csv).unwrap();
+        assert_eq!(sequential.len(), 120);
+        for chunk_size in [1, 64, 1000, csv.len()] {
+            let parallel = parse_wiztree_csv_parallel(
+                csv.as_bytes(),
+                chunk_size,
+                &[],
+                &[],
+                glob::MatchOptions::new(),
+                EntryFilter::default(),
+            )
+            .unwrap();
+            assert_eq!(parallel, sequential, "chunk size {}", chunk_size);
+        }
+
+        // Every chunk ends right after a record, never inside a quoted path
+        let body = csv.split_once("Attributes\r\n").unwrap().1;
+        let chunks = split_csv_records(body.as_bytes(), 64);
+        assert!(chunks.len() > 100);
+        for chunk in chunks {
+            assert!(chunk.starts_with(b"\"G:\\"), "{:?}", chunk);
+            assert!(chunk.ends_with(b"\r\n"));
+        }
+    }
+
+    #[test]
+    fn entries_stream_gives_the_listing_memory_back() {
+        let entries: Vec<FileEntry> = (0..1000)
+            .map(|i| FileEntry::new(PathBuf::from(format!(r"C:\files\{}.bin", i)), i))
+            .collect();
+        let dirlist = DirList {
+            entries: entries.clone(),
+            orphans: 0,
+            errors: FileErrors::default(),
+        };
+
+        let mut stream = dirlist.entries_stream();
+        assert_eq!(stream.len(), 1000);
+        let mut taken = Vec::new();
+        while let Some(entry) = stream.next() {
+            taken.push(entry);
+            // Never more than a few times what's still to be handed out
+            assert!(
+                stream.entries.capacity() <= 4 * (stream.len() + 1),
+                "{} entries held for {} left",
+                stream.entries.capacity(),
+                stream.len()
+            );
+        }
+        assert_eq!(taken, entries);
+    }
+
+    #[test]
+    fn wiztree_csv_round_trips() {
+        let dirlist = DirList {
+            entries: vec![
+                FileEntry::new(PathBuf::from(r"C:\plain\a.bin"), 10),
+                FileEntry::new(PathBuf::from(r"C:\one, two\b.bin"), 2048),
+            ],
+            orphans: 0,
+            errors: FileErrors::default(),
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let csv = dir.path().join("listing.csv");
+        dirlist.to_wiztree_csv(&csv).unwrap();
+
+        let options = glob::MatchOptions::new();
+        let read = DirList::from_wiztree_csv(
+            csv.to_str().unwrap(),
+            &[],
+            &[],
+            options,
+            EntryFilter::default(),
+        )
+        .unwrap();
+        let read: Vec<FileEntry> = read.into_iter().collect();
+        assert_eq!(read, dirlist.entries);
+    }
+
+    #[test]
+    fn everything_query_combines_matchers() {
+        let jpg = glob::Pattern::new("**.jpg").unwrap();
+        let png = glob::Pattern::new("**.png").unwrap();
+
+        assert_eq!(everything_query("C:", &[], 0), r"C:\");
+        assert_eq!(
+            everything_query(r"D:\", std::slice::from_ref(&jpg), 0),
+            r#"D:\ "**.jpg""#
+        );
+        assert_eq!(
+            everything_query("C:", &[jpg, png], 0),
+            r#"C:\ <"**.jpg"|"**.png">"#
+        );
+    }
+
+    #[test]
+    fn everything_query_anchors_relative_folders_at_the_root() {
+        let nested = glob::Pattern::new(r"Photos\*.jpg").unwrap();
+        let absolute = glob::Pattern::new(r"D:\Photos\*.jpg").unwrap();
+
+        assert_eq!(
+            everything_query("D:", &[nested, absolute], 0),
+            r#"D:\ <"D:\Photos\*.jpg"|"D:\Photos\*.jpg">"#
+        );
+    }
+
+    #[test]
+    fn everything_query_appends_size_filter() {
+        let jpg = glob::Pattern::new("**.jpg").unwrap();
+        let png = glob::Pattern::new("**.png").unwrap();
+
+        assert_eq!(everything_query("C:", &[], 1), r"C:\ size:>=1");
+        assert_eq!(
+            everything_query("C:", std::slice::from_ref(&jpg), 1048576),
+            r#"C:\ "**.jpg" size:>=1048576"#
+        );
+        assert_eq!(
+            everything_query(r"D:\Photos", &[jpg, png], 4096),
+            r#"D:\Photos\ <"**.jpg"|"**.png"> size:>=4096"#
+        );
+    }
+
+    #[test]
+    fn scan_root_restricts_to_subtree() {
+        let (volume, subdir) = split_scan_root(r"C:/Users/foo\");
+        assert_eq!(volume, "C:");
+        let subdir = subdir.unwrap();
+        assert_eq!(subdir, r"Users\foo");
+
+        let paths = [
+            PathBuf::from(r"Users\foo\a.txt"),
+            PathBuf::from(r"users\FOO\nested\b.txt"),
+            PathBuf::from(r"Users\foobar\c.txt"),
+            PathBuf::from(r"Windows\d.txt"),
+        ];
+        let kept: Vec<_> = paths
+            .iter()
+            .filter(|p| is_within(p, Path::new(&subdir)))
+            .collect();
+        assert_eq!(kept, [&paths[0], &paths[1]]);
+
+        assert_eq!(split_scan_root("C:"), ("C:".to_string(), None));
+        assert_eq!(split_scan_root(r"C:\"), ("C:".to_string(), None));
+    }
+
     #[test]
     fn compare_walkdir_to_dirlist() {
         println!("What is this\r\n");
@@ -236,7 +2088,17 @@ mod tests {
             require_literal_leading_dot: false,
             require_literal_separator: false,
         };
-        let dirlist = DirList::new("C:", None, options, Backend::USN).unwrap();
+        let dirlist = DirList::new(
+            "C:",
+            &[],
+            &[],
+            options,
+            0,
+            EntryFilter::default(),
+            Backend::USN,
+            &ProgressReporter::hidden(),
+        )
+        .unwrap();
         for (p, _) in dirlist.iter() {
             v2.push(String::from(p.to_str().unwrap()));
         }
@@ -1,4 +1,8 @@
 use snafu::Snafu;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use winapi::shared::winerror::{ERROR_JOURNAL_DELETE_IN_PROGRESS, ERROR_JOURNAL_NOT_ACTIVE};
 
 #[derive(Debug, Snafu)]
 #[snafu(visibility(pub))]
@@ -18,8 +22,24 @@ pub enum AppError {
     #[snafu(display("Failed to query USN journal: {}", source))]
     UsnJournalQuery { source: std::io::Error },
 
-    #[snafu(display("Everything search error: {}", message))]
-    Everything { message: String },
+    #[snafu(display(
+        "The USN journal on {} is not active ({}), enable it with `fsutil usn createjournal m=104857600 a=1048576 {}` or run again with --create-journal",
+        volume,
+        source,
+        volume
+    ))]
+    UsnJournalInactive {
+        volume: String,
+        source: std::io::Error,
+    },
+
+    /// `code` is from `Everything3_GetLastError`
+    #[snafu(display(
+        "Everything search error: {} ({})",
+        message,
+        crate::everything::describe_error(*code)
+    ))]
+    Everything { message: String, code: u32 },
 
     #[snafu(display("Other error: {}", message))]
     Other { message: String },
@@ -29,3 +49,157 @@ pub enum AppError {
 }
 
 pub type Result<T> = std::result::Result<T, AppError>;
+
+/// Tell a disabled journal apart from other query failures, since that one
+/// the user can fix
+pub fn usn_journal_query_error(volume: &str, source: std::io::Error) -> AppError {
+    match source.raw_os_error().map(|code| code as u32) {
+        Some(ERROR_JOURNAL_NOT_ACTIVE) | Some(ERROR_JOURNAL_DELETE_IN_PROGRESS) => {
+            AppError::UsnJournalInactive {
+                volume: volume.to_string(),
+                source,
+            }
+        }
+        _ => AppError::UsnJournalQuery { source },
+    }
+}
+
+/// A file whose length no longer matches the size it was listed and grouped
+/// with. Carried inside an `io::Error` so `FileErrors` can count it apart
+/// from real failures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileChanged {
+    pub listed: u64,
+    pub actual: u64,
+}
+
+impl FileChanged {
+    /// Whether `error` was made from a `FileChanged`
+    pub fn is(error: &std::io::Error) -> bool {
+        error
+            .get_ref()
+            .is_some_and(|inner| inner.is::<FileChanged>())
+    }
+}
+
+impl std::fmt::Display for FileChanged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "file changed during scan, {} bytes instead of {}",
+            self.actual, self.listed
+        )
+    }
+}
+
+impl std::error::Error for FileChanged {}
+
+impl From<FileChanged> for std::io::Error {
+    fn from(changed: FileChanged) -> Self {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, changed)
+    }
+}
+
+/// How many failures are kept to show in the summary
+const ERROR_SAMPLES: usize = 10;
+
+/// Files skipped because of I/O errors. Every failure is counted, but only
+/// the first few are kept so a drive full of denied files stays cheap. Files
+/// that changed size since they were listed are counted on their own, see
+/// `FileChanged`.
+#[derive(Debug, Default)]
+pub struct FileErrors {
+    count: AtomicUsize,
+    changed: AtomicUsize,
+    samples: Mutex<Vec<(PathBuf, std::io::Error)>>,
+}
+
+impl FileErrors {
+    pub fn record(&self, path: &Path, error: std::io::Error) {
+        log::debug!("Skipping {}: {}", path.display(), error);
+        if FileChanged::is(&error) {
+            self.changed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut samples) = self.samples.lock() {
+            if samples.len() < ERROR_SAMPLES {
+                samples.push((path.to_path_buf(), error));
+            }
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Files left out because they grew or shrank after they were listed
+    pub fn changed(&self) -> usize {
+        self.changed.load(Ordering::Relaxed)
+    }
+
+    /// Warn about the skipped files, if there were any
+    pub fn log_summary(&self, context: &str) {
+        let changed = self.changed();
+        if changed > 0 {
+            log::warn!(
+                "{} files changed during the scan and were left out while {}",
+                changed,
+                context
+            );
+        }
+        let count = self.count();
+        if count == 0 {
+            return;
+        }
+        log::warn!("{} files could not be read while {}", count, context);
+        if let Ok(samples) = self.samples.lock() {
+            for (path, error) in samples.iter() {
+                log::warn!("\t{}: {}", path.display(), error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_files_are_counted_apart_from_errors() {
+        let errors = FileErrors::default();
+        let changed = FileChanged {
+            listed: 10,
+            actual: 4,
+        };
+        errors.record(Path::new(r"C:\a.bin"), changed.into());
+        errors.record(
+            Path::new(r"C:\b.bin"),
+            std::io::Error::from(std::io::ErrorKind::PermissionDenied),
+        );
+
+        assert_eq!(errors.changed(), 1);
+        assert_eq!(errors.count(), 1);
+        assert!(!FileChanged::is(&std::io::Error::other("unrelated")));
+    }
+
+    #[test]
+    fn inactive_journal_suggests_creating_it() {
+        let error = std::io::Error::from_raw_os_error(ERROR_JOURNAL_NOT_ACTIVE as i32);
+        let error = usn_journal_query_error("D:", error);
+        assert!(matches!(&error, AppError::UsnJournalInactive { volume, .. } if volume == "D:"));
+        assert!(error.to_string().contains("fsutil usn createjournal"));
+
+        let error = std::io::Error::from_raw_os_error(ERROR_JOURNAL_DELETE_IN_PROGRESS as i32);
+        assert!(matches!(
+            usn_journal_query_error("D:", error),
+            AppError::UsnJournalInactive { .. }
+        ));
+
+        let error = std::io::Error::from_raw_os_error(5);
+        assert!(matches!(
+            usn_journal_query_error("D:", error),
+            AppError::UsnJournalQuery { .. }
+        ));
+    }
+}
@@ -1,56 +1,248 @@
 use everything3_sys::*;
 use rayon::prelude::*;
+use std::borrow::Cow;
 use std::ffi::CString;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::ptr;
 use std::sync::atomic::{AtomicU64, Ordering};
+use winapi::shared::winerror::ERROR_ACCESS_DENIED;
+
+use crate::dirlist::{EntryFilter, FileEntry};
+use crate::progress::ProgressReporter;
+use crate::utils::{filetime_to_unix_seconds, is_directory_attribute, strip_volume};
+
+/// Entry for one search result, with its FILETIME modification date
+fn result_entry(path: &str, size: u64, date_modified: u64, attributes: u32) -> FileEntry {
+    FileEntry {
+        path: PathBuf::from(path),
+        size,
+        mtime: filetime_to_unix_seconds(date_modified),
+        attributes,
+    }
+}
 
 // Wrapper to allow passing raw pointers to rayon threads
 struct SendPtr<T>(*mut T);
 unsafe impl<T> Send for SendPtr<T> {}
 unsafe impl<T> Sync for SendPtr<T> {}
 
+fn search_error(context: &str, code: u32) -> crate::error::AppError {
+    crate::error::AppError::Everything {
+        message: format!("{} failed with error code {}", context, code),
+        code,
+    }
+}
+
+/// What an `Everything3_GetLastError` code means for the user
+pub fn describe_error(code: u32) -> &'static str {
+    match code {
+        EVERYTHING3_ERROR_IPC_PIPE_NOT_FOUND => "Everything is not installed or not running",
+        EVERYTHING3_ERROR_DISCONNECTED => "the connection to Everything was lost",
+        EVERYTHING3_ERROR_SHUTDOWN => "Everything is shutting down",
+        EVERYTHING3_ERROR_OUT_OF_MEMORY => "out of memory",
+        EVERYTHING3_ERROR_BAD_REQUEST
+        | EVERYTHING3_ERROR_INVALID_COMMAND
+        | EVERYTHING3_ERROR_BAD_RESPONSE
+        | EVERYTHING3_ERROR_PROPERTY_NOT_FOUND => {
+            "the running Everything is too old, version 1.5 or later is required"
+        }
+        ERROR_ACCESS_DENIED => {
+            "access denied, Everything may be running elevated or as another user"
+        }
+        _ => "unknown error",
+    }
+}
+
+/// Errors that may go away by reconnecting, rather than falling back
+pub fn is_transient_error(code: u32) -> bool {
+    code == EVERYTHING3_ERROR_DISCONNECTED
+}
+
+/// Size of the stack buffer tried first when reading result text
+const TEXT_BUFFER_SIZE: usize = 4096;
+
+/// Read text through one of the Everything3 getters that copy into a caller
+/// buffer. Those truncate silently, so when the text fills the stack buffer
+/// the required length is queried and the read repeated on the heap. Returns
+/// `None` when there is no text.
+fn read_utf8(mut read: impl FnMut(*mut u8, u64) -> u64) -> Option<Vec<u8>> {
+    let mut buffer = [0u8; TEXT_BUFFER_SIZE];
+    let len = read(buffer.as_mut_ptr(), buffer.len() as u64) as usize;
+    if len == 0 {
+        return None;
+    }
+    if len + 1 < buffer.len() {
+        return Some(buffer[..len].to_vec());
+    }
+
+    // Possibly cut off, a null buffer makes the getter report the full size
+    let required = read(ptr::null_mut(), 0) as usize;
+    let mut buffer = vec![0u8; required.max(len) + 1];
+    let len = read(buffer.as_mut_ptr(), buffer.len() as u64) as usize;
+    buffer.truncate(len.min(buffer.len()));
+    Some(buffer)
+}
+
+/// Hardlink names are reported without a drive letter or share, so strip
+/// it and fold case and separators to compare paths the way NTFS does
+fn normalize_link_name(path: &str) -> String {
+    strip_volume(&path.replace('/', "\\")).to_lowercase()
+}
+
+/// Only one name of a set of hardlinks should be kept. Every link sees the
+/// same name list, so electing the smallest normalized name gives the same
+/// leader no matter which thread asks.
+fn is_hardlink_leader(current: &str, names: &str) -> bool {
+    let current = normalize_link_name(current);
+    names
+        .split(';')
+        .filter(|name| !name.is_empty())
+        .map(normalize_link_name)
+        .all(|name| current <= name)
+}
+
+/// Counters for how the results of a search were handled
+#[derive(Default)]
+struct ResultStats {
+    skipped_dirs: AtomicU64,
+    skipped_filtered: AtomicU64,
+    zero_len_paths: AtomicU64,
+    skipped_hardlinks: AtomicU64,
+    added_files: AtomicU64,
+    lossy_paths: AtomicU64,
+}
+
+impl ResultStats {
+    /// Decode result text, replacing invalid UTF-8 rather than losing the
+    /// file. Such paths can't be opened as-is, but still show up in reports.
+    fn decode<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        let text = String::from_utf8_lossy(bytes);
+        if let Cow::Owned(_) = text {
+            self.lossy_paths.fetch_add(1, Ordering::Relaxed);
+            log::debug!("[Everything] Result is not valid UTF-8: {}", text);
+        }
+        text
+    }
+
+    /// Summarize a search in a single line, only shown with --verbose
+    fn log(&self, count: u64) {
+        for (level, line) in self.summary(count) {
+            log::log!(level, "{}", line);
+        }
+    }
+
+    /// Lines `log` writes for a search of `count` results, with their level
+    fn summary(&self, count: u64) -> Vec<(log::Level, String)> {
+        let mut lines = vec![(
+            log::Level::Debug,
+            format!(
+                "[Everything] Processed {} results - {} dirs skipped, {} filtered by attributes, {} zero-length paths, {} hardlinks skipped, {} files added",
+                count,
+                self.skipped_dirs.load(Ordering::Relaxed),
+                self.skipped_filtered.load(Ordering::Relaxed),
+                self.zero_len_paths.load(Ordering::Relaxed),
+                self.skipped_hardlinks.load(Ordering::Relaxed),
+                self.added_files.load(Ordering::Relaxed)
+            ),
+        )];
+
+        let lossy = self.lossy_paths.load(Ordering::Relaxed);
+        if lossy > 0 {
+            lines.push((
+                log::Level::Warn,
+                format!(
+                    "[Everything] {} paths were not valid UTF-8 and had characters replaced",
+                    lossy
+                ),
+            ));
+        }
+        lines
+    }
+}
+
 pub struct EverythingSearch {
     client: *mut EVERYTHING3_CLIENT,
 }
 
+/// `text` as a C string, or an error naming `what` it is if it holds a NUL,
+/// which Everything would otherwise never see past
+fn c_string(text: &str, what: &str) -> crate::error::Result<CString> {
+    CString::new(text).map_err(|_| crate::error::AppError::Everything {
+        message: format!(
+            "{} '{}' contains a NUL character",
+            what,
+            text.escape_debug()
+        ),
+        code: EVERYTHING3_ERROR_INVALID_PARAMETER,
+    })
+}
+
+/// Connect to the named Everything instance, or the default one for `None`.
+/// A null client means Everything refused, see `Everything3_GetLastError`.
+unsafe fn connect(instance: Option<&str>) -> crate::error::Result<*mut EVERYTHING3_CLIENT> {
+    Ok(match instance {
+        Some(name) => {
+            let name = c_string(name, "Instance name")?;
+            Everything3_ConnectUTF8(name.as_ptr() as *const u8)
+        }
+        None => Everything3_ConnectUTF8(ptr::null()),
+    })
+}
+
 impl EverythingSearch {
-    pub fn new() -> Option<Self> {
+    /// Connect to the default instance, or failing that the `1.5a` one
+    pub fn new() -> crate::error::Result<Self> {
         unsafe {
             // First try default instance
-            let mut client = Everything3_ConnectUTF8(ptr::null());
+            let mut client = connect(None)?;
             let mut instance_used = "default";
             if client.is_null() {
                 // Try 1.5a instance as fallback
-                let instance_name = CString::new("1.5a").unwrap();
-                client = Everything3_ConnectUTF8(instance_name.as_ptr() as *const u8);
+                client = connect(Some("1.5a"))?;
                 instance_used = "1.5a";
             }
 
             if client.is_null() {
-                log::error!("[Everything] Error: Could not connect to Everything service (ConnectUTF8 returned NULL for default and 1.5a instances)");
-                None
-            } else {
-                log::debug!(
-                    "[Everything] Debug: Connected to '{}' instance",
-                    instance_used
-                );
-                Some(EverythingSearch { client })
+                return Err(search_error(
+                    "Connecting to the default and 1.5a instances",
+                    Everything3_GetLastError(),
+                ));
             }
+            log::debug!("[Everything] Connected to '{}' instance", instance_used);
+            Ok(EverythingSearch { client })
         }
     }
 
+    /// Connect to a specific named instance, such as a portable install
+    /// started with `-instance <name>`
+    pub fn with_instance(name: &str) -> crate::error::Result<Self> {
+        let client = unsafe { connect(Some(name))? };
+        if client.is_null() {
+            return Err(search_error(
+                &format!("Connecting to instance '{}'", name),
+                unsafe { Everything3_GetLastError() },
+            ));
+        }
+
+        log::debug!("[Everything] Connected to '{}' instance", name);
+        Ok(EverythingSearch { client })
+    }
+
     pub fn get_all_files(
         &self,
         query_str: &str,
         case_sensitive: bool,
-    ) -> crate::error::Result<Vec<(PathBuf, u64)>> {
+        filter: EntryFilter,
+        progress: &ProgressReporter,
+    ) -> crate::error::Result<Vec<FileEntry>> {
+        let query = c_string(query_str, "Query")?;
         unsafe {
             let search_state = Everything3_CreateSearchState();
             if search_state.is_null() {
-                return Err(crate::error::AppError::Everything {
-                    message: "Failed to create search state".to_string(),
-                });
+                return Err(search_error(
+                    "Creating search state",
+                    Everything3_GetLastError(),
+                ));
             }
 
             // Request necessary properties
@@ -58,6 +250,10 @@ impl EverythingSearch {
             Everything3_AddSearchPropertyRequest(search_state, EVERYTHING3_PROPERTY_ID_PATH);
             Everything3_AddSearchPropertyRequest(search_state, EVERYTHING3_PROPERTY_ID_SIZE);
             Everything3_AddSearchPropertyRequest(search_state, EVERYTHING3_PROPERTY_ID_ATTRIBUTES);
+            Everything3_AddSearchPropertyRequest(
+                search_state,
+                EVERYTHING3_PROPERTY_ID_DATE_MODIFIED,
+            );
             Everything3_AddSearchPropertyRequest(
                 search_state,
                 EVERYTHING3_PROPERTY_ID_PATH_AND_NAME,
@@ -77,54 +273,47 @@ impl EverythingSearch {
             Everything3_SetSearchMatchCase(search_state, if case_sensitive { 1 } else { 0 });
             Everything3_SetSearchRequestTotalSize(search_state, 1);
 
-            let query = CString::new(query_str).unwrap();
             Everything3_SetSearchTextUTF8(search_state, query.as_ptr() as *const u8);
 
-            log::debug!(
-                "[Everything] Debug: Executing search with query: {}",
-                query_str
-            );
+            log::debug!("[Everything] Executing search with query: {}", query_str);
             let results = Everything3_Search(self.client, search_state);
 
             if results.is_null() {
                 let err = Everything3_GetLastError();
                 log::error!(
-                    "[Everything] Error: Search for '{}' failed with error code {}",
+                    "[Everything] Search for '{}' failed with error code {}",
                     query_str,
                     err
                 );
                 Everything3_DestroySearchState(search_state);
-                return Err(crate::error::AppError::Everything {
-                    message: format!("Search for '{}' failed with error code {}", query_str, err),
-                });
+                return Err(search_error(&format!("Search for '{}'", query_str), err));
             }
 
             let count = Everything3_GetResultListCount(results);
             if count == 0 {
-                log::debug!(
-                    "[Everything] Debug: Search for '{}' returned 0 results",
-                    query_str
-                );
+                log::debug!("[Everything] Search for '{}' returned 0 results", query_str);
             }
 
-            let skipped_dirs = AtomicU64::new(0);
-            let zero_len_paths = AtomicU64::new(0);
-            let added_files = AtomicU64::new(0);
-            let skipped_hardlinks = AtomicU64::new(0);
+            let stats = ResultStats::default();
+            let progress = progress.counter(count);
 
             // Wrap pointer for rayon
             let results_ptr = SendPtr(results);
 
-            let collected_results: Vec<(PathBuf, u64)> = (0..count)
+            let collected_results: Vec<FileEntry> = (0..count)
                 .into_par_iter()
                 .map(|i| {
                     let results = results_ptr.0;
-                    let mut buffer = [0u8; 4096]; // Thread-local buffer
+                    progress.inc(1);
 
-                    // Skip directories (FILE_ATTRIBUTE_DIRECTORY = 0x10)
+                    // Skip directories
                     let attributes = Everything3_GetResultAttributes(results, i);
-                    if (attributes & 0x00000010) != 0 {
-                        skipped_dirs.fetch_add(1, Ordering::Relaxed);
+                    if is_directory_attribute(attributes) {
+                        stats.skipped_dirs.fetch_add(1, Ordering::Relaxed);
+                        return None;
+                    }
+                    if !filter.keeps(attributes) {
+                        stats.skipped_filtered.fetch_add(1, Ordering::Relaxed);
                         return None;
                     }
 
@@ -136,94 +325,72 @@ impl EverythingSearch {
                     );
                     if hl_count > 1 {
                         // Get all hardlink names
-                        let len_hl = Everything3_GetResultPropertyTextUTF8(
-                            results,
-                            i,
-                            EVERYTHING3_PROPERTY_ID_HARD_LINK_FILE_NAMES,
-                            buffer.as_mut_ptr(),
-                            buffer.len() as u64,
-                        );
-                        if len_hl > 0 {
-                            let hl_names_str =
-                                std::str::from_utf8(&buffer[..len_hl as usize]).unwrap_or("");
-                            let mut names: Vec<&str> = hl_names_str.split(';').collect();
-
-                            let mut current_path_buffer = [0u8; 4096];
-                            let len_cur = Everything3_GetResultFullPathNameUTF8(
+                        let hl_names = read_utf8(|buf, size| {
+                            Everything3_GetResultPropertyTextUTF8(
                                 results,
                                 i,
-                                current_path_buffer.as_mut_ptr(),
-                                current_path_buffer.len() as u64,
-                            );
-                            if len_cur > 0 {
-                                let current_path_full =
-                                    std::str::from_utf8(&current_path_buffer[..len_cur as usize])
-                                        .unwrap_or("");
-                                // Strip drive letter "X:" if present
-                                let current_path_suffix = if current_path_full.len() >= 2
-                                    && current_path_full.chars().nth(1) == Some(':')
-                                {
-                                    &current_path_full[2..]
-                                } else {
-                                    current_path_full
-                                };
-
-                                names.sort();
-                                if let Some(first) = names.first() {
-                                    if *first != current_path_suffix {
-                                        // We are not the leader, skip
-                                        skipped_hardlinks.fetch_add(1, Ordering::Relaxed);
-                                        return None;
-                                    }
-                                }
+                                EVERYTHING3_PROPERTY_ID_HARD_LINK_FILE_NAMES,
+                                buf,
+                                size,
+                            )
+                        });
+                        let current_path = read_utf8(|buf, size| {
+                            Everything3_GetResultFullPathNameUTF8(results, i, buf, size)
+                        });
+                        if let (Some(hl_names), Some(current_path)) = (hl_names, current_path) {
+                            // Only the kept path below counts towards lossy stats
+                            let hl_names_str = String::from_utf8_lossy(&hl_names);
+                            let current_path_str = String::from_utf8_lossy(&current_path);
+                            if !is_hardlink_leader(&current_path_str, &hl_names_str) {
+                                // We are not the leader, skip
+                                stats.skipped_hardlinks.fetch_add(1, Ordering::Relaxed);
+                                return None;
                             }
                         }
                     }
 
-                    let len = Everything3_GetResultFullPathNameUTF8(
-                        results,
-                        i,
-                        buffer.as_mut_ptr(),
-                        buffer.len() as u64,
-                    );
-
-                    if len == 0 {
+                    let path = read_utf8(|buf, size| {
+                        Everything3_GetResultFullPathNameUTF8(results, i, buf, size)
+                    })
+                    .or_else(|| {
                         // Fallback to getting PATH_AND_NAME property directly if helper fails
-                        let len2 = Everything3_GetResultPropertyTextUTF8(
-                            results,
-                            i,
-                            EVERYTHING3_PROPERTY_ID_PATH_AND_NAME,
-                            buffer.as_mut_ptr(),
-                            buffer.len() as u64,
-                        );
-                        if len2 > 0 {
-                            let path_str =
-                                std::str::from_utf8(&buffer[..len2 as usize]).unwrap_or("");
-                            let size = Everything3_GetResultSize(results, i);
-                            added_files.fetch_add(1, Ordering::Relaxed);
-                            Some((PathBuf::from(path_str), size))
-                        } else {
-                            zero_len_paths.fetch_add(1, Ordering::Relaxed);
+                        read_utf8(|buf, size| {
+                            Everything3_GetResultPropertyTextUTF8(
+                                results,
+                                i,
+                                EVERYTHING3_PROPERTY_ID_PATH_AND_NAME,
+                                buf,
+                                size,
+                            )
+                        })
+                    });
+
+                    match path {
+                        Some(path) => {
+                            let path_str = stats.decode(&path);
+                            if !filter.keeps_reparse(Path::new(&*path_str), attributes) {
+                                stats.skipped_filtered.fetch_add(1, Ordering::Relaxed);
+                                return None;
+                            }
+                            stats.added_files.fetch_add(1, Ordering::Relaxed);
+                            Some(result_entry(
+                                &path_str,
+                                Everything3_GetResultSize(results, i),
+                                Everything3_GetResultDateModified(results, i),
+                                attributes,
+                            ))
+                        }
+                        None => {
+                            stats.zero_len_paths.fetch_add(1, Ordering::Relaxed);
                             None
                         }
-                    } else {
-                        let path_str = std::str::from_utf8(&buffer[..len as usize]).unwrap_or("");
-                        let size = Everything3_GetResultSize(results, i);
-                        added_files.fetch_add(1, Ordering::Relaxed);
-                        Some((PathBuf::from(path_str), size))
                     }
                 })
                 .flatten()
                 .collect();
 
-            log::debug!(
-                "[Everything] Debug: Processed {} results - {} dirs skipped, {} zero-length paths, {} hardlinks skipped, {} files added",
-                count,
-                skipped_dirs.load(Ordering::Relaxed),
-                zero_len_paths.load(Ordering::Relaxed),
-                skipped_hardlinks.load(Ordering::Relaxed),
-                added_files.load(Ordering::Relaxed)
-            );
+            progress.finish();
+            stats.log(count);
 
             Everything3_DestroyResultList(results);
             Everything3_DestroySearchState(search_state);
@@ -242,3 +409,161 @@ impl Drop for EverythingSearch {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_carry_modification_time_and_attributes() {
+        // 2024-01-01T00:00:00Z, read-only and archive
+        let entry = result_entry(r"C:\data\a.bin", 4096, 133_485_408_000_000_000, 0x21);
+        assert_eq!(
+            entry,
+            FileEntry {
+                path: PathBuf::from(r"C:\data\a.bin"),
+                size: 4096,
+                mtime: Some(1_704_067_200),
+                attributes: 0x21,
+            }
+        );
+        assert_ne!(entry.mtime, FileEntry::new(entry.path.clone(), 4096).mtime);
+
+        // Everything reports an unknown date as all ones
+        assert_eq!(result_entry(r"C:\b", 1, u64::MAX, 0x20).mtime, None);
+    }
+
+    #[test]
+    fn nul_in_instance_name_is_an_error() {
+        let err = unsafe { connect(Some("1.5a\0")) }.unwrap_err();
+        assert!(
+            err.to_string().contains("Instance name '1.5a\\0'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn search_error_carries_code() {
+        let err = search_error("Search for 'C:\\'", EVERYTHING3_ERROR_IPC_PIPE_NOT_FOUND);
+        match &err {
+            crate::error::AppError::Everything { message, code } => {
+                assert_eq!(*code, EVERYTHING3_ERROR_IPC_PIPE_NOT_FOUND);
+                assert!(message.starts_with("Search for 'C:\\'"));
+                assert!(message.ends_with(&EVERYTHING3_ERROR_IPC_PIPE_NOT_FOUND.to_string()));
+            }
+            other => panic!("unexpected error variant: {:?}", other),
+        }
+        assert!(err.to_string().starts_with("Everything search error:"));
+    }
+
+    #[test]
+    fn error_codes_are_explained() {
+        assert_eq!(
+            describe_error(EVERYTHING3_ERROR_IPC_PIPE_NOT_FOUND),
+            "Everything is not installed or not running"
+        );
+        assert!(describe_error(EVERYTHING3_ERROR_BAD_REQUEST).contains("too old"));
+        assert!(describe_error(ERROR_ACCESS_DENIED).starts_with("access denied"));
+        assert_eq!(describe_error(0xDEAD), "unknown error");
+
+        let err = search_error("Search for 'D:'", EVERYTHING3_ERROR_DISCONNECTED);
+        assert!(err
+            .to_string()
+            .ends_with("(the connection to Everything was lost)"));
+        assert!(is_transient_error(EVERYTHING3_ERROR_DISCONNECTED));
+        assert!(!is_transient_error(EVERYTHING3_ERROR_IPC_PIPE_NOT_FOUND));
+    }
+
+    /// Behaves like the Everything3 text getters: copies what fits including
+    /// the null terminator, or reports the required size for a null buffer
+    fn fake_getter(text: &[u8], buf: *mut u8, size: u64) -> u64 {
+        if buf.is_null() {
+            return text.len() as u64 + 1;
+        }
+        let copied = text.len().min(size as usize - 1);
+        unsafe {
+            ptr::copy_nonoverlapping(text.as_ptr(), buf, copied);
+            *buf.add(copied) = 0;
+        }
+        copied as u64
+    }
+
+    #[test]
+    fn long_text_is_not_truncated() {
+        let long_path = format!(r"\\?\C:\{}\file.bin", "deep\\".repeat(1000));
+        assert!(long_path.len() > TEXT_BUFFER_SIZE);
+        let read = read_utf8(|buf, size| fake_getter(long_path.as_bytes(), buf, size));
+        assert_eq!(read.as_deref(), Some(long_path.as_bytes()));
+
+        let short = read_utf8(|buf, size| fake_getter(b"C:\\a.txt", buf, size));
+        assert_eq!(short.as_deref(), Some(&b"C:\\a.txt"[..]));
+
+        assert_eq!(read_utf8(|buf, size| fake_getter(b"", buf, size)), None);
+    }
+
+    #[test]
+    fn exactly_one_hardlink_leader_is_elected() {
+        // Mixed case and an unsorted list, as Everything may report them
+        let names: Vec<String> = (0..50)
+            .rev()
+            .map(|i| {
+                if i % 2 == 0 {
+                    format!(r"\Data\Link{:02}.bin", i)
+                } else {
+                    format!(r"\data\LINK{:02}.BIN", i)
+                }
+            })
+            .collect();
+        let list = names.join(";");
+
+        let leaders: Vec<&String> = names
+            .iter()
+            .filter(|name| is_hardlink_leader(&format!("C:{}", name), &list))
+            .collect();
+        assert_eq!(leaders, [r"\Data\Link00.bin"]);
+    }
+
+    #[test]
+    fn hardlink_names_ignore_volume_spelling() {
+        let names = r"\Data\a.bin;\data\b.bin";
+        for current in [
+            r"C:\data\a.bin",
+            r"\\?\C:\data\a.bin",
+            r"\\server\share\data\a.bin",
+            r"\\?\UNC\server\share\Data\A.bin",
+        ] {
+            assert!(is_hardlink_leader(current, names), "{}", current);
+        }
+        assert!(!is_hardlink_leader(r"\\server\share\data\b.bin", names));
+    }
+
+    #[test]
+    fn invalid_utf8_path_is_kept() {
+        let stats = ResultStats::default();
+        let bytes = b"C:\\photos\\caf\xe9.jpg";
+
+        let path = stats.decode(bytes);
+        assert_eq!(path, "C:\\photos\\caf\u{FFFD}.jpg");
+        assert_eq!(stats.lossy_paths.load(Ordering::Relaxed), 1);
+
+        assert_eq!(stats.decode(b"C:\\a.txt"), "C:\\a.txt");
+        assert_eq!(stats.lossy_paths.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn stats_are_silent_at_default_level() {
+        let stats = ResultStats::default();
+        stats.added_files.fetch_add(3, Ordering::Relaxed);
+        let lines = stats.summary(3);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].0, log::Level::Debug);
+        assert!(lines[0].1.contains("3 files added"));
+
+        // Replaced characters are worth a warning even without --verbose
+        stats.lossy_paths.fetch_add(1, Ordering::Relaxed);
+        let lines = stats.summary(3);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].0, log::Level::Warn);
+    }
+}
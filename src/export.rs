@@ -0,0 +1,416 @@
+use std::io::{self, Write};
+
+use nanoserde::SerJson;
+
+use super::algorithm::{sort_groups, summarize, DuplicateGroup, SortOrder};
+
+/// Quote a CSV field if it contains a delimiter, quote or line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write one `group_id,size,path` row per duplicated file.
+///
+/// Group ids are assigned after ordering groups by size and then by their
+/// sorted paths, so the same results always get the same ids.
+pub fn write_csv<W: Write>(groups: &[DuplicateGroup], mut writer: W) -> io::Result<()> {
+    let mut ordered: Vec<(u64, Vec<&String>)> = groups
+        .iter()
+        .map(|group| {
+            let mut paths: Vec<&String> = group.paths.iter().collect();
+            paths.sort();
+            (group.size, paths)
+        })
+        .collect();
+    ordered.sort();
+
+    writeln!(writer, "group_id,size,path")?;
+    for (group_id, (size, paths)) in ordered.iter().enumerate() {
+        for path in paths {
+            writeln!(writer, "{},{},{}", group_id, size, csv_field(path))?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// Write the groups as a JSON array one group at a time, producing the same
+/// bytes as `serialize_json` on the whole list without holding it in memory.
+pub fn write_json<W: Write>(groups: &[DuplicateGroup], mut writer: W) -> io::Result<()> {
+    writer.write_all(b"[")?;
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(group.serialize_json().as_bytes())?;
+    }
+    writer.write_all(b"]")?;
+
+    writer.flush()
+}
+
+/// Write one group per line as a JSON object, which line-based tools such
+/// as `jq -c` can consume while it is being written.
+pub fn write_jsonl<W: Write>(groups: &[DuplicateGroup], mut writer: W) -> io::Result<()> {
+    for group in groups {
+        writer.write_all(group.serialize_json().as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()
+}
+
+/// Write bare paths for scripts, one per line, with a blank line between
+/// groups. Groups keep their order.
+pub fn write_paths<W: Write>(groups: &[DuplicateGroup], mut writer: W) -> io::Result<()> {
+    for (i, group) in groups.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer)?;
+        }
+        for path in &group.paths {
+            writeln!(writer, "{}", path)?;
+        }
+    }
+
+    writer.flush()
+}
+
+/// Write every path of every group on a line of its own, with nothing in
+/// between, as shell pipelines expect. `exclude_leader` leaves out the first
+/// path of each group, the one links would point to, so only the redundant
+/// copies are listed.
+pub fn write_path_list<W: Write>(
+    groups: &[DuplicateGroup],
+    exclude_leader: bool,
+    mut writer: W,
+) -> io::Result<()> {
+    let skip = usize::from(exclude_leader);
+    for path in groups
+        .iter()
+        .flat_map(|group| group.paths.iter().skip(skip))
+    {
+        writeln!(writer, "{}", path)?;
+    }
+
+    writer.flush()
+}
+
+/// Escape text for use in HTML content and attribute values
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Byte count in binary units for people, e.g. `1.5 MiB`
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+const HTML_STYLE: &str = "body{font-family:system-ui,sans-serif;margin:2em;color:#222}\
+summary{cursor:pointer;padding:.3em 0}\
+.group{border-bottom:1px solid #ddd}\
+.group ul{margin:.2em 0 .6em;font-family:ui-monospace,monospace}\
+.size{display:inline-block;min-width:7em;font-weight:bold}\
+.reclaimable{color:#a33}";
+
+/// Write a self-contained HTML page with one collapsible section per group,
+/// largest reclaimable space first, and the totals at the top
+pub fn write_html<W: Write>(groups: &[DuplicateGroup], mut writer: W) -> io::Result<()> {
+    let mut ordered = groups.to_vec();
+    sort_groups(&mut ordered, SortOrder::Size);
+    let summary = summarize(&ordered);
+
+    writeln!(writer, "<!DOCTYPE html>")?;
+    writeln!(writer, "<html lang=\"en\">")?;
+    writeln!(writer, "<head>")?;
+    writeln!(writer, "<meta charset=\"utf-8\">")?;
+    writeln!(writer, "<title>ddup report</title>")?;
+    writeln!(writer, "<style>{}</style>", HTML_STYLE)?;
+    writeln!(writer, "</head>")?;
+    writeln!(writer, "<body>")?;
+    writeln!(writer, "<h1>Duplicate files</h1>")?;
+    writeln!(
+        writer,
+        "<p class=\"summary\" data-bytes=\"{}\">{} groups, {} redundant files, {} ({} bytes) reclaimable</p>",
+        summary.reclaimable_bytes,
+        summary.groups,
+        summary.redundant_files,
+        human_size(summary.reclaimable_bytes),
+        summary.reclaimable_bytes
+    )?;
+
+    for group in &ordered {
+        let reclaimable = summarize(std::slice::from_ref(group)).reclaimable_bytes;
+        writeln!(
+            writer,
+            "<details class=\"group\" data-size=\"{}\" data-reclaimable=\"{}\">",
+            group.size, reclaimable
+        )?;
+        writeln!(
+            writer,
+            "<summary><span class=\"size\">{}</span> {} copies, <span class=\"reclaimable\">{} reclaimable</span></summary>",
+            human_size(group.size),
+            group.paths.len(),
+            human_size(reclaimable)
+        )?;
+        writeln!(writer, "<ul>")?;
+        for path in &group.paths {
+            writeln!(writer, "<li>{}</li>", html_escape(path))?;
+        }
+        writeln!(writer, "</ul>")?;
+        writeln!(writer, "</details>")?;
+    }
+
+    writeln!(writer, "</body>")?;
+    writeln!(writer, "</html>")?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::Confidence;
+
+    #[test]
+    fn csv_has_header_and_row_per_path() {
+        let groups = vec![
+            DuplicateGroup {
+                size: 200,
+                paths: vec![r"C:\b\x.bin".to_string(), r"C:\a\x.bin".to_string()],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+            DuplicateGroup {
+                size: 100,
+                paths: vec![
+                    r"C:\one, two.txt".to_string(),
+                    r"C:\three.txt".to_string(),
+                    r"C:\four.txt".to_string(),
+                ],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_csv(&groups, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "group_id,size,path");
+        assert_eq!(lines[1], r"0,100,C:\four.txt");
+        assert_eq!(lines[2], r#"0,100,"C:\one, two.txt""#);
+        assert_eq!(lines[4], r"1,200,C:\a\x.bin");
+    }
+
+    #[test]
+    fn streamed_json_matches_serialize_json() {
+        let groups: Vec<DuplicateGroup> = (0..500)
+            .map(|i| DuplicateGroup {
+                size: i * 1024,
+                paths: (0..3)
+                    .map(|j| format!(r#"C:\data\{}\copy "{}".bin"#, i, j))
+                    .collect(),
+                mtimes: None,
+                confidence: Confidence::Exact,
+            })
+            .collect();
+
+        let mut output = Vec::new();
+        write_json(&groups, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), groups.serialize_json());
+
+        let mut output = Vec::new();
+        write_json(&[], &mut output).unwrap();
+        assert_eq!(
+            output,
+            Vec::<DuplicateGroup>::new().serialize_json().as_bytes()
+        );
+    }
+
+    #[test]
+    fn jsonl_lines_parse_back_on_their_own() {
+        use nanoserde::DeJson;
+
+        let groups = vec![
+            DuplicateGroup {
+                size: 100,
+                paths: vec![
+                    r"C:\one, two.txt".to_string(),
+                    "C:\\new\nline.txt".to_string(),
+                ],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+            DuplicateGroup {
+                size: 200,
+                paths: vec![r#"D:\"quoted".bin"#.to_string(), r"D:\b.bin".to_string()],
+                mtimes: Some(vec![1, 2]),
+                confidence: Confidence::Exact,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_jsonl(&groups, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), groups.len());
+        for (line, group) in lines.iter().zip(&groups) {
+            let parsed = DuplicateGroup::deserialize_json(line).unwrap();
+            assert_eq!(parsed.size, group.size);
+            assert_eq!(parsed.paths, group.paths);
+            assert_eq!(parsed.mtimes, group.mtimes);
+        }
+    }
+
+    #[test]
+    fn empty_jsonl_export_imports_as_no_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let export = dir.path().join("none.jsonl");
+        write_jsonl(&[], std::fs::File::create(&export).unwrap()).unwrap();
+        assert_eq!(std::fs::metadata(&export).unwrap().len(), 0);
+        assert!(DuplicateGroup::load_json(&export).unwrap().is_empty());
+
+        std::fs::write(&export, "\r\n  \n").unwrap();
+        assert!(DuplicateGroup::load_json(&export).unwrap().is_empty());
+    }
+
+    #[test]
+    fn paths_are_separated_by_blank_lines() {
+        let groups = vec![
+            DuplicateGroup {
+                size: 100,
+                paths: vec![r"C:\a.txt".to_string(), r"C:\b.txt".to_string()],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+            DuplicateGroup {
+                size: 50,
+                paths: vec![r"D:\c d.txt".to_string(), r"D:\e.txt".to_string()],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_paths(&groups, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "C:\\a.txt\nC:\\b.txt\n\nD:\\c d.txt\nD:\\e.txt\n"
+        );
+
+        let mut output = Vec::new();
+        write_paths(&[], &mut output).unwrap();
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn path_list_can_leave_out_leaders() {
+        let groups = vec![
+            DuplicateGroup {
+                size: 100,
+                paths: vec![
+                    r"C:\keep.txt".to_string(),
+                    r"C:\copy 1.txt".to_string(),
+                    r"C:\copy 2.txt".to_string(),
+                ],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+            DuplicateGroup {
+                size: 50,
+                paths: vec![r"D:\keep.bin".to_string(), r"D:\copy.bin".to_string()],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_path_list(&groups, false, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "C:\\keep.txt\nC:\\copy 1.txt\nC:\\copy 2.txt\nD:\\keep.bin\nD:\\copy.bin\n"
+        );
+
+        let mut output = Vec::new();
+        write_path_list(&groups, true, &mut output).unwrap();
+        let listed = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = listed.lines().collect();
+        assert_eq!(lines, [r"C:\copy 1.txt", r"C:\copy 2.txt", r"D:\copy.bin"]);
+    }
+
+    #[test]
+    fn html_report_has_a_section_per_group_with_totals() {
+        let groups = vec![
+            DuplicateGroup {
+                size: 100,
+                paths: vec![r"C:\a.txt".to_string(), r"C:\b.txt".to_string()],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+            DuplicateGroup {
+                size: 2 * 1024 * 1024,
+                paths: vec![
+                    r"C:\video.mp4".to_string(),
+                    r"D:\backup\video.mp4".to_string(),
+                    r"D:\<old> & video.mp4".to_string(),
+                ],
+                mtimes: None,
+                confidence: Confidence::Exact,
+            },
+        ];
+
+        let mut output = Vec::new();
+        write_html(&groups, &mut output).unwrap();
+        let html = String::from_utf8(output).unwrap();
+
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(!html.contains("<script") && !html.contains("<link"));
+        assert_eq!(html.matches("<details class=\"group\"").count(), 2);
+        assert_eq!(html.matches("<li>").count(), 5);
+        let total = 100 + 2 * 2 * 1024 * 1024;
+        assert!(html.contains(&format!("data-bytes=\"{}\"", total)));
+        assert!(html.contains(&format!("({} bytes) reclaimable", total)));
+
+        // Largest reclaimable first
+        let video = html.find("data-reclaimable=\"4194304\"").unwrap();
+        let text = html.find("data-reclaimable=\"100\"").unwrap();
+        assert!(video < text);
+        assert!(html.contains("4.0 MiB reclaimable"));
+        assert!(html.contains(r"<li>D:\&lt;old&gt; &amp; video.mp4</li>"));
+    }
+
+    #[test]
+    fn sizes_are_shown_in_binary_units() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+}
@@ -0,0 +1,164 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+use crate::progress::CancelFlag;
+use crate::throttle::Throttle;
+
+/// Default cap on files held open at once, well below the Windows C runtime
+/// and handle limits
+pub const DEFAULT_MAX_OPEN_FILES: usize = 256;
+
+/// Counting semaphore bounding how many files are open at the same time.
+/// Rayon happily runs a closure per file on every thread, so without it a
+/// large size group can run into `ERROR_TOO_MANY_OPEN_FILES`. Every read
+/// goes through it, so it also carries the optional read rate limit and
+/// whether the run was cancelled.
+#[derive(Debug)]
+pub struct OpenFileGate {
+    limit: usize,
+    available: Mutex<usize>,
+    released: Condvar,
+    /// Most slots taken at once so far
+    peak: AtomicUsize,
+    throttle: Option<Throttle>,
+    cancel: CancelFlag,
+}
+
+/// Slots taken from an `OpenFileGate`, handed back on drop
+#[derive(Debug)]
+pub struct Permit<'a> {
+    gate: &'a OpenFileGate,
+    count: usize,
+}
+
+impl OpenFileGate {
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        OpenFileGate {
+            limit,
+            available: Mutex::new(limit),
+            released: Condvar::new(),
+            peak: AtomicUsize::new(0),
+            throttle: None,
+            cancel: CancelFlag::default(),
+        }
+    }
+
+    /// Have hashing skip the files it hasn't started on once `cancel` is set
+    pub fn with_cancel(mut self, cancel: CancelFlag) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Whether the run was cancelled, checked before each file is read
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Cap reads through this gate at `bytes_per_second` in total
+    pub fn with_throttle(mut self, bytes_per_second: u64) -> Self {
+        self.throttle = Some(Throttle::new(bytes_per_second));
+        self
+    }
+
+    /// Account for `bytes` read, waiting if the throttle asks for it. Called
+    /// per read buffer, so a large file is read at the rate as well instead
+    /// of in a burst followed by a long wait.
+    pub fn throttle(&self, bytes: u64) {
+        if let Some(throttle) = &self.throttle {
+            throttle.take(bytes);
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Most files held open through this gate at once so far
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+
+    /// Wait for a slot to open a single file
+    pub fn acquire(&self) -> Permit<'_> {
+        self.acquire_many(1)
+    }
+
+    /// Wait for slots to hold `count` files open together. Callers split
+    /// larger work to fit the limit, a request above it would otherwise
+    /// just take every slot and still open more.
+    pub fn acquire_many(&self, count: usize) -> Permit<'_> {
+        debug_assert!(
+            count <= self.limit,
+            "{} files requested of {}",
+            count,
+            self.limit
+        );
+        let count = count.clamp(1, self.limit);
+        let mut available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        while *available < count {
+            available = self
+                .released
+                .wait(available)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+        *available -= count;
+        self.peak
+            .fetch_max(self.limit - *available, Ordering::Relaxed);
+        Permit { gate: self, count }
+    }
+}
+
+impl Default for OpenFileGate {
+    fn default() -> Self {
+        OpenFileGate::new(DEFAULT_MAX_OPEN_FILES)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let mut available = self
+            .gate
+            .available
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        *available += self.count;
+        self.gate.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+
+    #[test]
+    fn gate_never_exceeds_limit() {
+        let gate = OpenFileGate::new(4);
+        let open = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(16)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            (0..2000).into_par_iter().for_each(|i| {
+                let _permit = if i % 10 == 0 {
+                    gate.acquire_many(3)
+                } else {
+                    gate.acquire()
+                };
+                let weight = if i % 10 == 0 { 3 } else { 1 };
+                let now = open.fetch_add(weight, Ordering::SeqCst) + weight;
+                peak.fetch_max(now, Ordering::SeqCst);
+                std::thread::yield_now();
+                open.fetch_sub(weight, Ordering::SeqCst);
+            });
+        });
+
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert!(gate.peak() <= 4);
+        assert_eq!(*gate.available.lock().unwrap(), 4);
+    }
+}
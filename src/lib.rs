@@ -1,13 +1,25 @@
 pub mod algorithm;
+pub mod builder;
+mod cache;
+pub mod checkpoint;
 pub mod dirlist;
 pub mod error;
 pub mod everything;
+pub mod export;
+pub mod gate;
+pub mod link;
+mod mft;
 mod ntfs;
+pub mod progress;
+pub mod throttle;
 pub mod utils;
 mod volume;
 mod winioctl;
 
-pub use dirlist::{Backend, DirList};
+pub use builder::DedupBuilder;
+pub use dirlist::{Backend, DirList, FileEntry};
 pub use ntfs::Ntfs;
 pub use ntfs::{UsnRange, UsnRecord, UsnRecordType, UsnRecordsIterator};
-pub use volume::Volume;
+pub use progress::{CancelFlag, Phase, ProgressObserver, ProgressReporter};
+pub use volume::{allocated_size, file_identity, named_streams_size, reflink, FileId, Volume};
+pub use winioctl::{USN_REASON_FILE_CREATE, USN_REASON_FILE_DELETE};
@@ -0,0 +1,1103 @@
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::os::windows::fs::symlink_file;
+use std::path::{Component, Path, PathBuf, Prefix};
+use std::sync::Mutex;
+
+use nanoserde::{DeJson, SerJson};
+use rayon::prelude::*;
+
+use super::algorithm::{common_directory, fill_mtimes, DuplicateGroup};
+use super::utils::{file_attributes, protected_attribute};
+use super::{file_identity, reflink};
+
+/// A single link action, recorded so it can be reverted later
+#[derive(SerJson, DeJson, Debug, Clone, PartialEq)]
+pub struct UndoEntry {
+    pub path: String,
+    pub leader: String,
+    pub size: u64,
+    /// The file at `path` was moved to `leader` by `organize`, rather than
+    /// replaced with a link to it
+    #[nserde(default)]
+    pub moved: bool,
+}
+
+/// Append-only JSON lines log of the links created during a run
+pub struct UndoLog {
+    file: Mutex<fs::File>,
+}
+
+impl UndoLog {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(UndoLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    fn record(&self, entry: &UndoEntry) {
+        let line = entry.serialize_json() + "\n";
+        let result = match self.file.lock() {
+            Ok(mut file) => file.write_all(line.as_bytes()),
+            Err(_) => Err(io::Error::other("undo log mutex was poisoned")),
+        };
+        if let Err(e) = result {
+            log::error!("Failed to record {} in undo log: {}", entry.path, e);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkKind {
+    Hard,
+    /// Works across volumes, but needs elevation or Developer Mode on Windows
+    Symbolic,
+    /// Copy-on-write clone, keeps files independent but needs ReFS or Dev Drive
+    Reflink,
+}
+
+impl LinkKind {
+    fn create(self, leader: &Path, path: &Path) -> io::Result<()> {
+        match self {
+            LinkKind::Hard => fs::hard_link(leader, path),
+            LinkKind::Symbolic => symlink_file(leader, path),
+            LinkKind::Reflink => reflink(leader, path),
+        }
+    }
+}
+
+/// Which file of a group is kept as the link target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeepPolicy {
+    /// The first path after sorting, alphabetically
+    #[default]
+    First,
+    /// Earliest modification time, likely the original
+    Oldest,
+    /// Latest modification time
+    Newest,
+    /// The file in the shallowest directory
+    ShortestPath,
+    /// Lowest NTFS file id, which survives renames, so repeated runs keep
+    /// linking to the same file instead of whichever path sorts first
+    LowestId,
+}
+
+impl KeepPolicy {
+    pub const NAMES: [&'static str; 5] =
+        ["first", "oldest", "newest", "shortest-path", "lowest-id"];
+}
+
+impl std::str::FromStr for KeepPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first" => Ok(KeepPolicy::First),
+            "oldest" => Ok(KeepPolicy::Oldest),
+            "newest" => Ok(KeepPolicy::Newest),
+            "shortest-path" => Ok(KeepPolicy::ShortestPath),
+            "lowest-id" => Ok(KeepPolicy::LowestId),
+            _ => Err(format!("Unknown keep policy `{}`", s)),
+        }
+    }
+}
+
+/// Move the file `policy` keeps to the front of each group, where
+/// `link_duplicates` takes its leader from. Reads modification times if
+/// they weren't recorded with the groups.
+pub fn apply_keep_policy(groups: &mut [DuplicateGroup], policy: KeepPolicy) {
+    if matches!(policy, KeepPolicy::Oldest | KeepPolicy::Newest)
+        && groups.iter().any(|group| group.mtimes.is_none())
+    {
+        fill_mtimes(groups);
+    }
+
+    for group in groups.iter_mut() {
+        let leader = match policy {
+            KeepPolicy::First => None,
+            // Unreadable times are 0, don't mistake them for the oldest
+            KeepPolicy::Oldest => group.mtimes.as_ref().and_then(|mtimes| {
+                (0..mtimes.len()).min_by_key(|&i| match mtimes[i] {
+                    0 => u64::MAX,
+                    mtime => mtime,
+                })
+            }),
+            KeepPolicy::Newest => group
+                .mtimes
+                .as_ref()
+                .and_then(|mtimes| (0..mtimes.len()).min_by_key(|&i| Reverse(mtimes[i]))),
+            KeepPolicy::ShortestPath => (0..group.paths.len()).min_by_key(|&i| {
+                let path = Path::new(&group.paths[i]);
+                (path.components().count(), group.paths[i].len())
+            }),
+            // Files whose id can't be read sort last
+            KeepPolicy::LowestId => (0..group.paths.len()).min_by_key(|&i| {
+                file_identity(Path::new(&group.paths[i]))
+                    .map_or((u32::MAX, u64::MAX), |id| (id.volume_serial, id.index))
+            }),
+        };
+
+        if let Some(leader) = leader.filter(|&i| i > 0 && i < group.paths.len()) {
+            let path = group.paths.remove(leader);
+            group.paths.insert(0, path);
+            if let Some(mtimes) = group.mtimes.as_mut().filter(|m| leader < m.len()) {
+                let mtime = mtimes.remove(leader);
+                mtimes.insert(0, mtime);
+            }
+        }
+    }
+}
+
+/// Split every group into one group per parent directory, so linking never
+/// reaches across folders. Folders are compared case-insensitively like
+/// NTFS does, and folders left with a single copy are dropped. Paths keep
+/// their order and their mtimes.
+pub fn split_by_directory(groups: Vec<DuplicateGroup>) -> Vec<DuplicateGroup> {
+    let mut split = Vec::new();
+    for group in groups {
+        let mut directories: Vec<(String, DuplicateGroup)> = Vec::new();
+        for (i, path) in group.paths.iter().enumerate() {
+            let directory = Path::new(path)
+                .parent()
+                .map(|parent| parent.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            let index = match directories.iter().position(|(d, _)| *d == directory) {
+                Some(index) => index,
+                None => {
+                    let empty = DuplicateGroup {
+                        size: group.size,
+                        paths: Vec::new(),
+                        mtimes: group.mtimes.as_ref().map(|_| Vec::new()),
+                        confidence: group.confidence,
+                    };
+                    directories.push((directory, empty));
+                    directories.len() - 1
+                }
+            };
+            let subgroup = &mut directories[index].1;
+            subgroup.paths.push(path.clone());
+            if let (Some(mtimes), Some(all)) = (subgroup.mtimes.as_mut(), group.mtimes.as_ref()) {
+                mtimes.push(all.get(i).copied().unwrap_or(0));
+            }
+        }
+        split.extend(
+            directories
+                .into_iter()
+                .map(|(_, subgroup)| subgroup)
+                .filter(|subgroup| subgroup.paths.len() > 1),
+        );
+    }
+    split
+}
+
+/// Identify the volume a path lives on from its prefix (`C:`, `\\?\C:` or
+/// `\\server\share`), or `None` for paths without one.
+fn volume_of(path: &Path) -> Option<String> {
+    match path.components().next()? {
+        Component::Prefix(prefix) => Some(match prefix.kind() {
+            Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+                format!("{}:", letter.to_ascii_uppercase() as char)
+            }
+            Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => format!(
+                r"\\{}\{}",
+                server.to_string_lossy().to_lowercase(),
+                share.to_string_lossy().to_lowercase()
+            ),
+            _ => prefix.as_os_str().to_string_lossy().to_lowercase(),
+        }),
+        _ => None,
+    }
+}
+
+/// Whether two paths are known to live on different volumes
+fn on_different_volumes(a: &Path, b: &Path) -> bool {
+    match (volume_of(a), volume_of(b)) {
+        (Some(a), Some(b)) => a != b,
+        _ => false,
+    }
+}
+
+/// Whether `path` is read-only, system or hidden, which `--link` leaves
+/// alone unless `--link-readonly` is given
+fn is_protected(path: &str) -> bool {
+    match file_attributes(Path::new(path)) {
+        Ok(attributes) => match protected_attribute(attributes) {
+            Some(name) => {
+                log::info!(
+                    "Skipping {}: {} file, pass --link-readonly to link it anyway",
+                    path,
+                    name
+                );
+                true
+            }
+            None => false,
+        },
+        // Let the link attempt report why the file can't be read
+        Err(_) => false,
+    }
+}
+
+/// Suffix of the backup older versions moved a duplicate to while linking
+const TMP_SUFFIX: &str = ".ddup_tmp";
+/// Suffix of a link waiting to be renamed over its duplicate
+const LINK_SUFFIX: &str = ".ddup_link";
+
+/// Put back files left as `*.ddup_tmp` by a run that was killed while
+/// linking. A backup is only renamed back when its original name is missing,
+/// otherwise the link was made and the backup is left for the user to check.
+/// Unfinished `*.ddup_link` files are removed, their duplicate is untouched.
+/// Returns how many files were restored.
+pub fn recover(root: &Path) -> usize {
+    let mut restored = 0;
+    for entry in walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+    {
+        let tmp_path = entry.path();
+        let Some(name) = tmp_path.to_str() else {
+            continue;
+        };
+        if name.ends_with(LINK_SUFFIX) {
+            match fs::remove_file(tmp_path) {
+                Ok(()) => log::info!("Removed unfinished link {}", name),
+                Err(e) => log::warn!("Failed to remove unfinished link {}: {}", name, e),
+            }
+            continue;
+        }
+        let Some(original) = name.strip_suffix(TMP_SUFFIX) else {
+            continue;
+        };
+
+        if Path::new(original).exists() {
+            log::warn!(
+                "Leaving {}: {} exists, delete the backup once it is no longer needed",
+                tmp_path.display(),
+                original
+            );
+            continue;
+        }
+
+        match fs::rename(tmp_path, original) {
+            Ok(()) => {
+                log::info!("Restored {} from {}", original, tmp_path.display());
+                restored += 1;
+            }
+            Err(e) => log::error!("Failed to restore {}: {}", original, e),
+        }
+    }
+    restored
+}
+
+/// Replace `path` with a link to `leader`. The link is made under a
+/// temporary name next to `path` and then renamed over it, which replaces
+/// the original in one step, so `path` never goes missing. On failure the
+/// original is left as it was.
+pub fn relink_safely(leader: &str, path: &str, kind: LinkKind) -> io::Result<()> {
+    relink_with(Path::new(leader), Path::new(path), |leader, link| {
+        kind.create(leader, link)
+    })
+}
+
+fn relink_with(
+    leader: &Path,
+    path: &Path,
+    create: impl FnOnce(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut link_path = path.as_os_str().to_owned();
+    link_path.push(LINK_SUFFIX);
+    let link_path = PathBuf::from(link_path);
+
+    if let Err(e) = create(leader, &link_path) {
+        let _ = fs::remove_file(&link_path);
+        return Err(e);
+    }
+
+    // Windows refuses to replace a read-only file
+    let permissions = fs::metadata(path)?.permissions();
+    if permissions.readonly() {
+        let mut writable = permissions.clone();
+        // Only clears FILE_ATTRIBUTE_READONLY on Windows
+        #[allow(clippy::permissions_set_readonly_false)]
+        writable.set_readonly(false);
+        fs::set_permissions(path, writable)?;
+    }
+
+    if let Err(e) = fs::rename(&link_path, path) {
+        let _ = fs::remove_file(&link_path);
+        if permissions.readonly() {
+            let _ = fs::set_permissions(path, permissions);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Returns whether the duplicate was replaced
+fn replace_with_link(leader: &str, path: &str, kind: LinkKind) -> bool {
+    match relink_safely(leader, path, kind) {
+        Ok(()) => true,
+        Err(e) => {
+            log::error!("Failed to link {} to {}: {}", path, leader, e);
+            false
+        }
+    }
+}
+
+/// Replace every duplicate with a link to the first path of its group and
+/// return the number of bytes freed.
+///
+/// With `dry_run` set nothing is touched; the planned links are logged and the
+/// returned figure is what a real run would free if every link succeeded.
+pub fn link_duplicates(
+    duplicates: &[DuplicateGroup],
+    kind: LinkKind,
+    dry_run: bool,
+    link_protected: bool,
+    undo_log: Option<&UndoLog>,
+) -> u64 {
+    if kind == LinkKind::Symbolic && !dry_run {
+        log::warn!(
+            "Creating symbolic links may require Administrator privileges or Developer Mode"
+        );
+    }
+
+    duplicates
+        .par_iter()
+        .map(|group| {
+            let mut group_freed = 0;
+            if let Some(first) = group.paths.first() {
+                // Hardlinks share attributes, so a protected leader would
+                // pass its flags on to every duplicate
+                if !link_protected && is_protected(first) {
+                    return 0;
+                }
+                for path in &group.paths[1..] {
+                    if !link_protected && is_protected(path) {
+                        continue;
+                    }
+                    if kind != LinkKind::Symbolic
+                        && on_different_volumes(Path::new(first), Path::new(path))
+                    {
+                        log::warn!(
+                            "Skipping {}: {:?} links cannot cross volumes (leader is {})",
+                            path,
+                            kind,
+                            first
+                        );
+                        continue;
+                    }
+
+                    if dry_run {
+                        log::info!("[Dry run] Would link {} -> {}", path, first);
+                        group_freed += group.size;
+                        continue;
+                    }
+
+                    log::info!("Linking {} -> {}", path, first);
+                    if replace_with_link(first, path, kind) {
+                        group_freed += group.size;
+                        if let Some(undo_log) = undo_log {
+                            undo_log.record(&UndoEntry {
+                                path: path.clone(),
+                                leader: first.clone(),
+                                size: group.size,
+                                moved: false,
+                            });
+                        }
+                    }
+                }
+            }
+            group_freed
+        })
+        .sum()
+}
+
+/// Where `organize` moves the leader of each group: its path below the
+/// folder common to all duplicates, recreated under `output_dir`. A name
+/// already taken, on disk or by an earlier group, gets a ` (2)`, ` (3)`...
+/// suffix before the extension.
+fn organize_targets(duplicates: &[DuplicateGroup], output_dir: &Path) -> Vec<PathBuf> {
+    let root = common_directory(
+        duplicates
+            .iter()
+            .flat_map(|group| group.paths.iter().map(Path::new)),
+    );
+    let mut taken = HashSet::new();
+    duplicates
+        .iter()
+        .map(|group| {
+            let leader = Path::new(group.paths.first().map_or("", String::as_str));
+            // Only the names below the root, a drive prefix would replace
+            // `output_dir` when joined
+            let relative: PathBuf = leader
+                .strip_prefix(&root)
+                .unwrap_or(leader)
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect();
+            let wanted = output_dir.join(&relative);
+
+            let mut target = wanted.clone();
+            let mut copy = 1;
+            // The leader itself is no collision, it was organized before
+            while (target != leader && fs::symlink_metadata(&target).is_ok())
+                || !taken.insert(target.to_string_lossy().to_lowercase())
+            {
+                copy += 1;
+                let mut name = wanted.file_stem().unwrap_or_default().to_owned();
+                name.push(format!(" ({})", copy));
+                if let Some(extension) = wanted.extension() {
+                    name.push(".");
+                    name.push(extension);
+                }
+                target = wanted.with_file_name(name);
+            }
+            target
+        })
+        .collect()
+}
+
+/// Move the leader of every group into `output_dir`, keeping its path below
+/// the folder common to all duplicates, then replace the other copies with
+/// links to the moved file. Groups whose leader can't be moved are left
+/// alone. Moves are recorded in `undo_log` ahead of the links to the moved
+/// file, so `undo` puts the file back once the links are reverted. Returns
+/// the bytes freed, as `link_duplicates` does.
+pub fn organize(
+    duplicates: &[DuplicateGroup],
+    output_dir: &Path,
+    kind: LinkKind,
+    dry_run: bool,
+    link_protected: bool,
+    undo_log: Option<&UndoLog>,
+) -> u64 {
+    let targets = organize_targets(duplicates, output_dir);
+    let moved: Vec<DuplicateGroup> = duplicates
+        .iter()
+        .zip(targets)
+        .filter_map(|(group, target)| {
+            let leader = group.paths.first()?;
+            if !link_protected && is_protected(leader) {
+                return None;
+            }
+            // A move across volumes would be a copy, and leave the leader
+            // where the other copies can't link to it
+            if on_different_volumes(Path::new(leader), &target) {
+                log::warn!(
+                    "Skipping {}: cannot move it to {} on another volume",
+                    leader,
+                    target.display()
+                );
+                return None;
+            }
+
+            if Path::new(leader) == target {
+                log::debug!("{} is in place already", leader);
+            } else if dry_run {
+                log::info!("[Dry run] Would move {} -> {}", leader, target.display());
+            } else {
+                log::info!("Moving {} -> {}", leader, target.display());
+                let moved = target
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|()| fs::rename(leader, &target));
+                if let Err(e) = moved {
+                    log::error!("Failed to move {} to {}: {}", leader, target.display(), e);
+                    return None;
+                }
+                if let Some(undo_log) = undo_log {
+                    undo_log.record(&UndoEntry {
+                        path: leader.clone(),
+                        leader: target.to_string_lossy().into_owned(),
+                        size: group.size,
+                        moved: true,
+                    });
+                }
+            }
+
+            let mut paths = group.paths.clone();
+            paths[0] = target.to_string_lossy().into_owned();
+            Some(DuplicateGroup {
+                paths,
+                ..group.clone()
+            })
+        })
+        .collect();
+
+    link_duplicates(&moved, kind, dry_run, link_protected, undo_log)
+}
+
+/// Whether `path` is still a hard or symbolic link to `leader`
+fn is_linked_to(path: &Path, leader: &Path) -> io::Result<bool> {
+    if fs::symlink_metadata(path)?.file_type().is_symlink() {
+        return Ok(fs::read_link(path)? == leader);
+    }
+    Ok(file_identity(path)? == file_identity(leader)?)
+}
+
+/// Turn `path` back into an independent copy of `leader`
+fn restore_copy(path: &Path, leader: &Path) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".ddup_undo");
+
+    fs::copy(leader, &tmp_path)?;
+    if let Err(e) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Move a file `organize` moved to `moved_to` back to `path`
+fn restore_move(path: &Path, moved_to: &Path) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(moved_to, path)
+}
+
+/// Revert the links and moves recorded in an undo log, last first, and
+/// return how many files were turned back into independent copies or moved
+/// back. Entries that were already reverted or whose files are gone are
+/// skipped, so running this twice is harmless.
+pub fn undo(log_path: &Path) -> io::Result<usize> {
+    let file = match fs::File::open(log_path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            log::warn!("Undo log {} does not exist", log_path.display());
+            return Ok(0);
+        }
+        Err(e) => return Err(e),
+    };
+
+    // Links to a moved file have to be reverted before it is moved back
+    let lines = io::BufReader::new(file)
+        .lines()
+        .collect::<io::Result<Vec<String>>>()?;
+    let mut restored = 0;
+    for line in lines.iter().rev() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let entry = match UndoEntry::deserialize_json(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("Skipping malformed undo log entry: {}", e);
+                continue;
+            }
+        };
+        let path = Path::new(&entry.path);
+        let leader = Path::new(&entry.leader);
+
+        if entry.moved {
+            if path.exists() || !leader.exists() {
+                log::debug!("{} was already moved back, skipping", entry.path);
+                continue;
+            }
+            log::info!("Moving {} back to {}", entry.leader, entry.path);
+            match restore_move(path, leader) {
+                Ok(()) => restored += 1,
+                Err(e) => log::error!("Failed to move back {}: {}", entry.leader, e),
+            }
+            continue;
+        }
+
+        match is_linked_to(path, leader) {
+            Ok(true) => {}
+            Ok(false) => {
+                log::debug!("{} is no longer linked, skipping", entry.path);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("Skipping {}: {}", entry.path, e);
+                continue;
+            }
+        }
+
+        log::info!("Restoring {} from {}", entry.path, entry.leader);
+        match restore_copy(path, leader) {
+            Ok(()) => restored += 1,
+            Err(e) => log::error!("Failed to restore {}: {}", entry.path, e),
+        }
+    }
+
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algorithm::Confidence;
+
+    #[test]
+    fn dry_run_touches_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = ["a.bin", "b.bin", "c.bin"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                fs::write(&path, b"duplicate").unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        let groups = vec![DuplicateGroup {
+            size: 9,
+            paths: paths.clone(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+
+        let before: Vec<_> = paths
+            .iter()
+            .map(|p| fs::metadata(p).unwrap().modified().unwrap())
+            .collect();
+
+        let freed = link_duplicates(&groups, LinkKind::Hard, true, false, None);
+
+        assert_eq!(freed, 2 * 9);
+        for (path, modified) in paths.iter().zip(before) {
+            let metadata = fs::metadata(path).unwrap();
+            assert_eq!(metadata.modified().unwrap(), modified);
+            assert_eq!(fs::read(path).unwrap(), b"duplicate");
+        }
+        let leftovers = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(leftovers, 3);
+    }
+
+    #[test]
+    fn keep_oldest_links_to_earliest_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let epoch = std::time::UNIX_EPOCH;
+        let ages = [("a.bin", 3000), ("b.bin", 1000), ("c.bin", 2000)];
+        let paths: Vec<String> = ages
+            .iter()
+            .map(|(name, seconds)| {
+                let path = dir.path().join(name);
+                fs::write(&path, b"duplicate").unwrap();
+                fs::File::options()
+                    .write(true)
+                    .open(&path)
+                    .unwrap()
+                    .set_modified(epoch + std::time::Duration::from_secs(*seconds))
+                    .unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect();
+        let mut groups = vec![DuplicateGroup {
+            size: 9,
+            paths: paths.clone(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+
+        apply_keep_policy(&mut groups, KeepPolicy::Oldest);
+        assert_eq!(groups[0].paths[0], paths[1]);
+        assert_eq!(groups[0].mtimes, Some(vec![1000, 3000, 2000]));
+
+        assert_eq!(
+            link_duplicates(&groups, LinkKind::Hard, false, false, None),
+            18
+        );
+        for path in &paths {
+            assert_eq!(
+                file_identity(Path::new(path)).unwrap(),
+                file_identity(Path::new(&paths[1])).unwrap()
+            );
+            let modified = fs::metadata(path).unwrap().modified().unwrap();
+            assert_eq!(modified, epoch + std::time::Duration::from_secs(1000));
+        }
+    }
+
+    #[test]
+    fn same_dir_only_links_copies_within_one_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("other")).unwrap();
+        let original = dir.path().join("photo.jpg");
+        let copy = dir.path().join("photo (1).jpg");
+        let elsewhere = dir.path().join("other").join("photo.jpg");
+        for path in [&original, &copy, &elsewhere] {
+            fs::write(path, b"duplicate").unwrap();
+        }
+        let groups = vec![DuplicateGroup {
+            size: 9,
+            paths: [&copy, &original, &elsewhere]
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            mtimes: Some(vec![2, 1, 3]),
+            confidence: Confidence::Exact,
+        }];
+
+        let split = split_by_directory(groups);
+        assert_eq!(split.len(), 1);
+        assert_eq!(split[0].mtimes, Some(vec![2, 1]));
+
+        assert_eq!(
+            link_duplicates(&split, LinkKind::Hard, false, false, None),
+            9
+        );
+        assert_eq!(
+            file_identity(&copy).unwrap(),
+            file_identity(&original).unwrap()
+        );
+        assert_ne!(
+            file_identity(&elsewhere).unwrap(),
+            file_identity(&original).unwrap()
+        );
+    }
+
+    #[test]
+    fn organize_moves_leader_and_links_copies_to_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let output = dir.path().join("library");
+        for folder in ["2024", "backup"] {
+            fs::create_dir_all(source.join(folder)).unwrap();
+        }
+        let leader = source.join("2024").join("photo.jpg");
+        let copy = source.join("backup").join("photo.jpg");
+        for path in [&leader, &copy] {
+            fs::write(path, b"duplicate").unwrap();
+        }
+        // Someone else's file already has the name the leader would get
+        fs::create_dir_all(output.join("2024")).unwrap();
+        fs::write(output.join("2024").join("photo.jpg"), b"unrelated").unwrap();
+
+        let groups = vec![DuplicateGroup {
+            size: 9,
+            paths: [&leader, &copy]
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+        assert_eq!(
+            organize(&groups, &output, LinkKind::Hard, false, false, None),
+            9
+        );
+
+        let moved = output.join("2024").join("photo (2).jpg");
+        assert!(!leader.exists());
+        assert_eq!(fs::read(&moved).unwrap(), b"duplicate");
+        assert_eq!(
+            file_identity(&copy).unwrap(),
+            file_identity(&moved).unwrap()
+        );
+        assert_eq!(
+            fs::read(output.join("2024").join("photo.jpg")).unwrap(),
+            b"unrelated"
+        );
+    }
+
+    #[test]
+    fn undo_moves_organized_files_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let output = dir.path().join("library");
+        fs::create_dir_all(source.join("backup")).unwrap();
+        let leader = source.join("photo.jpg");
+        let copy = source.join("backup").join("photo.jpg");
+        for path in [&leader, &copy] {
+            fs::write(path, b"duplicate").unwrap();
+        }
+        let log_path = dir.path().join("undo.jsonl");
+
+        let groups = vec![DuplicateGroup {
+            size: 9,
+            paths: [&leader, &copy]
+                .iter()
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+        let undo_log = UndoLog::open(&log_path).unwrap();
+        organize(
+            &groups,
+            &output,
+            LinkKind::Hard,
+            false,
+            false,
+            Some(&undo_log),
+        );
+        let moved = output.join("photo.jpg");
+        assert!(!leader.exists());
+
+        // The link and the move
+        assert_eq!(undo(&log_path).unwrap(), 2);
+        assert!(!moved.exists());
+        assert_eq!(fs::read(&leader).unwrap(), b"duplicate");
+        assert_eq!(fs::read(&copy).unwrap(), b"duplicate");
+        assert_ne!(
+            file_identity(&leader).unwrap(),
+            file_identity(&copy).unwrap()
+        );
+        assert_eq!(undo(&log_path).unwrap(), 0);
+    }
+
+    #[test]
+    fn shortest_path_prefers_shallow_directory() {
+        let mut groups = vec![DuplicateGroup {
+            size: 1,
+            paths: vec![
+                r"C:\a\b\c\x.bin".to_string(),
+                r"C:\data\longer name.bin".to_string(),
+                r"C:\z\y\x.bin".to_string(),
+            ],
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+        apply_keep_policy(&mut groups, KeepPolicy::ShortestPath);
+        assert_eq!(groups[0].paths[0], r"C:\data\longer name.bin");
+        assert_eq!(groups[0].paths[1], r"C:\a\b\c\x.bin");
+    }
+
+    #[test]
+    fn lowest_id_leader_survives_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let group_of = |names: [&str; 2]| {
+            vec![DuplicateGroup {
+                size: 9,
+                paths: names
+                    .iter()
+                    .map(|name| dir.path().join(name).to_string_lossy().to_string())
+                    .collect(),
+                mtimes: None,
+                confidence: Confidence::Exact,
+            }]
+        };
+        for name in ["a.bin", "b.bin"] {
+            fs::write(dir.path().join(name), b"duplicate").unwrap();
+        }
+
+        let mut groups = group_of(["a.bin", "b.bin"]);
+        apply_keep_policy(&mut groups, KeepPolicy::LowestId);
+        let leader = file_identity(Path::new(&groups[0].paths[0])).unwrap();
+        let other = file_identity(Path::new(&groups[0].paths[1])).unwrap();
+        assert!(leader.index < other.index);
+
+        // Swap the names, so the leader would flip if picked by path
+        fs::rename(dir.path().join("a.bin"), dir.path().join("tmp.bin")).unwrap();
+        fs::rename(dir.path().join("b.bin"), dir.path().join("a.bin")).unwrap();
+        fs::rename(dir.path().join("tmp.bin"), dir.path().join("b.bin")).unwrap();
+
+        let mut renamed = group_of(["a.bin", "b.bin"]);
+        apply_keep_policy(&mut renamed, KeepPolicy::LowestId);
+        assert_eq!(
+            file_identity(Path::new(&renamed[0].paths[0])).unwrap(),
+            leader
+        );
+        assert_ne!(renamed[0].paths[0], groups[0].paths[0]);
+    }
+
+    #[test]
+    fn read_only_duplicate_is_skipped_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let leader = dir.path().join("leader.bin");
+        let locked = dir.path().join("locked.bin");
+        fs::write(&leader, b"duplicate").unwrap();
+        fs::write(&locked, b"duplicate").unwrap();
+        let mut permissions = fs::metadata(&locked).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&locked, permissions).unwrap();
+
+        let groups = vec![DuplicateGroup {
+            size: 9,
+            paths: vec![
+                leader.to_string_lossy().to_string(),
+                locked.to_string_lossy().to_string(),
+            ],
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+
+        assert_eq!(
+            link_duplicates(&groups, LinkKind::Hard, false, false, None),
+            0
+        );
+        assert_ne!(
+            file_identity(&leader).unwrap(),
+            file_identity(&locked).unwrap()
+        );
+        assert!(!dir.path().join("locked.bin.ddup_link").exists());
+
+        assert_eq!(
+            link_duplicates(&groups, LinkKind::Hard, false, true, None),
+            9
+        );
+        assert_eq!(
+            file_identity(&leader).unwrap(),
+            file_identity(&locked).unwrap()
+        );
+    }
+
+    #[test]
+    fn recover_restores_orphaned_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+
+        // Killed after moving the duplicate away, before linking it
+        let orphaned = nested.join("photo.jpg");
+        fs::write(nested.join("photo.jpg.ddup_tmp"), b"precious").unwrap();
+        // Killed after linking, before removing the backup
+        let linked = dir.path().join("linked.bin");
+        fs::write(&linked, b"linked").unwrap();
+        fs::write(dir.path().join("linked.bin.ddup_tmp"), b"backup").unwrap();
+        // Killed before an unfinished link was renamed into place
+        let pending = dir.path().join("pending.bin");
+        fs::write(&pending, b"pending").unwrap();
+        fs::write(dir.path().join("pending.bin.ddup_link"), b"pending").unwrap();
+
+        assert_eq!(recover(dir.path()), 1);
+        assert_eq!(fs::read(&orphaned).unwrap(), b"precious");
+        assert!(!nested.join("photo.jpg.ddup_tmp").exists());
+        assert_eq!(fs::read(&linked).unwrap(), b"linked");
+        assert!(dir.path().join("linked.bin.ddup_tmp").exists());
+        assert_eq!(fs::read(&pending).unwrap(), b"pending");
+        assert!(!dir.path().join("pending.bin.ddup_link").exists());
+
+        assert_eq!(recover(dir.path()), 0);
+    }
+
+    #[test]
+    fn failed_relink_leaves_original_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let leader = dir.path().join("leader.bin");
+        let path = dir.path().join("copy.bin");
+        fs::write(&leader, b"duplicate").unwrap();
+        fs::write(&path, b"duplicate").unwrap();
+        let before = file_identity(&path).unwrap();
+
+        // Fail after partially writing the link
+        let result = relink_with(&leader, &path, |_, link| {
+            fs::write(link, b"dup")?;
+            Err(io::Error::other("injected failure"))
+        });
+        assert!(result.is_err());
+        assert_eq!(file_identity(&path).unwrap(), before);
+        assert_eq!(fs::read(&path).unwrap(), b"duplicate");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        // A leader that is gone fails the same way
+        let missing = dir.path().join("missing.bin");
+        assert!(relink_safely(
+            missing.to_str().unwrap(),
+            path.to_str().unwrap(),
+            LinkKind::Hard
+        )
+        .is_err());
+        assert_eq!(file_identity(&path).unwrap(), before);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        relink_safely(
+            leader.to_str().unwrap(),
+            path.to_str().unwrap(),
+            LinkKind::Hard,
+        )
+        .unwrap();
+        assert_eq!(
+            file_identity(&path).unwrap(),
+            file_identity(&leader).unwrap()
+        );
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn undo_restores_independent_copies() {
+        let dir = tempfile::tempdir().unwrap();
+        let leader = dir.path().join("leader.bin");
+        let copy = dir.path().join("copy.bin");
+        fs::write(&leader, b"duplicate").unwrap();
+        fs::write(&copy, b"duplicate").unwrap();
+        let log_path = dir.path().join("undo.jsonl");
+
+        let groups = vec![DuplicateGroup {
+            size: 9,
+            paths: vec![
+                leader.to_string_lossy().to_string(),
+                copy.to_string_lossy().to_string(),
+            ],
+            mtimes: None,
+            confidence: Confidence::Exact,
+        }];
+        let undo_log = UndoLog::open(&log_path).unwrap();
+        assert_eq!(
+            link_duplicates(&groups, LinkKind::Hard, false, false, Some(&undo_log)),
+            9
+        );
+        assert_eq!(
+            file_identity(&leader).unwrap(),
+            file_identity(&copy).unwrap()
+        );
+
+        assert_eq!(undo(&log_path).unwrap(), 1);
+        assert_ne!(
+            file_identity(&leader).unwrap(),
+            file_identity(&copy).unwrap()
+        );
+        assert_eq!(fs::read(&copy).unwrap(), b"duplicate");
+
+        // Already reverted entries are left alone
+        assert_eq!(undo(&log_path).unwrap(), 0);
+        assert_eq!(undo(&dir.path().join("missing.jsonl")).unwrap(), 0);
+    }
+
+    #[test]
+    fn volume_comparison_detects_different_drives() {
+        assert!(on_different_volumes(
+            Path::new(r"C:\data\a.bin"),
+            Path::new(r"D:\data\a.bin")
+        ));
+        assert!(!on_different_volumes(
+            Path::new(r"C:\data\a.bin"),
+            Path::new(r"c:\other\b.bin")
+        ));
+        assert!(!on_different_volumes(
+            Path::new(r"\\?\C:\data\a.bin"),
+            Path::new(r"C:\other\b.bin")
+        ));
+        assert!(on_different_volumes(
+            Path::new(r"\\server\share\a.bin"),
+            Path::new(r"C:\a.bin")
+        ));
+        assert!(!on_different_volumes(
+            Path::new(r"relative\a.bin"),
+            Path::new(r"C:\a.bin")
+        ));
+    }
+
+    #[test]
+    fn symlink_resolves_to_leader() {
+        let dir = tempfile::tempdir().unwrap();
+        let leader = dir.path().join("leader.bin");
+        let copy = dir.path().join("copy.bin");
+        fs::write(&leader, b"duplicate").unwrap();
+        fs::write(&copy, b"duplicate").unwrap();
+
+        // Symlinks need elevation or Developer Mode, skip when neither is available
+        let probe = dir.path().join("probe.lnk");
+        if symlink_file(&leader, &probe).is_err() {
+            return;
+        }
+
+        let leader = leader.to_string_lossy().to_string();
+        let copy = copy.to_string_lossy().to_string();
+        assert!(replace_with_link(&leader, &copy, LinkKind::Symbolic));
+
+        let metadata = fs::symlink_metadata(&copy).unwrap();
+        assert!(metadata.file_type().is_symlink());
+        assert_eq!(fs::read_link(&copy).unwrap(), Path::new(&leader));
+        assert_eq!(fs::read(&copy).unwrap(), b"duplicate");
+    }
+}
@@ -0,0 +1,800 @@
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::io::{Error, ErrorKind};
+use std::path::PathBuf;
+
+use rayon::prelude::*;
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::um::fileapi::ReadFile;
+use winapi::um::minwinbase::OVERLAPPED;
+
+use super::utils::filetime_to_unix_seconds;
+use super::volume::Volume;
+
+/// MFT record numbers of metadata files: the volume root, and `$Extend`
+/// whose children such as `$UsnJrnl` are no user files
+const ROOT_RECORD_NUMBER: u64 = 5;
+const EXTEND_RECORD_NUMBER: u64 = 11;
+/// Records below this are reserved for metadata files
+const FIRST_USER_RECORD: u64 = 16;
+
+const ATTRIBUTE_STANDARD_INFORMATION: u32 = 0x10;
+const ATTRIBUTE_LIST: u32 = 0x20;
+const ATTRIBUTE_FILE_NAME: u32 = 0x30;
+const ATTRIBUTE_DATA: u32 = 0x80;
+const ATTRIBUTE_END: u32 = 0xFFFF_FFFF;
+
+const RECORD_IN_USE: u16 = 0x01;
+const RECORD_IS_DIRECTORY: u16 = 0x02;
+
+/// Short 8.3 names, only used when a file has no other name
+const NAMESPACE_DOS: u8 = 2;
+
+/// The update sequence protects every 512 bytes of a record, whatever the
+/// sector size
+const FIXUP_STRIDE: usize = 512;
+
+/// Bytes read from the volume at a time
+const READ_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// A run of clusters of a non-resident attribute, `(first cluster, count)`
+type Extent = (Option<u64>, u64);
+
+/// Hands out raw bytes of a volume, or of a synthetic one in tests
+trait VolumeReader {
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Error>;
+}
+
+impl VolumeReader for Volume {
+    fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut overlapped: OVERLAPPED = unsafe { std::mem::zeroed() };
+        unsafe {
+            let position = overlapped.u.s_mut();
+            position.Offset = offset as DWORD;
+            position.OffsetHigh = (offset >> 32) as DWORD;
+        }
+        let mut read: DWORD = 0;
+
+        let res = unsafe {
+            ReadFile(
+                self.handle,
+                buffer.as_mut_ptr() as LPVOID,
+                buffer.len() as DWORD,
+                &mut read,
+                &mut overlapped,
+            )
+        };
+
+        match res {
+            0 => Err(Error::last_os_error()),
+            _ => Ok(read as usize),
+        }
+    }
+}
+
+fn read_exact_at(reader: &dyn VolumeReader, offset: u64, buffer: &mut [u8]) -> Result<(), Error> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read_at(offset + filled as u64, &mut buffer[filled..])? {
+            0 => return Err(ErrorKind::UnexpectedEof.into()),
+            read => filled += read,
+        }
+    }
+    Ok(())
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// Where the MFT starts and how it is laid out, from the NTFS boot sector
+#[derive(Debug, PartialEq, Eq)]
+struct Geometry {
+    cluster_size: u64,
+    record_size: usize,
+    mft_offset: u64,
+}
+
+fn parse_boot_sector(sector: &[u8]) -> Result<Geometry, Error> {
+    if sector.get(3..11) != Some(&b"NTFS    "[..]) {
+        return Err(invalid("not an NTFS volume"));
+    }
+    let bytes_per_sector = u16_at(sector, 0x0B).unwrap_or(0) as u64;
+    let sectors_per_cluster = sector[0x0D] as u64;
+    let cluster_size = bytes_per_sector * sectors_per_cluster;
+    let mft_cluster = u64_at(sector, 0x30).unwrap_or(0);
+    // Negative values give the record size as a power of two
+    let record_size = match sector[0x40] as i8 {
+        clusters if clusters > 0 => clusters as u64 * cluster_size,
+        shift => 1u64.checked_shl(shift.unsigned_abs() as u32).unwrap_or(0),
+    };
+    if cluster_size == 0 || record_size < FIXUP_STRIDE as u64 || record_size > 64 * 1024 {
+        return Err(invalid("NTFS boot sector has an unexpected geometry"));
+    }
+
+    Ok(Geometry {
+        cluster_size,
+        record_size: record_size as usize,
+        mft_offset: mft_cluster * cluster_size,
+    })
+}
+
+/// Undo the update sequence NTFS writes over the last two bytes of every
+/// 512 byte block, failing on a record that was torn while being written
+fn apply_fixups(record: &mut [u8]) -> Option<()> {
+    let usa_offset = u16_at(record, 4)? as usize;
+    let usa_count = u16_at(record, 6)? as usize;
+    if usa_count == 0 || (usa_count - 1) * FIXUP_STRIDE > record.len() {
+        return None;
+    }
+    let check = u16_at(record, usa_offset)?;
+    for block in 1..usa_count {
+        let end = block * FIXUP_STRIDE - 2;
+        if u16_at(record, end)? != check {
+            return None;
+        }
+        let original = u16_at(record, usa_offset + 2 * block)?;
+        record[end..end + 2].copy_from_slice(&original.to_le_bytes());
+    }
+    Some(())
+}
+
+/// Walk the attributes of a fixed-up record as `(type, bytes)`
+fn attributes(record: &[u8]) -> impl Iterator<Item = (u32, &[u8])> + '_ {
+    let end = u32_at(record, 0x18).map_or(0, |used| (used as usize).min(record.len()));
+    let mut offset = u16_at(record, 0x14).map_or(end, usize::from);
+    std::iter::from_fn(move || {
+        let kind = u32_at(record, offset)?;
+        if kind == ATTRIBUTE_END {
+            return None;
+        }
+        let length = u32_at(record, offset + 4)? as usize;
+        if length < 0x18 || offset + length > end {
+            return None;
+        }
+        let attribute = &record[offset..offset + length];
+        offset += length;
+        Some((kind, attribute))
+    })
+}
+
+fn is_resident(attribute: &[u8]) -> bool {
+    attribute.get(8) == Some(&0)
+}
+
+/// Named attributes are alternate streams or index data, not the file's
+/// own content
+fn is_unnamed(attribute: &[u8]) -> bool {
+    attribute.get(9) == Some(&0)
+}
+
+fn resident_value(attribute: &[u8]) -> Option<&[u8]> {
+    if !is_resident(attribute) {
+        return None;
+    }
+    let length = u32_at(attribute, 0x10)? as usize;
+    let offset = u16_at(attribute, 0x14)? as usize;
+    attribute.get(offset..offset.checked_add(length)?)
+}
+
+/// Logical size of a `$DATA` attribute. Only the first fragment of a
+/// non-resident attribute carries it.
+fn data_size(attribute: &[u8]) -> Option<u64> {
+    if is_resident(attribute) {
+        return resident_value(attribute).map(|value| value.len() as u64);
+    }
+    match u64_at(attribute, 0x10)? {
+        0 => u64_at(attribute, 0x30),
+        _ => None,
+    }
+}
+
+/// Little-endian integer of `bytes.len()` bytes, sign extended if `signed`
+fn read_varint(bytes: &[u8], signed: bool) -> i64 {
+    let mut value: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    let bits = 8 * bytes.len() as u32;
+    if signed && bits > 0 && bits < 64 && bytes[bytes.len() - 1] & 0x80 != 0 {
+        value -= 1 << bits;
+    }
+    value
+}
+
+/// Decode the mapping pairs of a non-resident attribute into extents of
+/// `(first cluster, cluster count)`. Sparse extents have no first cluster.
+fn decode_runs(runs: &[u8]) -> Option<Vec<Extent>> {
+    let mut extents = Vec::new();
+    let mut lcn: i64 = 0;
+    let mut pos = 0;
+    loop {
+        let header = *runs.get(pos)?;
+        if header == 0 {
+            return Some(extents);
+        }
+        let length_size = (header & 0x0F) as usize;
+        let offset_size = (header >> 4) as usize;
+        pos += 1;
+
+        let length = read_varint(runs.get(pos..pos + length_size)?, false) as u64;
+        pos += length_size;
+        let start = match offset_size {
+            0 => None,
+            _ => {
+                // Each offset is relative to the previous extent
+                lcn += read_varint(runs.get(pos..pos + offset_size)?, true);
+                Some(u64::try_from(lcn).ok()?)
+            }
+        };
+        pos += offset_size;
+        extents.push((start, length));
+    }
+}
+
+/// A file or directory from one in-use MFT record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MftRecord {
+    /// File reference number, the record number with its sequence number
+    /// in the top 16 bits, as used by parent references
+    pub reference: u64,
+    pub parent: u64,
+    pub name: String,
+    pub is_directory: bool,
+    /// Win32 attributes from `$STANDARD_INFORMATION`
+    pub attributes: u32,
+    /// Modification time from `$STANDARD_INFORMATION`, seconds since the
+    /// Unix epoch
+    pub mtime: Option<u64>,
+    /// Size of the unnamed data stream, `None` when it lives in an extension
+    /// record that is not parsed
+    pub size: Option<u64>,
+}
+
+/// Parse the record at `number` in place, or `None` for free, extension and
+/// damaged records
+fn parse_record(number: u64, record: &mut [u8]) -> Option<MftRecord> {
+    if record.get(0..4) != Some(&b"FILE"[..]) {
+        return None;
+    }
+    apply_fixups(record)?;
+    let flags = u16_at(record, 0x16)?;
+    // Extension records hold overflow attributes of a base record
+    if flags & RECORD_IN_USE == 0 || u64_at(record, 0x20)? != 0 {
+        return None;
+    }
+    let sequence = u16_at(record, 0x10)? as u64;
+
+    let mut attributes_value = 0;
+    let mut mtime = None;
+    let mut has_list = false;
+    let mut name: Option<(u8, u64, String)> = None;
+    let mut size = None;
+    for (kind, attribute) in attributes(record) {
+        match kind {
+            ATTRIBUTE_STANDARD_INFORMATION => {
+                if let Some(value) = resident_value(attribute) {
+                    attributes_value = u32_at(value, 0x20).unwrap_or(0);
+                    mtime = u64_at(value, 0x08).and_then(filetime_to_unix_seconds);
+                }
+            }
+            ATTRIBUTE_LIST => has_list = true,
+            ATTRIBUTE_FILE_NAME => {
+                let Some(value) = resident_value(attribute) else {
+                    continue;
+                };
+                let (Some(parent), Some(&length), Some(&namespace)) =
+                    (u64_at(value, 0), value.get(0x40), value.get(0x41))
+                else {
+                    continue;
+                };
+                let Some(units) = value.get(0x42..0x42 + 2 * length as usize) else {
+                    continue;
+                };
+                // A hardlinked file has several names, keep the first that
+                // isn't a short 8.3 alias
+                let better = match &name {
+                    None => true,
+                    Some((kept, _, _)) => *kept == NAMESPACE_DOS && namespace != NAMESPACE_DOS,
+                };
+                if better {
+                    let units: Vec<u16> = units
+                        .chunks_exact(2)
+                        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                        .collect();
+                    name = Some((namespace, parent, String::from_utf16_lossy(&units)));
+                }
+            }
+            ATTRIBUTE_DATA if is_unnamed(attribute) && size.is_none() => {
+                size = data_size(attribute);
+            }
+            _ => {}
+        }
+    }
+
+    let (_, parent, name) = name?;
+    let is_directory = flags & RECORD_IS_DIRECTORY != 0;
+    Some(MftRecord {
+        reference: number | (sequence << 48),
+        parent,
+        name,
+        is_directory,
+        attributes: attributes_value,
+        mtime,
+        // An attribute list means parts of the record live elsewhere
+        size: if has_list && size.is_none() {
+            None
+        } else if is_directory {
+            Some(0)
+        } else {
+            size
+        },
+    })
+}
+
+/// Extents of the MFT and its size in bytes, from the `$DATA` attribute of
+/// its own first record
+fn mft_layout(record: &mut [u8]) -> Option<(Vec<Extent>, u64)> {
+    if record.get(0..4) != Some(&b"FILE"[..]) {
+        return None;
+    }
+    apply_fixups(record)?;
+    let (_, data) = attributes(record)
+        .find(|(kind, attribute)| *kind == ATTRIBUTE_DATA && is_unnamed(attribute))?;
+    if is_resident(data) {
+        return None;
+    }
+    let runs_offset = u16_at(data, 0x20)? as usize;
+    Some((decode_runs(data.get(runs_offset..)?)?, data_size(data)?))
+}
+
+/// Read every in-use record of the MFT straight off the volume. Only the
+/// base record of each file is parsed, see `MftRecord::size`.
+fn read_records(reader: &dyn VolumeReader) -> Result<Vec<MftRecord>, Error> {
+    let mut boot = vec![0u8; 4096];
+    read_exact_at(reader, 0, &mut boot)?;
+    let geometry = parse_boot_sector(&boot)?;
+    let record_size = geometry.record_size;
+
+    let mut first = vec![0u8; (geometry.cluster_size as usize).max(record_size)];
+    read_exact_at(reader, geometry.mft_offset, &mut first)?;
+    let (extents, mft_size) = mft_layout(&mut first[..record_size])
+        .ok_or_else(|| invalid("the first MFT record is damaged"))?;
+    let record_count = mft_size / record_size as u64;
+
+    let chunk_clusters = (READ_CHUNK_SIZE / geometry.cluster_size).max(1);
+    let mut records = Vec::new();
+    // Bytes of a record cut off at the end of the previous read
+    let mut pending: Vec<u8> = Vec::new();
+    let mut number = 0u64;
+    'extents: for (start, clusters) in extents {
+        let mut done = 0;
+        while done < clusters {
+            let count = (clusters - done).min(chunk_clusters);
+            let offset = pending.len();
+            pending.resize(offset + (count * geometry.cluster_size) as usize, 0);
+            if let Some(start) = start {
+                let position = (start + done) * geometry.cluster_size;
+                read_exact_at(reader, position, &mut pending[offset..])?;
+            }
+            done += count;
+
+            let whole = pending.len() / record_size * record_size;
+            let remaining = (record_count - number) as usize;
+            let usable = whole.min(remaining * record_size);
+            let first_number = number;
+            records.par_extend(
+                pending[..usable]
+                    .par_chunks_mut(record_size)
+                    .enumerate()
+                    .filter_map(|(i, record)| {
+                        let number = first_number + i as u64;
+                        if number < FIRST_USER_RECORD && number != ROOT_RECORD_NUMBER {
+                            return None;
+                        }
+                        parse_record(number, record)
+                    }),
+            );
+            number += (usable / record_size) as u64;
+            pending.drain(..whole);
+            if number >= record_count {
+                break 'extents;
+            }
+        }
+    }
+
+    Ok(records)
+}
+
+/// Every file and directory on `volume` read from its MFT, which needs no
+/// USN journal but the same privileges
+pub fn read_mft(volume: &Volume) -> Result<Vec<MftRecord>, Error> {
+    read_records(volume)
+}
+
+/// A file from the MFT, with its path relative to the volume root
+pub struct MftFile {
+    pub path: PathBuf,
+    pub size: Option<u64>,
+    pub attributes: u32,
+    pub mtime: Option<u64>,
+}
+
+pub struct ResolvedFiles {
+    pub files: Vec<MftFile>,
+    /// Files whose parent directory was not found
+    pub orphans: usize,
+}
+
+/// Join each file's name onto the path of its parent directories. Files
+/// under `$Extend` are metadata and left out.
+pub fn resolve_files(records: Vec<MftRecord>) -> ResolvedFiles {
+    let mut directories: HashMap<u64, (u64, String)> = HashMap::new();
+    let mut files = Vec::new();
+    for record in records {
+        if record.is_directory {
+            directories.insert(record.reference, (record.parent, record.name));
+        } else {
+            files.push(record);
+        }
+    }
+
+    // `None` marks directories that can't be placed under the root
+    let mut resolved: HashMap<u64, Option<PathBuf>> = HashMap::new();
+    let mut resolve = |reference: u64| -> Option<PathBuf> {
+        let mut chain = Vec::new();
+        let mut current = reference;
+        let base = loop {
+            if let Some(path) = resolved.get(&current) {
+                break path.clone();
+            }
+            match current & 0x0000_FFFF_FFFF_FFFF {
+                ROOT_RECORD_NUMBER => break Some(PathBuf::new()),
+                EXTEND_RECORD_NUMBER => break None,
+                _ => {}
+            }
+            // A loop in corrupt data would never reach the root
+            if chain.len() > 4096 {
+                break None;
+            }
+            match directories.get(&current) {
+                Some((parent, _)) => {
+                    chain.push(current);
+                    current = *parent;
+                }
+                None => break None,
+            }
+        };
+
+        let mut path = base;
+        for directory in chain.into_iter().rev() {
+            path = path.map(|path| path.join(&directories[&directory].1));
+            resolved.insert(directory, path.clone());
+        }
+        path
+    };
+
+    let mut orphans = 0;
+    let files = files
+        .into_iter()
+        .filter_map(|record| {
+            let parent = resolve(record.parent);
+            if parent.is_none() && record.parent & 0x0000_FFFF_FFFF_FFFF != EXTEND_RECORD_NUMBER {
+                orphans += 1;
+            }
+            Some(MftFile {
+                path: parent?.join(&record.name),
+                size: record.size,
+                attributes: record.attributes,
+                mtime: record.mtime,
+            })
+        })
+        .collect();
+
+    ResolvedFiles { files, orphans }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD_SIZE: usize = 1024;
+
+    /// Content of a test attribute
+    enum Value<'a> {
+        Resident(&'a [u8]),
+        /// Mapping pairs and logical size
+        NonResident(&'a [u8], u64),
+    }
+
+    fn attribute(kind: u32, value: Value) -> Vec<u8> {
+        let mut bytes = match value {
+            Value::Resident(value) => {
+                let mut bytes = vec![0u8; 0x18];
+                bytes[0x10..0x14].copy_from_slice(&(value.len() as u32).to_le_bytes());
+                bytes[0x14..0x16].copy_from_slice(&0x18u16.to_le_bytes());
+                bytes.extend_from_slice(value);
+                bytes
+            }
+            Value::NonResident(runs, size) => {
+                let mut bytes = vec![0u8; 0x40];
+                bytes[8] = 1;
+                bytes[0x20..0x22].copy_from_slice(&0x40u16.to_le_bytes());
+                bytes[0x28..0x30].copy_from_slice(&size.to_le_bytes());
+                bytes[0x30..0x38].copy_from_slice(&size.to_le_bytes());
+                bytes[0x38..0x40].copy_from_slice(&size.to_le_bytes());
+                bytes.extend_from_slice(runs);
+                bytes
+            }
+        };
+        bytes.resize(bytes.len().div_ceil(8) * 8, 0);
+        bytes[0..4].copy_from_slice(&kind.to_le_bytes());
+        let length = bytes.len() as u32;
+        bytes[4..8].copy_from_slice(&length.to_le_bytes());
+        bytes
+    }
+
+    fn file_name(parent: u64, name: &str, namespace: u8) -> Vec<u8> {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let mut value = vec![0u8; 0x42];
+        value[0..8].copy_from_slice(&parent.to_le_bytes());
+        value[0x40] = units.len() as u8;
+        value[0x41] = namespace;
+        for unit in units {
+            value.extend_from_slice(&unit.to_le_bytes());
+        }
+        attribute(ATTRIBUTE_FILE_NAME, Value::Resident(&value))
+    }
+
+    fn standard_information(attributes: u32, modified: u64) -> Vec<u8> {
+        let mut value = vec![0u8; 0x48];
+        value[0x08..0x10].copy_from_slice(&modified.to_le_bytes());
+        value[0x20..0x24].copy_from_slice(&attributes.to_le_bytes());
+        attribute(ATTRIBUTE_STANDARD_INFORMATION, Value::Resident(&value))
+    }
+
+    /// A FILE record as it is on disk, update sequence applied
+    fn record(sequence: u16, flags: u16, attributes: &[Vec<u8>]) -> Vec<u8> {
+        let mut record = vec![0u8; RECORD_SIZE];
+        record[0..4].copy_from_slice(b"FILE");
+        record[4..6].copy_from_slice(&0x30u16.to_le_bytes());
+        record[6..8].copy_from_slice(&3u16.to_le_bytes());
+        record[0x10..0x12].copy_from_slice(&sequence.to_le_bytes());
+        record[0x14..0x16].copy_from_slice(&0x38u16.to_le_bytes());
+        record[0x16..0x18].copy_from_slice(&flags.to_le_bytes());
+
+        let mut offset = 0x38;
+        for attribute in attributes {
+            record[offset..offset + attribute.len()].copy_from_slice(attribute);
+            offset += attribute.len();
+        }
+        record[offset..offset + 4].copy_from_slice(&ATTRIBUTE_END.to_le_bytes());
+        let used = (offset + 8) as u32;
+        record[0x18..0x1C].copy_from_slice(&used.to_le_bytes());
+        record[0x1C..0x20].copy_from_slice(&(RECORD_SIZE as u32).to_le_bytes());
+
+        // Move the last two bytes of each block into the update sequence
+        let check = 0x0042u16.to_le_bytes();
+        record[0x30..0x32].copy_from_slice(&check);
+        for block in 1..3 {
+            let end = block * FIXUP_STRIDE - 2;
+            let saved = [record[end], record[end + 1]];
+            record[0x30 + 2 * block..0x32 + 2 * block].copy_from_slice(&saved);
+            record[end..end + 2].copy_from_slice(&check);
+        }
+        record
+    }
+
+    const ROOT: u64 = ROOT_RECORD_NUMBER | (5 << 48);
+
+    #[test]
+    fn record_blob_parses_into_name_and_size() {
+        let mut blob = record(
+            3,
+            RECORD_IN_USE,
+            &[
+                standard_information(0x20, 133_485_408_000_000_000),
+                file_name(ROOT, "REPORT~1.PDF", NAMESPACE_DOS),
+                file_name(ROOT, "Quarterly report.pdf", 1),
+                attribute(
+                    ATTRIBUTE_DATA,
+                    Value::NonResident(&[0x21, 0x10, 0x00, 0x01, 0x00], 123_456),
+                ),
+            ],
+        );
+
+        let parsed = parse_record(40, &mut blob).unwrap();
+
+        assert_eq!(
+            parsed,
+            MftRecord {
+                reference: 40 | (3 << 48),
+                parent: ROOT,
+                name: "Quarterly report.pdf".to_string(),
+                is_directory: false,
+                attributes: 0x20,
+                mtime: Some(1_704_067_200),
+                size: Some(123_456),
+            }
+        );
+    }
+
+    #[test]
+    fn resident_data_and_free_records() {
+        let attributes = [
+            file_name(ROOT, "notes.txt", 1),
+            attribute(ATTRIBUTE_DATA, Value::Resident(b"hello, world")),
+        ];
+        let mut blob = record(1, RECORD_IN_USE, &attributes);
+        assert_eq!(parse_record(41, &mut blob).unwrap().size, Some(12));
+
+        let mut free = record(1, 0, &attributes);
+        assert_eq!(parse_record(41, &mut free), None);
+
+        // A torn write leaves a block without the update sequence number
+        let mut torn = record(1, RECORD_IN_USE, &attributes);
+        torn[FIXUP_STRIDE - 2] ^= 0xFF;
+        assert_eq!(parse_record(41, &mut torn), None);
+    }
+
+    #[test]
+    fn mapping_pairs_decode_to_extents() {
+        let runs = [
+            0x21, 0x18, 0x34, 0x56, // 0x18 clusters at 0x5634
+            0x11, 0x08, 0xF0, // 8 clusters, 0x10 back
+            0x01, 0x04, // 4 sparse clusters
+            0x00,
+        ];
+        assert_eq!(
+            decode_runs(&runs),
+            Some(vec![
+                (Some(0x5634), 0x18),
+                (Some(0x5624), 0x08),
+                (None, 0x04)
+            ])
+        );
+        assert_eq!(decode_runs(&[0x21, 0x18]), None);
+    }
+
+    struct SyntheticVolume(Vec<u8>);
+
+    impl VolumeReader for SyntheticVolume {
+        fn read_at(&self, offset: u64, buffer: &mut [u8]) -> Result<usize, Error> {
+            let start = (offset as usize).min(self.0.len());
+            let read = buffer.len().min(self.0.len() - start);
+            buffer[..read].copy_from_slice(&self.0[start..start + read]);
+            Ok(read)
+        }
+    }
+
+    #[test]
+    fn synthetic_volume_lists_files_with_paths() {
+        const CLUSTER: usize = 4096;
+        const MFT_CLUSTER: usize = 4;
+        const RECORDS: usize = 48;
+        let mft_clusters = RECORDS * RECORD_SIZE / CLUSTER;
+
+        let mut volume = vec![0u8; (MFT_CLUSTER + mft_clusters) * CLUSTER];
+        volume[3..11].copy_from_slice(b"NTFS    ");
+        volume[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        volume[0x0D] = 8;
+        volume[0x30..0x38].copy_from_slice(&(MFT_CLUSTER as u64).to_le_bytes());
+        volume[0x40] = (-10i8) as u8;
+
+        let directory = RECORD_IN_USE | RECORD_IS_DIRECTORY;
+        let runs = [0x11, mft_clusters as u8, MFT_CLUSTER as u8, 0x00];
+        let mft_size = (RECORDS * RECORD_SIZE) as u64;
+        let photos = 30 | (2 << 48);
+        let records = [
+            (
+                0,
+                record(
+                    1,
+                    RECORD_IN_USE,
+                    &[
+                        file_name(ROOT, "$MFT", 3),
+                        attribute(ATTRIBUTE_DATA, Value::NonResident(&runs, mft_size)),
+                    ],
+                ),
+            ),
+            (5, record(5, directory, &[file_name(ROOT, ".", 3)])),
+            (11, record(11, directory, &[file_name(ROOT, "$Extend", 3)])),
+            (
+                24,
+                record(
+                    1,
+                    RECORD_IN_USE,
+                    &[file_name(EXTEND_RECORD_NUMBER | (11 << 48), "$UsnJrnl", 3)],
+                ),
+            ),
+            (30, record(2, directory, &[file_name(ROOT, "Photos", 1)])),
+            (
+                31,
+                record(
+                    1,
+                    RECORD_IN_USE,
+                    &[
+                        file_name(photos, "a.jpg", 1),
+                        attribute(
+                            ATTRIBUTE_DATA,
+                            Value::NonResident(&[0x11, 0x01, 0x20, 0x00], 5000),
+                        ),
+                    ],
+                ),
+            ),
+            (
+                32,
+                record(
+                    1,
+                    RECORD_IN_USE,
+                    &[
+                        file_name(ROOT, "top.txt", 1),
+                        attribute(ATTRIBUTE_DATA, Value::Resident(b"top")),
+                    ],
+                ),
+            ),
+            // Parent whose sequence number no longer matches
+            (
+                33,
+                record(
+                    1,
+                    RECORD_IN_USE,
+                    &[
+                        file_name(30 | (1 << 48), "stale.txt", 1),
+                        attribute(ATTRIBUTE_DATA, Value::Resident(b"stale")),
+                    ],
+                ),
+            ),
+            // Past the end of the MFT's data
+            (
+                RECORDS,
+                record(1, RECORD_IN_USE, &[file_name(ROOT, "beyond.txt", 1)]),
+            ),
+        ];
+        volume.resize(volume.len() + RECORD_SIZE, 0);
+        for (number, record) in records {
+            let offset = MFT_CLUSTER * CLUSTER + number * RECORD_SIZE;
+            volume[offset..offset + RECORD_SIZE].copy_from_slice(&record);
+        }
+
+        let records = read_records(&SyntheticVolume(volume)).unwrap();
+        let resolved = resolve_files(records);
+
+        let mut files: Vec<(PathBuf, Option<u64>)> = resolved
+            .files
+            .into_iter()
+            .map(|file| (file.path, file.size))
+            .collect();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                (PathBuf::from("Photos").join("a.jpg"), Some(5000)),
+                (PathBuf::from("top.txt"), Some(3)),
+            ]
+        );
+        assert_eq!(resolved.orphans, 1);
+    }
+
+    #[test]
+    fn boot_sector_of_another_file_system_is_rejected() {
+        let mut sector = vec![0u8; 512];
+        sector[3..11].copy_from_slice(b"EXFAT   ");
+        assert_eq!(
+            parse_boot_sector(&sector).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+}
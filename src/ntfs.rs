@@ -2,13 +2,15 @@ use std::io::Error;
 use std::ptr::null_mut;
 
 use super::volume::Volume;
-use super::winioctl::{CREATE_USN_JOURNAL_DATA, MFT_ENUM_DATA, USN_JOURNAL_DATA, USN_RECORD};
+use super::winioctl::{
+    CREATE_USN_JOURNAL_DATA, MFT_ENUM_DATA, READ_USN_JOURNAL_DATA, USN_JOURNAL_DATA, USN_RECORD,
+};
 
 use winapi::shared::minwindef::{DWORD, LPDWORD, LPVOID};
 use winapi::shared::ntdef::USN;
 use winapi::um::ioapiset::DeviceIoControl;
 use winapi::um::winioctl::{
-    FSCTL_CREATE_USN_JOURNAL, FSCTL_ENUM_USN_DATA, FSCTL_QUERY_USN_JOURNAL,
+    FSCTL_CREATE_USN_JOURNAL, FSCTL_ENUM_USN_DATA, FSCTL_QUERY_USN_JOURNAL, FSCTL_READ_USN_JOURNAL,
 };
 use winapi::um::winnt::FILE_ATTRIBUTE_DIRECTORY;
 
@@ -23,6 +25,8 @@ pub struct UsnRecord {
     pub parent_id: u64,
     pub record_type: UsnRecordType,
     pub filename: String,
+    /// Change reason flags, always zero for records from MFT enumeration
+    pub reason: u32,
 }
 
 pub struct UsnRange {
@@ -30,63 +34,162 @@ pub struct UsnRange {
     pub high: USN,
 }
 
-const BUFFER_SIZE: usize = 1024 * 64;
+/// Bytes read per control call unless `with_chunk_size` says otherwise
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 64;
+/// Room for the cursor and the largest record, one with a 255 character name
+const MIN_CHUNK_SIZE: usize = 1024;
+
+/// Where the iterator reads records from and how far it has got
+enum UsnSource {
+    /// Every file currently on the volume, keyed by file reference number
+    Mft { next_reference: u64 },
+    /// Changes recorded in the journal, keyed by USN, limited to records
+    /// with any of the `reason_mask` bits set
+    Journal {
+        journal_id: u64,
+        next_usn: USN,
+        reason_mask: u32,
+    },
+}
+
+/// Answers the control codes records are read with, a volume or a
+/// synthetic journal in tests
+trait UsnDevice {
+    fn control(&self, code: DWORD, input: &[u8], output: &mut [u8]) -> Result<usize, Error>;
+}
+
+impl UsnDevice for Volume {
+    fn control(&self, code: DWORD, input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+        let mut returned_bytes: u32 = 0;
+
+        let res = unsafe {
+            DeviceIoControl(
+                self.handle,
+                code,
+                input.as_ptr() as LPVOID,
+                input.len() as DWORD,
+                output.as_mut_ptr() as LPVOID,
+                output.len() as DWORD,
+                &mut returned_bytes as LPDWORD,
+                null_mut(),
+            )
+        };
+
+        match res {
+            0 => Err(Error::last_os_error()),
+            _ => Ok(returned_bytes as usize),
+        }
+    }
+}
 
+/// Streams records one bounded chunk at a time, so memory stays flat however
+/// large the journal is and stopping early skips the remaining reads
 pub struct UsnRecordsIterator<'a> {
-    volume: &'a Volume,
-    buffer: [u8; BUFFER_SIZE],
-    reference_number: u64,
+    device: &'a dyn UsnDevice,
+    /// Held as `u64`s so records, which are 8-byte aligned, stay aligned
+    buffer: Vec<u64>,
+    source: UsnSource,
     usn_range: &'a UsnRange,
     size: usize,
     offset: usize,
 }
 
 impl<'a> UsnRecordsIterator<'a> {
-    fn new(volume: &'a Volume, usn_range: &'a UsnRange) -> UsnRecordsIterator<'a> {
+    fn new(device: &'a dyn UsnDevice, usn_range: &'a UsnRange) -> UsnRecordsIterator<'a> {
+        Self::with_source(device, usn_range, UsnSource::Mft { next_reference: 0 })
+    }
+
+    fn with_source(
+        device: &'a dyn UsnDevice,
+        usn_range: &'a UsnRange,
+        source: UsnSource,
+    ) -> UsnRecordsIterator<'a> {
         UsnRecordsIterator {
-            volume,
-            buffer: [0; BUFFER_SIZE],
-            reference_number: 0,
+            device,
+            buffer: Vec::new(),
+            source,
             usn_range,
             size: 0,
             offset: 0,
         }
+        .with_chunk_size(DEFAULT_CHUNK_SIZE)
+    }
+
+    /// Read at most `bytes` per control call, rounded up to fit the largest
+    /// record. Takes effect from the next read.
+    pub fn with_chunk_size(mut self, bytes: usize) -> Self {
+        let words = bytes
+            .max(MIN_CHUNK_SIZE)
+            .div_ceil(std::mem::size_of::<u64>());
+        self.buffer = vec![0; words];
+        self
     }
 }
 
 impl<'a> UsnRecordsIterator<'a> {
-    fn fetch(&mut self) -> Result<(), Error> {
-        let mut returned_bytes: u32 = 0;
-        let mft_enum_data = MFT_ENUM_DATA {
-            StartFileReferenceNumber: self.reference_number,
-            LowUsn: self.usn_range.low,
-            HighUsn: self.usn_range.high,
+    fn control<T>(&mut self, code: DWORD, input: &T) -> Result<usize, Error> {
+        let input = unsafe {
+            std::slice::from_raw_parts(input as *const T as *const u8, std::mem::size_of_val(input))
         };
-
-        let res = unsafe {
-            DeviceIoControl(
-                self.volume.handle,
-                FSCTL_ENUM_USN_DATA,
-                &mft_enum_data as *const MFT_ENUM_DATA as LPVOID,
-                std::mem::size_of_val(&mft_enum_data) as DWORD,
-                self.buffer.as_mut_ptr() as *mut USN_RECORD as LPVOID,
-                self.buffer.len() as DWORD,
-                &mut returned_bytes as LPDWORD,
-                null_mut(),
+        let output = unsafe {
+            std::slice::from_raw_parts_mut(
+                self.buffer.as_mut_ptr() as *mut u8,
+                std::mem::size_of_val(self.buffer.as_slice()),
             )
         };
+        self.device.control(code, input, output)
+    }
 
-        if res != 0 {
-            self.reference_number = unsafe { *(self.buffer.as_ptr() as *const u64) };
+    /// Refill the buffer with the next chunk, returning `false` once there is
+    /// nothing left to read. A chunk can be empty while the cursor still
+    /// moves, when the reason mask filtered out everything in it.
+    fn fetch(&mut self) -> Result<bool, Error> {
+        let returned_bytes = match self.source {
+            UsnSource::Mft { next_reference } => {
+                let mft_enum_data = MFT_ENUM_DATA {
+                    StartFileReferenceNumber: next_reference,
+                    LowUsn: self.usn_range.low,
+                    HighUsn: self.usn_range.high,
+                };
+                match self.control(FSCTL_ENUM_USN_DATA, &mft_enum_data) {
+                    // EOF
+                    Err(err) if Some(38) == err.raw_os_error() => return Ok(false),
+                    result => result?,
+                }
+            }
+            UsnSource::Journal {
+                journal_id,
+                next_usn,
+                reason_mask,
+            } => {
+                if next_usn >= self.usn_range.high {
+                    return Ok(false);
+                }
+                let read_data = READ_USN_JOURNAL_DATA {
+                    StartUsn: next_usn,
+                    ReasonMask: reason_mask,
+                    UsnJournalID: journal_id,
+                    ..Default::default()
+                };
+                self.control(FSCTL_READ_USN_JOURNAL, &read_data)?
+            }
+        };
 
-            self.size = returned_bytes as usize;
-            self.offset = std::mem::size_of_val(&self.reference_number);
-        }
+        // Both controls lead the output with the cursor for the next call
+        let cursor = self.buffer[0];
+        let advanced = match &mut self.source {
+            UsnSource::Mft { next_reference } => {
+                std::mem::replace(next_reference, cursor) != cursor
+            }
+            UsnSource::Journal { next_usn, .. } => {
+                std::mem::replace(next_usn, cursor as USN) != cursor as USN
+            }
+        };
 
-        match res {
-            0 => Err(Error::last_os_error()),
-            _ => Ok(()),
-        }
+        self.size = returned_bytes;
+        self.offset = std::mem::size_of_val(&cursor);
+
+        Ok(self.offset < self.size || advanced)
     }
 }
 
@@ -94,23 +197,43 @@ impl<'a> Iterator for UsnRecordsIterator<'a> {
     type Item = UsnRecord;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.offset >= self.size {
+        while self.offset >= self.size {
             match self.fetch() {
-                Err(err) if Some(38) == err.raw_os_error() => {
-                    // EOF
-                    return None;
-                }
+                Ok(false) => return None,
                 Err(err) => {
                     panic!("Usn records iteration failed with {}", err);
                 }
-                _ => (),
+                Ok(true) => (),
             }
         }
 
-        let base = self.buffer.as_ptr();
+        let base = self.buffer.as_ptr() as *const u8;
         let ptr = unsafe { base.add(self.offset) };
         assert_eq!(ptr as usize % std::mem::align_of::<USN_RECORD>(), 0);
+        // The controls only return whole records, so one running past the
+        // end of the chunk means the data is corrupt
+        let remaining = self.size - self.offset;
+        assert!(
+            remaining >= std::mem::size_of::<USN_RECORD>(),
+            "Usn record at offset {} is cut off",
+            self.offset
+        );
         let usn_record: &USN_RECORD = unsafe { &*(ptr as *const USN_RECORD) };
+        let record_length = usn_record.RecordLength as usize;
+        assert!(
+            record_length >= std::mem::size_of::<USN_RECORD>() && record_length <= remaining,
+            "Usn record at offset {} is cut off",
+            self.offset
+        );
+
+        if let UsnSource::Journal { next_usn, .. } = &mut self.source {
+            if usn_record.Usn >= self.usn_range.high {
+                // Written after the range was taken, leave it for the next read
+                *next_usn = self.usn_range.high;
+                self.offset = self.size;
+                return None;
+            }
+        }
 
         let filename = unsafe { ptr.offset(usn_record.FileNameOffset as isize) as *const u16 };
         let filename = unsafe {
@@ -119,7 +242,7 @@ impl<'a> Iterator for UsnRecordsIterator<'a> {
         let filename = String::from_utf16_lossy(filename);
 
         // Advance to next record
-        self.offset += usn_record.RecordLength as usize;
+        self.offset += record_length;
 
         let record_type = if usn_record.FileAttributes & FILE_ATTRIBUTE_DIRECTORY != 0 {
             UsnRecordType::Directory
@@ -132,6 +255,7 @@ impl<'a> Iterator for UsnRecordsIterator<'a> {
             parent_id: usn_record.ParentFileReferenceNumber,
             record_type,
             filename,
+            reason: usn_record.Reason,
         })
     }
 }
@@ -140,6 +264,20 @@ pub trait Ntfs {
     fn create_usn_journal(&self) -> Result<(), Error>;
     fn query_usn_journal(&self) -> Result<USN_JOURNAL_DATA, Error>;
     fn usn_records<'a>(&'a self, range: &'a UsnRange) -> UsnRecordsIterator<'a>;
+    /// Read the changes recorded in `range` of the journal, oldest first
+    fn usn_journal_records<'a>(
+        &'a self,
+        journal_id: u64,
+        range: &'a UsnRange,
+    ) -> UsnRecordsIterator<'a>;
+    /// Like `usn_journal_records` on the active journal, but the kernel only
+    /// returns records with any of the `reasons` bits set, such as
+    /// `USN_REASON_FILE_CREATE`
+    fn usn_records_filtered<'a>(
+        &'a self,
+        range: &'a UsnRange,
+        reasons: u32,
+    ) -> Result<UsnRecordsIterator<'a>, Error>;
 }
 
 impl Ntfs for Volume {
@@ -195,4 +333,149 @@ impl Ntfs for Volume {
     fn usn_records<'a>(&'a self, usn_range: &'a UsnRange) -> UsnRecordsIterator<'a> {
         UsnRecordsIterator::new(self, usn_range)
     }
+
+    fn usn_journal_records<'a>(
+        &'a self,
+        journal_id: u64,
+        usn_range: &'a UsnRange,
+    ) -> UsnRecordsIterator<'a> {
+        let source = UsnSource::Journal {
+            journal_id,
+            next_usn: usn_range.low,
+            reason_mask: u32::MAX,
+        };
+        UsnRecordsIterator::with_source(self, usn_range, source)
+    }
+
+    fn usn_records_filtered<'a>(
+        &'a self,
+        usn_range: &'a UsnRange,
+        reasons: u32,
+    ) -> Result<UsnRecordsIterator<'a>, Error> {
+        let journal = self.query_usn_journal()?;
+        let source = UsnSource::Journal {
+            journal_id: journal.UsnJournalID,
+            next_usn: usn_range.low,
+            reason_mask: reasons,
+        };
+        Ok(UsnRecordsIterator::with_source(self, usn_range, source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::convert::TryInto;
+    use std::mem::{offset_of, size_of};
+
+    /// A journal whose USNs are byte offsets, like the real one, answering
+    /// reads the way the kernel does: the next USN, then whole records only
+    struct SyntheticJournal {
+        records: Vec<(USN, Vec<u8>)>,
+        end: USN,
+        reads: Cell<usize>,
+    }
+
+    impl SyntheticJournal {
+        fn new(names: impl Iterator<Item = String>) -> Self {
+            let mut records = Vec::new();
+            let mut usn = 0;
+            for (id, name) in names.enumerate() {
+                let record = encode(usn, id as u64, &name);
+                let length = record.len() as USN;
+                records.push((usn, record));
+                usn += length;
+            }
+            SyntheticJournal {
+                records,
+                end: usn,
+                reads: Cell::new(0),
+            }
+        }
+    }
+
+    impl UsnDevice for SyntheticJournal {
+        fn control(&self, code: DWORD, input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+            assert_eq!(code, FSCTL_READ_USN_JOURNAL);
+            self.reads.set(self.reads.get() + 1);
+            let start = USN::from_le_bytes(input[..8].try_into().unwrap());
+
+            let mut cursor = start;
+            let mut written = size_of::<USN>();
+            for (usn, record) in self.records.iter().filter(|(usn, _)| *usn >= start) {
+                if written + record.len() > output.len() {
+                    break;
+                }
+                output[written..written + record.len()].copy_from_slice(record);
+                written += record.len();
+                cursor = usn + record.len() as USN;
+            }
+            output[..8].copy_from_slice(&cursor.to_le_bytes());
+            Ok(written)
+        }
+    }
+
+    fn encode(usn: USN, id: u64, name: &str) -> Vec<u8> {
+        let name: Vec<u8> = name.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let name_offset = offset_of!(USN_RECORD, FileName);
+        let length = (name_offset + name.len())
+            .next_multiple_of(8)
+            .max(size_of::<USN_RECORD>());
+
+        let mut header: USN_RECORD = unsafe { std::mem::zeroed() };
+        header.RecordLength = length as u32;
+        header.MajorVersion = 2;
+        header.FileReferenceNumber = id;
+        header.ParentFileReferenceNumber = 5;
+        header.Usn = usn;
+        header.Reason = 0x100;
+        header.FileNameLength = name.len() as u16;
+        header.FileNameOffset = name_offset as u16;
+
+        let mut record = vec![0; length];
+        let header = unsafe {
+            std::slice::from_raw_parts(
+                &header as *const USN_RECORD as *const u8,
+                size_of::<USN_RECORD>(),
+            )
+        };
+        record[..header.len()].copy_from_slice(header);
+        record[name_offset..name_offset + name.len()].copy_from_slice(&name);
+        record
+    }
+
+    #[test]
+    fn small_chunks_yield_the_same_records_as_one_read() {
+        // Names of varying length so chunk boundaries land everywhere
+        let journal =
+            SyntheticJournal::new((0..300).map(|i| format!("{}{}.bin", "x".repeat(i % 40), i)));
+        let range = UsnRange {
+            low: 0,
+            high: journal.end,
+        };
+        let read = |chunk_size: usize| {
+            journal.reads.set(0);
+            let source = UsnSource::Journal {
+                journal_id: 1,
+                next_usn: 0,
+                reason_mask: u32::MAX,
+            };
+            let records: Vec<(u64, String)> =
+                UsnRecordsIterator::with_source(&journal, &range, source)
+                    .with_chunk_size(chunk_size)
+                    .map(|record| (record.id, record.filename))
+                    .collect();
+            (records, journal.reads.get())
+        };
+
+        let (whole, reads) = read(1024 * 1024);
+        assert_eq!(whole.len(), 300);
+        assert_eq!(whole[299], (299, format!("{}299.bin", "x".repeat(19))));
+        assert_eq!(reads, 1);
+
+        let (chunked, reads) = read(MIN_CHUNK_SIZE);
+        assert_eq!(chunked, whole);
+        assert!(reads > 10);
+    }
 }
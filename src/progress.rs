@@ -0,0 +1,260 @@
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Stages of a run, in the order they happen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Listing,
+    GroupingBySize,
+    Hashing,
+}
+
+/// Receives progress for a UI of its own, such as a GUI embedding ddup.
+/// Called from worker threads.
+pub trait ProgressObserver: Send + Sync {
+    fn on_phase(&self, phase: Phase);
+    /// `done` out of `total` items of the current phase: files, or size
+    /// groups while hashing. `done` never goes down within a phase.
+    fn on_file(&self, done: u64, total: u64);
+}
+
+/// Asks a run to stop early, such as from a Ctrl-C handler. Clones share
+/// the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelFlag(Arc<AtomicBool>);
+
+impl CancelFlag {
+    pub fn new() -> Self {
+        CancelFlag::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Decides once per run whether progress bars are drawn, so every phase
+/// agrees and nothing is drawn into log files or piped output. An observer
+/// set with `with_observer` is told about progress either way.
+#[derive(Clone)]
+pub struct ProgressReporter {
+    visible: bool,
+    observer: Option<Arc<dyn ProgressObserver>>,
+    cancel: CancelFlag,
+}
+
+impl std::fmt::Debug for ProgressReporter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("visible", &self.visible)
+            .field("observer", &self.observer.is_some())
+            .field("cancelled", &self.cancel.is_cancelled())
+            .finish()
+    }
+}
+
+impl ProgressReporter {
+    /// Draw progress when `enabled` and stderr is a terminal
+    pub fn new(enabled: bool) -> Self {
+        ProgressReporter {
+            visible: enabled && std::io::stderr().is_terminal(),
+            observer: None,
+            cancel: CancelFlag::default(),
+        }
+    }
+
+    pub fn hidden() -> Self {
+        ProgressReporter {
+            visible: false,
+            observer: None,
+            cancel: CancelFlag::default(),
+        }
+    }
+
+    pub fn with_observer(mut self, observer: Arc<dyn ProgressObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Stop the run early once `cancel` is set. Listing and hashing check it
+    /// between items and return what they finished.
+    pub fn with_cancel(mut self, cancel: CancelFlag) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// The flag `with_cancel` set, to hand on to what checks it per file
+    pub fn cancel_flag(&self) -> CancelFlag {
+        self.cancel.clone()
+    }
+
+    /// Tell the observer, if any, that a new phase started
+    pub fn phase(&self, phase: Phase) {
+        if let Some(observer) = &self.observer {
+            observer.on_phase(phase);
+        }
+    }
+
+    /// Count `total` items on a bar of their own, see `counter_on`
+    pub fn counter(&self, total: u64) -> ItemCounter {
+        self.counter_on(self.bar(total), total)
+    }
+
+    /// Count `total` items on `bar`, reporting them to the observer too
+    pub fn counter_on(&self, bar: ProgressBar, total: u64) -> ItemCounter {
+        ItemCounter {
+            bar,
+            total,
+            done: Mutex::new(0),
+            observer: self.observer.clone(),
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    fn target(&self) -> ProgressDrawTarget {
+        if self.visible {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        }
+    }
+
+    pub fn bar(&self, len: u64) -> ProgressBar {
+        ProgressBar::with_draw_target(Some(len), self.target())
+    }
+
+    /// A bar counting bytes, which gives a useful ETA when items vary in size
+    pub fn bytes_bar(&self, total: u64) -> ProgressBar {
+        self.bar(total).with_style(
+            ProgressStyle::with_template("{wide_bar} {bytes}/{total_bytes} ({eta})").unwrap(),
+        )
+    }
+
+    /// Container for showing several bars at once
+    pub fn multi(&self) -> MultiProgress {
+        MultiProgress::with_draw_target(self.target())
+    }
+}
+
+impl Default for ProgressReporter {
+    fn default() -> Self {
+        ProgressReporter::new(true)
+    }
+}
+
+/// Items processed across threads, mirrored on a progress bar and passed on
+/// to the observer
+pub struct ItemCounter {
+    bar: ProgressBar,
+    total: u64,
+    /// Held while notifying, so the observer sees counts in order
+    done: Mutex<u64>,
+    observer: Option<Arc<dyn ProgressObserver>>,
+}
+
+impl ItemCounter {
+    pub fn inc(&self, count: u64) {
+        self.bar.inc(count);
+        if let Some(observer) = &self.observer {
+            let mut done = self.done.lock().unwrap_or_else(|e| e.into_inner());
+            *done += count;
+            observer.on_file(*done, self.total);
+        }
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
+
+/// Bytes processed across threads, mirrored on a progress bar
+#[derive(Debug)]
+pub struct ByteCounter {
+    total: AtomicU64,
+    bar: ProgressBar,
+}
+
+impl ByteCounter {
+    pub fn new(bar: ProgressBar) -> Self {
+        ByteCounter {
+            total: AtomicU64::new(0),
+            bar,
+        }
+    }
+
+    pub fn add(&self, bytes: u64) {
+        self.total.fetch_add(bytes, Ordering::Relaxed);
+        self.bar.inc(bytes);
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish();
+    }
+}
+
+impl Default for ByteCounter {
+    fn default() -> Self {
+        ByteCounter::new(ProgressBar::hidden())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disabled_progress_has_no_draw_target() {
+        let reporter = ProgressReporter::new(false);
+        assert!(!reporter.is_visible());
+
+        let bar = reporter.bar(1234);
+        assert!(bar.is_hidden());
+        assert_eq!(bar.length(), Some(1234));
+        bar.inc(1234);
+        bar.finish();
+        assert_eq!(bar.position(), 1234);
+    }
+
+    struct Recorder(Mutex<Vec<(u64, u64)>>);
+
+    impl ProgressObserver for Recorder {
+        fn on_phase(&self, _: Phase) {}
+
+        fn on_file(&self, done: u64, total: u64) {
+            self.0.lock().unwrap().push((done, total));
+        }
+    }
+
+    #[test]
+    fn counter_reports_in_order_across_threads() {
+        use rayon::prelude::*;
+
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let reporter = ProgressReporter::hidden().with_observer(recorder.clone());
+        let counter = reporter.counter(1000);
+        (0..1000).into_par_iter().for_each(|_| counter.inc(1));
+        counter.finish();
+
+        let seen = recorder.0.lock().unwrap();
+        let expected: Vec<(u64, u64)> = (1..=1000).map(|done| (done, 1000)).collect();
+        assert_eq!(*seen, expected);
+    }
+}
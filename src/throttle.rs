@@ -0,0 +1,129 @@
+use std::io::Error;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+use winapi::um::winbase::THREAD_MODE_BACKGROUND_BEGIN;
+
+/// Token bucket capping the bytes read per second across every thread that
+/// shares it. Readers take tokens after each read and sleep off any debt, so
+/// a single large file may run ahead for a moment, but the average over a
+/// run stays at the cap.
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_second: u64,
+    /// Tokens left, negative while readers are ahead of the rate, and when
+    /// they were last topped up
+    state: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        Throttle {
+            bytes_per_second,
+            state: Mutex::new((bytes_per_second as f64, Instant::now())),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// How long a reader of `bytes` has to wait at `now` to stay under the
+    /// rate. At most a second's worth of unused tokens carries over, so an
+    /// idle spell doesn't allow a burst of unlimited size afterwards.
+    fn debit(&self, bytes: u64, now: Instant) -> Duration {
+        let rate = self.bytes_per_second as f64;
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let (tokens, refilled) = &mut *state;
+        let elapsed = now.saturating_duration_since(*refilled).as_secs_f64();
+        *tokens = (*tokens + elapsed * rate).min(rate) - bytes as f64;
+        *refilled = now.max(*refilled);
+        if *tokens < 0.0 {
+            Duration::from_secs_f64(-*tokens / rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// Account for `bytes` just read, sleeping until the rate allows them
+    pub fn take(&self, bytes: u64) {
+        let wait = self.debit(bytes, Instant::now());
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+/// Lower the I/O and scheduling priority of the calling thread to
+/// background, so other programs get the disk first. Meant for every worker
+/// thread, through `rayon::ThreadPoolBuilder::start_handler`.
+pub fn background_priority() -> Result<(), Error> {
+    let res = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN as i32) };
+    match res {
+        0 => Err(Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_holds_simulated_reads_to_the_cap() {
+        let throttle = Throttle::new(1_000_000);
+        let start = Instant::now();
+
+        // The first second's worth is free, the rest has to be waited for
+        let mut clock = start;
+        let mut waited = Duration::ZERO;
+        for _ in 0..40 {
+            let wait = throttle.debit(100_000, clock);
+            waited += wait;
+            clock += wait;
+        }
+        let elapsed = clock - start;
+        assert!(
+            (2.9..=3.1).contains(&elapsed.as_secs_f64()),
+            "{:?}",
+            elapsed
+        );
+        assert_eq!(waited, elapsed);
+
+        // After idling, only a second's worth carries over
+        let idle = clock + Duration::from_secs(60);
+        assert_eq!(throttle.debit(1_000_000, idle), Duration::ZERO);
+        assert!(throttle.debit(500_000, idle) >= Duration::from_millis(499));
+    }
+
+    #[test]
+    fn threads_share_one_rate() {
+        let throttle = Throttle::new(1_000_000);
+        let start = Instant::now();
+
+        // Four readers debiting at the same simulated moment draw on one
+        // bucket: 8 MB against the first second's 1 MB leaves the last of
+        // them 7 s behind, whichever thread that is
+        let waits: Vec<Duration> = std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        (0..8)
+                            .map(|_| throttle.debit(250_000, start))
+                            .max()
+                            .unwrap()
+                    })
+                })
+                .collect();
+            readers.into_iter().map(|r| r.join().unwrap()).collect()
+        });
+        let longest = waits.into_iter().max().unwrap();
+        assert!(
+            (6.999..=7.001).contains(&longest.as_secs_f64()),
+            "{:?}",
+            longest
+        );
+    }
+}
@@ -1,5 +1,14 @@
 use std::collections::HashMap;
+use std::os::windows::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use winapi::um::fileapi::GetLogicalDrives;
+use winapi::um::winnt::{
+    FILE_ATTRIBUTE_COMPRESSED, FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_HIDDEN,
+    FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_REPARSE_POINT, FILE_ATTRIBUTE_SPARSE_FILE,
+    FILE_ATTRIBUTE_SYSTEM,
+};
 
 use super::{UsnRecord, UsnRecordType};
 
@@ -9,8 +18,140 @@ pub fn usn_records_to_hash_map(
     iterator.map(|record| (record.id, record)).collect()
 }
 
-pub fn hash_map_to_paths(map: &HashMap<u64, UsnRecord>) -> Vec<PathBuf> {
+/// Length of the volume prefix of a backslash-separated path: `C:`,
+/// `\\server\share`, or either behind `\\?\` (`\\?\C:`,
+/// `\\?\UNC\server\share`). Zero for paths without one.
+fn volume_prefix_len(path: &str) -> usize {
+    let (verbatim, rest) = match path.strip_prefix(r"\\?\") {
+        Some(rest) => (4, rest),
+        None => (0, path),
+    };
+    if let [letter, b':', ..] = rest.as_bytes() {
+        if letter.is_ascii_alphabetic() {
+            return verbatim + 2;
+        }
+    }
+
+    let share_start = if verbatim > 0 {
+        match rest.get(..4) {
+            Some(unc) if unc.eq_ignore_ascii_case(r"UNC\") => verbatim + 4,
+            _ => return verbatim,
+        }
+    } else if path.starts_with(r"\\") {
+        2
+    } else {
+        return 0;
+    };
+
+    // Server and share name, without the separator that follows
+    let mut end = share_start;
+    for _ in 0..2 {
+        match path[end..].find('\\') {
+            Some(index) => end += index + 1,
+            None => return path.len(),
+        }
+    }
+    end - 1
+}
+
+/// `path` without its volume prefix, see `volume_prefix_len`
+pub fn strip_volume(path: &str) -> &str {
+    &path[volume_prefix_len(path)..]
+}
+
+/// Spell a path read from a listing the way the rest of ddup expects:
+/// backslashes only, single separators and no `\\?\` prefix on drive or UNC
+/// paths, so the same file listed either way compares equal. std adds the
+/// long path prefix back when opening the file.
+pub fn normalize_path(raw: &str) -> PathBuf {
+    let path = raw.replace('/', "\\");
+    let path = match path.strip_prefix(r"\\?\") {
+        Some(rest)
+            if rest
+                .get(..4)
+                .is_some_and(|unc| unc.eq_ignore_ascii_case(r"UNC\")) =>
+        {
+            format!(r"\\{}", &rest[4..])
+        }
+        Some(rest) if volume_prefix_len(rest) == 2 => rest.to_string(),
+        _ => path,
+    };
+
+    // A share starts with two separators, everywhere else they collapse
+    let lead = if path.starts_with(r"\\") { 2 } else { 0 };
+    let mut normalized = String::with_capacity(path.len());
+    normalized.push_str(&path[..lead]);
+    for c in path[lead..].chars() {
+        if c != '\\' || normalized.len() == lead || !normalized.ends_with('\\') {
+            normalized.push(c);
+        }
+    }
+    PathBuf::from(normalized)
+}
+
+/// Split a scan target like `C:\Users\foo` into its volume (`C:`) and the
+/// directory relative to the volume root, if one was given. A share such as
+/// `\\server\share\dir` is split the same way, at `\\server\share`.
+pub fn split_scan_root(target: &str) -> (String, Option<String>) {
+    let normalized = normalize_path(target).to_string_lossy().into_owned();
+    let trimmed = normalized.trim_end_matches('\\');
+
+    let split = match volume_prefix_len(trimmed) {
+        0 => trimmed.split_once('\\'),
+        len => Some((&trimmed[..len], trimmed[len..].trim_start_matches('\\'))),
+    };
+    match split {
+        Some((volume, subdir)) if !subdir.is_empty() => {
+            (volume.to_string(), Some(subdir.to_string()))
+        }
+        Some((volume, _)) => (volume.to_string(), None),
+        None => (trimmed.to_string(), None),
+    }
+}
+
+/// Drive letters of the volumes that exist right now, such as `C:`
+pub fn logical_drives() -> Vec<String> {
+    let mask = unsafe { GetLogicalDrives() };
+    (b'A'..=b'Z')
+        .filter(|letter| mask & (1 << (letter - b'A')) != 0)
+        .map(|letter| format!("{}:", letter as char))
+        .collect()
+}
+
+/// Whether `path` lies under `prefix`, comparing components case-insensitively
+/// like NTFS does.
+pub fn is_within(path: &Path, prefix: &Path) -> bool {
+    let mut components = path.components();
+    prefix.components().all(|expected| {
+        components
+            .next()
+            .is_some_and(|c| c.as_os_str().eq_ignore_ascii_case(expected.as_os_str()))
+    })
+}
+
+/// MFT record number of the volume root directory, which USN enumeration
+/// never returns itself
+const ROOT_RECORD_NUMBER: u64 = 5;
+
+fn is_volume_root(id: u64) -> bool {
+    // The upper 16 bits of a file reference hold the sequence number
+    id & 0x0000_FFFF_FFFF_FFFF == ROOT_RECORD_NUMBER
+}
+
+/// Placeholder root for records whose parent chain is broken
+pub const UNKNOWN_ROOT: &str = "<unknown>";
+
+pub struct ResolvedPaths {
+    /// Paths relative to the volume root
+    pub paths: Vec<PathBuf>,
+    /// Records whose parent chain ends at a directory missing from the
+    /// journal, rooted at `UNKNOWN_ROOT` since their location is unknown
+    pub orphans: Vec<PathBuf>,
+}
+
+pub fn hash_map_to_paths(map: &HashMap<u64, UsnRecord>) -> ResolvedPaths {
     let mut full_paths = Vec::new();
+    let mut orphans = Vec::new();
 
     for record in map.values() {
         if let UsnRecordType::Directory = record.record_type {
@@ -27,8 +168,251 @@ pub fn hash_map_to_paths(map: &HashMap<u64, UsnRecord>) -> Vec<PathBuf> {
             path = Path::new(&current.filename).join(&path);
         }
 
-        full_paths.push(path);
+        if is_volume_root(current.parent_id) {
+            full_paths.push(path);
+        } else {
+            orphans.push(Path::new(UNKNOWN_ROOT).join(path));
+        }
+    }
+
+    ResolvedPaths {
+        paths: full_paths,
+        orphans,
+    }
+}
+
+/// Win32 attributes of `path` itself, not of a link target
+pub fn file_attributes(path: &Path) -> io::Result<u32> {
+    fs::symlink_metadata(path).map(|metadata| metadata.file_attributes())
+}
+
+pub fn is_directory_attribute(attributes: u32) -> bool {
+    attributes & FILE_ATTRIBUTE_DIRECTORY != 0
+}
+
+/// Hidden or system files, which users rarely mean to deduplicate
+pub fn is_hidden_attribute(attributes: u32) -> bool {
+    attributes & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0
+}
+
+/// Symlinks, junctions and other reparse points
+pub fn is_reparse_attribute(attributes: u32) -> bool {
+    attributes & FILE_ATTRIBUTE_REPARSE_POINT != 0
+}
+
+/// Sparse files, whose holes take up no space on disk
+pub fn is_sparse_attribute(attributes: u32) -> bool {
+    attributes & FILE_ATTRIBUTE_SPARSE_FILE != 0
+}
+
+/// NTFS-compressed files, which report their uncompressed length
+pub fn is_compressed_attribute(attributes: u32) -> bool {
+    attributes & FILE_ATTRIBUTE_COMPRESSED != 0
+}
+
+/// Name of the first attribute that marks a file as not ours to replace:
+/// read-only, system or hidden
+pub fn protected_attribute(attributes: u32) -> Option<&'static str> {
+    [
+        (FILE_ATTRIBUTE_READONLY, "read-only"),
+        (FILE_ATTRIBUTE_SYSTEM, "system"),
+        (FILE_ATTRIBUTE_HIDDEN, "hidden"),
+    ]
+    .iter()
+    .find(|(flag, _)| attributes & flag != 0)
+    .map(|&(_, name)| name)
+}
+
+/// 100ns intervals between the FILETIME epoch, 1601, and the Unix epoch
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// Seconds since the Unix epoch of a Win32 FILETIME, or `None` for the 0 and
+/// all-ones values APIs return for an unknown time
+pub fn filetime_to_unix_seconds(filetime: u64) -> Option<u64> {
+    match filetime {
+        0 | u64::MAX => None,
+        _ => Some(filetime.saturating_sub(FILETIME_UNIX_EPOCH) / 10_000_000),
+    }
+}
+
+/// Parse a byte count with an optional binary suffix, e.g. `512`, `4K` or `1MiB`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let digits_end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, suffix) = s.split_at(digits_end);
+
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("Invalid size `{}`", s))?;
+
+    let shift = match suffix.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => return Err(format!("Invalid size suffix in `{}`", s)),
+    };
+
+    value
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("Size `{}` is too large", s))
+}
+
+/// Parse a length of time with a unit, e.g. `90s`, `30m`, `24h`, `7d` or `2w`
+pub fn parse_age(s: &str) -> Result<std::time::Duration, String> {
+    let s = s.trim();
+    let digits_end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(digits_end);
+
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| format!("Invalid duration `{}`", s))?;
+
+    let seconds = match unit.to_ascii_lowercase().as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "Invalid duration unit in `{}`, expected s, m, h, d or w",
+                s
+            ))
+        }
+    };
+
+    value
+        .checked_mul(seconds)
+        .map(std::time::Duration::from_secs)
+        .ok_or_else(|| format!("Duration `{}` is too long", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages_parse_with_their_unit() {
+        assert_eq!(parse_age("90s"), Ok(std::time::Duration::from_secs(90)));
+        assert_eq!(parse_age("24h"), Ok(std::time::Duration::from_secs(86_400)));
+        assert_eq!(parse_age("7D"), Ok(std::time::Duration::from_secs(604_800)));
+        assert_eq!(
+            parse_age("2w"),
+            Ok(std::time::Duration::from_secs(1_209_600))
+        );
+        assert!(parse_age("7").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("3y").is_err());
+    }
+
+    #[test]
+    fn filetime_converts_to_unix_seconds() {
+        // 2024-01-01T00:00:00Z
+        assert_eq!(
+            filetime_to_unix_seconds(133_485_408_000_000_000),
+            Some(1_704_067_200)
+        );
+        assert_eq!(filetime_to_unix_seconds(FILETIME_UNIX_EPOCH), Some(0));
+        assert_eq!(filetime_to_unix_seconds(0), None);
+        assert_eq!(filetime_to_unix_seconds(u64::MAX), None);
+    }
+
+    fn record(id: u64, parent_id: u64, record_type: UsnRecordType, filename: &str) -> UsnRecord {
+        UsnRecord {
+            id,
+            parent_id,
+            record_type,
+            filename: filename.to_string(),
+            reason: 0,
+        }
+    }
+
+    #[test]
+    fn protected_attributes_are_named() {
+        assert_eq!(protected_attribute(0x20), None);
+        assert_eq!(protected_attribute(0x21), Some("read-only"));
+        assert_eq!(protected_attribute(0x06), Some("system"));
+        assert_eq!(protected_attribute(0x22), Some("hidden"));
+        assert!(is_directory_attribute(0x10));
+        assert!(!is_directory_attribute(0x20));
+        assert!(is_reparse_attribute(0x420));
+        assert!(!is_reparse_attribute(0x20));
+        assert!(is_hidden_attribute(0x22));
+        assert!(is_hidden_attribute(0x24));
+        assert!(!is_hidden_attribute(0x21));
     }
 
-    full_paths
+    #[test]
+    fn unc_and_verbatim_paths_are_normalized() {
+        let cases = [
+            (r"C:\data\a.bin", r"C:\data\a.bin"),
+            (r"C:/data//a.bin", r"C:\data\a.bin"),
+            (r"\\?\C:\data\a.bin", r"C:\data\a.bin"),
+            (r"\\server\share\data\a.bin", r"\\server\share\data\a.bin"),
+            (r"//server/share//data/a.bin", r"\\server\share\data\a.bin"),
+            (
+                r"\\?\UNC\server\share\data\a.bin",
+                r"\\server\share\data\a.bin",
+            ),
+            (r"\\?\unc\server\share\a.bin", r"\\server\share\a.bin"),
+            (
+                r"\\?\Volume{1b3b1146-4076-11e1-84aa-806e6f6e6963}\a.bin",
+                r"\\?\Volume{1b3b1146-4076-11e1-84aa-806e6f6e6963}\a.bin",
+            ),
+        ];
+        for (raw, expected) in cases {
+            assert_eq!(normalize_path(raw), PathBuf::from(expected), "{}", raw);
+        }
+    }
+
+    #[test]
+    fn volume_prefix_is_stripped_from_any_spelling() {
+        assert_eq!(strip_volume(r"C:\data\a.bin"), r"\data\a.bin");
+        assert_eq!(strip_volume(r"\\?\C:\data\a.bin"), r"\data\a.bin");
+        assert_eq!(strip_volume(r"\\server\share\data\a.bin"), r"\data\a.bin");
+        assert_eq!(strip_volume(r"\\?\UNC\server\share\a.bin"), r"\a.bin");
+        assert_eq!(strip_volume(r"\\server\share"), "");
+        assert_eq!(strip_volume(r"\data\a.bin"), r"\data\a.bin");
+        assert_eq!(strip_volume("relative.bin"), "relative.bin");
+    }
+
+    #[test]
+    fn share_is_split_like_a_drive() {
+        assert_eq!(
+            split_scan_root(r"\\server\share\photos\"),
+            (r"\\server\share".to_string(), Some("photos".to_string()))
+        );
+        assert_eq!(
+            split_scan_root(r"\\server\share"),
+            (r"\\server\share".to_string(), None)
+        );
+        assert_eq!(
+            split_scan_root(r"\\?\C:\Users\foo"),
+            ("C:".to_string(), Some(r"Users\foo".to_string()))
+        );
+    }
+
+    #[test]
+    fn dangling_parent_is_reported_as_orphan() {
+        let root = 0x0005_0000_0000_0005;
+        let records = vec![
+            record(0x10, root, UsnRecordType::Directory, "docs"),
+            record(0x11, 0x10, UsnRecordType::File, "a.txt"),
+            record(0x12, root, UsnRecordType::File, "b.txt"),
+            record(0x13, 0x99, UsnRecordType::File, "lost.txt"),
+        ];
+        let map = usn_records_to_hash_map(records.into_iter());
+
+        let resolved = hash_map_to_paths(&map);
+
+        let mut paths = resolved.paths;
+        paths.sort();
+        assert_eq!(
+            paths,
+            [PathBuf::from("b.txt"), Path::new("docs").join("a.txt")]
+        );
+        assert_eq!(resolved.orphans, [Path::new(UNKNOWN_ROOT).join("lost.txt")]);
+    }
 }
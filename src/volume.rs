@@ -1,10 +1,26 @@
 use std::ffi::CString;
+use std::fs::{self, File};
 use std::io::Error;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::AsRawHandle;
+use std::path::Path;
+use std::ptr::null_mut;
 
+use super::winioctl::{
+    DUPLICATE_EXTENTS_DATA, FSCTL_DUPLICATE_EXTENTS_TO_FILE, FSCTL_GET_INTEGRITY_INFORMATION,
+    FSCTL_GET_INTEGRITY_INFORMATION_BUFFER, WIN32_FIND_STREAM_DATA,
+};
+
+use winapi::shared::minwindef::{DWORD, LPDWORD, LPVOID};
+use winapi::shared::winerror::{ERROR_HANDLE_EOF, ERROR_INVALID_FUNCTION, ERROR_NOT_SUPPORTED};
 use winapi::um::fileapi::CreateFileA;
 use winapi::um::fileapi::OPEN_EXISTING;
+use winapi::um::fileapi::{FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard};
+use winapi::um::fileapi::{GetCompressedFileSizeW, INVALID_FILE_SIZE};
+use winapi::um::fileapi::{GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION};
 use winapi::um::handleapi::CloseHandle;
 use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+use winapi::um::ioapiset::DeviceIoControl;
 use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, HANDLE};
 
 pub struct Volume {
@@ -40,3 +56,274 @@ impl Volume {
         }
     }
 }
+
+/// Identifies a file independently of the path used to reach it, so every
+/// hardlink to the same data shares one `FileId`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId {
+    pub volume_serial: u32,
+    pub index: u64,
+}
+
+pub fn file_identity(path: &Path) -> Result<FileId, Error> {
+    let file = File::open(path)?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+
+    let res = unsafe { GetFileInformationByHandle(file.as_raw_handle() as HANDLE, &mut info) };
+
+    match res {
+        0 => Err(Error::last_os_error()),
+        _ => Ok(FileId {
+            volume_serial: info.dwVolumeSerialNumber,
+            index: ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64,
+        }),
+    }
+}
+
+/// Whether a stream listing ended normally rather than failed
+fn is_end_of_streams(error: &Error) -> bool {
+    error.raw_os_error() == Some(ERROR_HANDLE_EOF as i32)
+}
+
+/// Total size of the named data streams of a file, the alternate data
+/// streams that `metadata().len()` leaves out since it only reports the
+/// default `::$DATA` stream
+pub fn named_streams_size(path: &Path) -> Result<u64, Error> {
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut data: WIN32_FIND_STREAM_DATA = unsafe { std::mem::zeroed() };
+
+    let handle = unsafe {
+        FindFirstStreamW(
+            wide.as_ptr(),
+            FindStreamInfoStandard,
+            &mut data as *mut _ as LPVOID,
+            0,
+        )
+    };
+    if handle == INVALID_HANDLE_VALUE {
+        // Directories have no stream at all
+        let error = Error::last_os_error();
+        return if is_end_of_streams(&error) {
+            Ok(0)
+        } else {
+            Err(error)
+        };
+    }
+
+    let mut total = 0;
+    let error = loop {
+        let length = data.cStreamName.iter().position(|&c| c == 0).unwrap_or(0);
+        let name = String::from_utf16_lossy(&data.cStreamName[..length]);
+        if name != "::$DATA" {
+            total += unsafe { *data.StreamSize.QuadPart() } as u64;
+        }
+        if unsafe { FindNextStreamW(handle, &mut data as *mut _ as LPVOID) } == 0 {
+            break Error::last_os_error();
+        }
+    };
+    unsafe { FindClose(handle) };
+
+    if is_end_of_streams(&error) {
+        Ok(total)
+    } else {
+        Err(error)
+    }
+}
+
+/// Bytes a file occupies on disk, less than its length for sparse and
+/// compressed files, whose holes or compressed clusters take no space.
+/// Other files report their length.
+pub fn allocated_size(path: &Path) -> Result<u64, Error> {
+    let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut high: DWORD = 0;
+
+    let low = unsafe { GetCompressedFileSizeW(wide.as_ptr(), &mut high) };
+    // All ones is also a valid low half, the error code tells them apart
+    if low == INVALID_FILE_SIZE {
+        let error = Error::last_os_error();
+        if error.raw_os_error() != Some(0) {
+            return Err(error);
+        }
+    }
+    Ok((high as u64) << 32 | low as u64)
+}
+
+// Clone in chunks well below the 4 GiB per-call limit, cluster aligned
+const CLONE_CHUNK_SIZE: u64 = 1024 * 1024 * 1024;
+
+fn cluster_size(file: &File) -> Result<u64, Error> {
+    let mut returned_bytes: u32 = 0;
+    let mut info: FSCTL_GET_INTEGRITY_INFORMATION_BUFFER = Default::default();
+
+    let res = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle() as HANDLE,
+            FSCTL_GET_INTEGRITY_INFORMATION,
+            null_mut(),
+            0,
+            &mut info as *mut FSCTL_GET_INTEGRITY_INFORMATION_BUFFER as LPVOID,
+            std::mem::size_of_val(&info) as DWORD,
+            &mut returned_bytes as LPDWORD,
+            null_mut(),
+        )
+    };
+
+    match res {
+        0 => Err(Error::last_os_error()),
+        _ => Ok(info.ClusterSizeInBytes as u64),
+    }
+}
+
+fn duplicate_extents(source: &File, target: &File, size: u64) -> Result<(), Error> {
+    let cluster = cluster_size(source)?;
+    let mut offset = 0;
+
+    while offset < size {
+        // The last chunk may be rounded up to the cluster boundary past EOF
+        let count = (size - offset).min(CLONE_CHUNK_SIZE).div_ceil(cluster) * cluster;
+        let mut returned_bytes: u32 = 0;
+        let input = DUPLICATE_EXTENTS_DATA {
+            FileHandle: source.as_raw_handle() as HANDLE,
+            SourceFileOffset: offset as i64,
+            TargetFileOffset: offset as i64,
+            ByteCount: count as i64,
+        };
+
+        let res = unsafe {
+            DeviceIoControl(
+                target.as_raw_handle() as HANDLE,
+                FSCTL_DUPLICATE_EXTENTS_TO_FILE,
+                &input as *const DUPLICATE_EXTENTS_DATA as LPVOID,
+                std::mem::size_of_val(&input) as DWORD,
+                null_mut(),
+                0,
+                &mut returned_bytes as LPDWORD,
+                null_mut(),
+            )
+        };
+        if res == 0 {
+            return Err(Error::last_os_error());
+        }
+
+        offset += count;
+    }
+
+    Ok(())
+}
+
+/// Create `target` as a copy-on-write clone of `source` sharing its extents.
+///
+/// Only block-cloning filesystems (ReFS, Dev Drive) support this; elsewhere
+/// an error explaining so is returned. `target` is not left behind on error.
+pub fn reflink(source: &Path, target: &Path) -> Result<(), Error> {
+    let source_file = File::open(source)?;
+    let size = source_file.metadata()?.len();
+    let target_file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+
+    let result = target_file
+        .set_len(size)
+        .and_then(|_| duplicate_extents(&source_file, &target_file, size));
+
+    if let Err(e) = result {
+        drop(target_file);
+        let _ = fs::remove_file(target);
+        return Err(explain_unsupported(e));
+    }
+
+    Ok(())
+}
+
+/// Point out the filesystem when it can't clone blocks at all, and leave any
+/// other error (access denied, disk full, ...) as it is
+fn explain_unsupported(e: Error) -> Error {
+    match e.raw_os_error().map(|code| code as DWORD) {
+        Some(ERROR_INVALID_FUNCTION) | Some(ERROR_NOT_SUPPORTED) => Error::new(
+            e.kind(),
+            format!("block cloning requires a ReFS or Dev Drive volume: {}", e),
+        ),
+        _ => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use winapi::um::winioctl::FSCTL_SET_SPARSE;
+
+    fn set_sparse(file: &File) {
+        let mut returned_bytes: u32 = 0;
+        let res = unsafe {
+            DeviceIoControl(
+                file.as_raw_handle() as HANDLE,
+                FSCTL_SET_SPARSE,
+                null_mut(),
+                0,
+                null_mut(),
+                0,
+                &mut returned_bytes as LPDWORD,
+                null_mut(),
+            )
+        };
+        assert_ne!(res, 0, "{}", Error::last_os_error());
+    }
+
+    #[test]
+    fn sparse_file_allocates_less_than_its_length() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.bin");
+        fs::write(&plain, vec![1u8; 100_000]).unwrap();
+        assert_eq!(allocated_size(&plain).unwrap(), 100_000);
+
+        // A mostly empty virtual disk: a header and 64 MiB of hole
+        let sparse = dir.path().join("disk.vhdx");
+        let mut file = File::create(&sparse).unwrap();
+        set_sparse(&file);
+        file.write_all(b"vhdxfile").unwrap();
+        file.set_len(64 * 1024 * 1024).unwrap();
+        drop(file);
+
+        let attributes = crate::utils::file_attributes(&sparse).unwrap();
+        assert!(crate::utils::is_sparse_attribute(attributes));
+        assert!(!crate::utils::is_sparse_attribute(
+            crate::utils::file_attributes(&plain).unwrap()
+        ));
+        assert_eq!(fs::metadata(&sparse).unwrap().len(), 64 * 1024 * 1024);
+        let allocated = allocated_size(&sparse).unwrap();
+        assert!(allocated < 1024 * 1024, "{} bytes allocated", allocated);
+    }
+
+    #[test]
+    fn named_streams_are_summed_without_the_default_stream() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.jpg");
+        fs::write(&path, vec![0u8; 100]).unwrap();
+        assert_eq!(named_streams_size(&path).unwrap(), 0);
+
+        fs::write(
+            dir.path().join("photo.jpg:Zone.Identifier"),
+            "[ZoneTransfer]\r\nZoneId=3\r\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("photo.jpg:thumbnail"), vec![1u8; 4000]).unwrap();
+
+        assert_eq!(named_streams_size(&path).unwrap(), 26 + 4000);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 100);
+        assert_eq!(named_streams_size(dir.path()).unwrap(), 0);
+    }
+
+    #[test]
+    fn only_unsupported_clones_mention_the_filesystem() {
+        for code in [ERROR_INVALID_FUNCTION, ERROR_NOT_SUPPORTED] {
+            let e = explain_unsupported(Error::from_raw_os_error(code as i32));
+            assert!(e.to_string().contains("ReFS or Dev Drive"), "{}", e);
+        }
+
+        let denied = explain_unsupported(Error::from_raw_os_error(5));
+        assert_eq!(denied.raw_os_error(), Some(5));
+        assert!(!denied.to_string().contains("ReFS"));
+    }
+}
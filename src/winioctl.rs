@@ -1,9 +1,17 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
-use winapi::shared::minwindef::{DWORD, WORD};
-use winapi::shared::ntdef::{DWORDLONG, USN, WCHAR};
-use winapi::um::winnt::LARGE_INTEGER;
+use winapi::shared::minwindef::{DWORD, MAX_PATH, WORD};
+use winapi::shared::ntdef::{DWORDLONG, LONGLONG, USN, WCHAR};
+use winapi::um::winnt::{HANDLE, LARGE_INTEGER};
+
+// CTL_CODE(FILE_DEVICE_FILE_SYSTEM, 209, METHOD_BUFFERED, FILE_WRITE_DATA)
+pub const FSCTL_DUPLICATE_EXTENTS_TO_FILE: DWORD = 0x0009_8344;
+// CTL_CODE(FILE_DEVICE_FILE_SYSTEM, 159, METHOD_BUFFERED, FILE_ANY_ACCESS)
+pub const FSCTL_GET_INTEGRITY_INFORMATION: DWORD = 0x0009_027C;
+
+pub const USN_REASON_FILE_CREATE: DWORD = 0x0000_0100;
+pub const USN_REASON_FILE_DELETE: DWORD = 0x0000_0200;
 
 #[repr(C)]
 #[derive(Default, Debug)]
@@ -32,6 +40,17 @@ pub struct MFT_ENUM_DATA {
     pub HighUsn: USN,
 }
 
+#[repr(C)]
+#[derive(Default)]
+pub struct READ_USN_JOURNAL_DATA {
+    pub StartUsn: USN,
+    pub ReasonMask: DWORD,
+    pub ReturnOnlyOnClose: DWORD,
+    pub Timeout: DWORDLONG,
+    pub BytesToWaitFor: DWORDLONG,
+    pub UsnJournalID: DWORDLONG,
+}
+
 #[repr(C)]
 pub struct USN_RECORD {
     pub RecordLength: DWORD,
@@ -49,3 +68,48 @@ pub struct USN_RECORD {
     pub FileNameOffset: WORD,
     pub FileName: [WCHAR; 1],
 }
+
+#[repr(C)]
+pub struct DUPLICATE_EXTENTS_DATA {
+    pub FileHandle: HANDLE,
+    pub SourceFileOffset: LONGLONG,
+    pub TargetFileOffset: LONGLONG,
+    pub ByteCount: LONGLONG,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct FSCTL_GET_INTEGRITY_INFORMATION_BUFFER {
+    pub ChecksumAlgorithm: WORD,
+    pub Reserved: WORD,
+    pub Flags: DWORD,
+    pub ChecksumChunkSizeInBytes: DWORD,
+    pub ClusterSizeInBytes: DWORD,
+}
+
+/// Filled in by `FindFirstStreamW` and `FindNextStreamW`, which winapi
+/// declares without the struct
+#[repr(C)]
+pub struct WIN32_FIND_STREAM_DATA {
+    pub StreamSize: LARGE_INTEGER,
+    pub cStreamName: [WCHAR; MAX_PATH + 36],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    #[test]
+    fn duplicate_extents_data_layout() {
+        let handle = size_of::<HANDLE>();
+        assert_eq!(offset_of!(DUPLICATE_EXTENTS_DATA, FileHandle), 0);
+        assert_eq!(offset_of!(DUPLICATE_EXTENTS_DATA, SourceFileOffset), 8);
+        assert_eq!(offset_of!(DUPLICATE_EXTENTS_DATA, TargetFileOffset), 16);
+        assert_eq!(offset_of!(DUPLICATE_EXTENTS_DATA, ByteCount), 24);
+        assert_eq!(size_of::<DUPLICATE_EXTENTS_DATA>(), 24 + handle.max(8));
+        assert_eq!(size_of::<FSCTL_GET_INTEGRITY_INFORMATION_BUFFER>(), 16);
+        assert_eq!(offset_of!(WIN32_FIND_STREAM_DATA, cStreamName), 8);
+        assert_eq!(size_of::<WIN32_FIND_STREAM_DATA>(), 600);
+    }
+}
@@ -1,6 +1,7 @@
 use ddup::Ntfs;
 use ddup::UsnRange;
 use ddup::Volume;
+use ddup::USN_REASON_FILE_CREATE;
 
 #[test]
 fn enumerate_mft_records() -> Result<(), std::io::Error> {
@@ -31,3 +32,33 @@ fn create_journal() -> Result<(), std::io::Error> {
 
     Ok(())
 }
+
+#[test]
+fn filtered_records_only_have_requested_reasons() -> Result<(), std::io::Error> {
+    let volume = Volume::open(r"\\.\C:")?;
+    let low = volume.query_usn_journal()?.NextUsn;
+
+    // A create, then a delete after the handle is closed, so the delete is
+    // recorded without the create reason
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join("created.bin");
+    std::fs::write(&path, b"journal me")?;
+    std::fs::remove_file(&path)?;
+
+    let range = UsnRange {
+        low,
+        high: volume.query_usn_journal()?.NextUsn,
+    };
+    let all = volume.usn_records_filtered(&range, u32::MAX)?.count();
+    let created: Vec<_> = volume
+        .usn_records_filtered(&range, USN_REASON_FILE_CREATE)?
+        .collect();
+
+    assert!(!created.is_empty());
+    assert!(created.len() < all);
+    assert!(created
+        .iter()
+        .all(|record| record.reason & USN_REASON_FILE_CREATE != 0));
+
+    Ok(())
+}
@@ -0,0 +1,932 @@
+use std::collections::HashMap;
+use std::fs;
+
+use nanoserde::SerJson;
+
+use ddup::algorithm::{self, Comparison, Confidence, HashAlgorithm, HashOptions};
+use ddup::checkpoint::{self, Checkpoint};
+use ddup::dirlist::{build_match_options, EntryFilter};
+use ddup::gate::OpenFileGate;
+use ddup::{export, Backend, DedupBuilder, DirList, ProgressReporter};
+
+fn match_options() -> glob::MatchOptions {
+    build_match_options(false)
+}
+
+#[test]
+fn run_finds_duplicates_in_directory_tree() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("a").join("nested"))?;
+    fs::create_dir_all(root.join("b"))?;
+
+    fs::write(root.join("a").join("photo.jpg"), b"same content")?;
+    fs::write(root.join("b").join("copy.jpg"), b"same content")?;
+    fs::write(
+        root.join("a").join("nested").join("other.jpg"),
+        b"different!!!",
+    )?;
+    fs::write(root.join("b").join("unique.txt"), b"unique")?;
+
+    let duplicates = algorithm::run_groups(
+        root.to_str().unwrap(),
+        &[],
+        &[],
+        match_options(),
+        HashOptions {
+            algorithm: HashAlgorithm::Blake3,
+            ..HashOptions::new(Comparison::Strict)
+        },
+        0,
+        EntryFilter::default(),
+        Backend::WalkDir,
+        None,
+        None,
+        &ProgressReporter::hidden(),
+        &OpenFileGate::default(),
+    )
+    .unwrap();
+
+    assert_eq!(duplicates.len(), 1);
+    let group = &duplicates[0];
+    assert_eq!(group.size, 12);
+    assert_eq!(group.paths.len(), 2);
+    assert!(group.paths.iter().any(|p| p.ends_with("photo.jpg")));
+    assert!(group.paths.iter().any(|p| p.ends_with("copy.jpg")));
+
+    Ok(())
+}
+
+#[test]
+fn exclude_removes_build_artifacts() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("src"))?;
+    fs::create_dir_all(root.join("target").join("debug"))?;
+
+    fs::write(root.join("src").join("main.rs"), b"fn main() {}")?;
+    fs::write(
+        root.join("target").join("debug").join("main.rs"),
+        b"fn main() {}",
+    )?;
+    fs::write(root.join("target").join("debug").join("app.exe"), b"MZ")?;
+
+    let excludes = [glob::Pattern::new("**/target/**").unwrap()];
+    let dirlist = DirList::new(
+        root.to_str().unwrap(),
+        &[],
+        &excludes,
+        match_options(),
+        0,
+        EntryFilter::default(),
+        Backend::WalkDir,
+        &ProgressReporter::hidden(),
+    )
+    .unwrap();
+
+    let paths: Vec<_> = dirlist.iter().map(|(path, _)| path.to_path_buf()).collect();
+    assert_eq!(paths, vec![root.join("src").join("main.rs")]);
+
+    Ok(())
+}
+
+#[test]
+fn multiple_matchers_include_any_match() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("a.jpg"), b"jpg")?;
+    fs::write(root.join("b.png"), b"png")?;
+    fs::write(root.join("c.txt"), b"txt")?;
+
+    let matchers = [
+        glob::Pattern::new("**.jpg").unwrap(),
+        glob::Pattern::new("**.png").unwrap(),
+    ];
+    let dirlist = DirList::new(
+        root.to_str().unwrap(),
+        &matchers,
+        &[],
+        match_options(),
+        0,
+        EntryFilter::default(),
+        Backend::WalkDir,
+        &ProgressReporter::hidden(),
+    )
+    .unwrap();
+
+    let mut paths: Vec<_> = dirlist.iter().map(|(path, _)| path.to_path_buf()).collect();
+    paths.sort();
+    assert_eq!(paths, vec![root.join("a.jpg"), root.join("b.png")]);
+
+    Ok(())
+}
+
+#[test]
+fn repeated_runs_export_identical_json() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    for (i, content) in [b"aaaa", b"bbbb", b"aaaa", b"bbbb", b"aaaa"]
+        .iter()
+        .enumerate()
+    {
+        fs::write(root.join(format!("{}.bin", i)), content)?;
+    }
+    fs::write(root.join("big1.bin"), b"larger file")?;
+    fs::write(root.join("big2.bin"), b"larger file")?;
+
+    let scan = || {
+        algorithm::run_groups(
+            root.to_str().unwrap(),
+            &[],
+            &[],
+            match_options(),
+            HashOptions {
+                algorithm: HashAlgorithm::Blake3,
+                ..HashOptions::new(Comparison::Strict)
+            },
+            0,
+            EntryFilter::default(),
+            Backend::WalkDir,
+            None,
+            None,
+            &ProgressReporter::hidden(),
+            &OpenFileGate::default(),
+        )
+        .unwrap()
+    };
+
+    let first = scan();
+    assert_eq!(first.len(), 3);
+    assert_eq!(first.serialize_json(), scan().serialize_json());
+
+    Ok(())
+}
+
+#[test]
+fn stats_report_duplicates_of_known_tree() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("a.bin"), b"twelve bytes")?;
+    fs::write(root.join("b.bin"), b"twelve bytes")?;
+    fs::write(root.join("c.bin"), b"twelve bytes")?;
+    fs::write(root.join("d.bin"), b"twelve BYTES")?;
+    fs::write(root.join("e.bin"), b"unique")?;
+
+    let (duplicates, stats) = algorithm::run_with_stats(
+        root.to_str().unwrap(),
+        &[],
+        &[],
+        match_options(),
+        HashOptions {
+            algorithm: HashAlgorithm::Blake3,
+            ..HashOptions::new(Comparison::Strict)
+        },
+        0,
+        EntryFilter::default(),
+        Backend::WalkDir,
+        None,
+        None,
+        &ProgressReporter::hidden(),
+        &OpenFileGate::default(),
+    )
+    .unwrap();
+
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(stats.files_scanned, 5);
+    assert_eq!(stats.size_groups, 1);
+
+    let json = stats.serialize_json();
+    assert!(json.contains("\"duplicate_groups\":1"), "{}", json);
+    assert!(json.contains("\"redundant_files\":2"), "{}", json);
+    assert!(json.contains("\"reclaimable_bytes\":24"), "{}", json);
+
+    Ok(())
+}
+
+#[test]
+fn resumed_run_matches_fresh_run() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    let files: [(&str, &[u8]); 5] = [
+        ("a.bin", b"twelve bytes"),
+        ("b.bin", b"twelve bytes"),
+        ("c.bin", b"aaaa"),
+        ("d.bin", b"aaaa"),
+        ("e.bin", b"bbbb"),
+    ];
+    for (name, content) in files {
+        fs::write(root.join(name), content)?;
+    }
+
+    let state = tempfile::tempdir()?;
+    let checkpoint_path = state.path().join("scan.checkpoint");
+    let scan = |resume: Option<&std::path::Path>| {
+        algorithm::run_groups(
+            root.to_str().unwrap(),
+            &[],
+            &[],
+            match_options(),
+            HashOptions {
+                algorithm: HashAlgorithm::Blake3,
+                ..HashOptions::new(Comparison::Strict)
+            },
+            0,
+            EntryFilter::default(),
+            Backend::WalkDir,
+            None,
+            resume,
+            &ProgressReporter::hidden(),
+            &OpenFileGate::default(),
+        )
+        .unwrap()
+    };
+    let fresh = scan(None);
+    assert_eq!(fresh.len(), 2);
+
+    // Pretend an earlier run finished the 12 byte group before stopping
+    let paths: Vec<_> = files.iter().map(|(name, _)| root.join(name)).collect();
+    let mut map: HashMap<u64, Vec<&std::path::Path>> = HashMap::new();
+    for (path, (_, content)) in paths.iter().zip(files) {
+        map.entry(content.len() as u64).or_default().push(path);
+    }
+    let partial = Checkpoint {
+        scan: checkpoint::scan_key(
+            root.to_str().unwrap(),
+            &[],
+            &[],
+            &Comparison::Strict,
+            &HashAlgorithm::Blake3,
+        ),
+        fingerprint: checkpoint::fingerprint(&map),
+        completed: vec![12],
+        groups: fresh.iter().filter(|g| g.size == 12).cloned().collect(),
+    };
+    partial.save(&checkpoint_path)?;
+
+    // Same size, different content: only a skipped group still reports it
+    fs::write(root.join("b.bin"), b"twelve BYTES")?;
+
+    let resumed = scan(Some(&checkpoint_path));
+    assert_eq!(resumed.serialize_json(), fresh.serialize_json());
+    assert!(!checkpoint_path.exists());
+
+    Ok(())
+}
+
+#[test]
+fn report_counts_every_listed_file() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("nested"))?;
+    fs::write(root.join("a.bin"), b"same")?;
+    fs::write(root.join("nested").join("b.bin"), b"same")?;
+    fs::write(root.join("c.bin"), b"unique content")?;
+
+    let dirlist = DirList::new(
+        root.to_str().unwrap(),
+        &[],
+        &[],
+        match_options(),
+        0,
+        EntryFilter::default(),
+        Backend::WalkDir,
+        &ProgressReporter::hidden(),
+    )
+    .unwrap();
+
+    let report = algorithm::run(
+        root.to_str().unwrap(),
+        &[],
+        &[],
+        match_options(),
+        HashOptions {
+            algorithm: HashAlgorithm::Blake3,
+            ..HashOptions::new(Comparison::Strict)
+        },
+        0,
+        EntryFilter::default(),
+        Backend::WalkDir,
+        None,
+        None,
+        &ProgressReporter::hidden(),
+        &OpenFileGate::default(),
+    )
+    .unwrap();
+
+    assert_eq!(report.scanned, dirlist.len());
+    assert_eq!(report.errors, 0);
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.stats.files_scanned, report.scanned);
+
+    Ok(())
+}
+
+#[test]
+fn exported_groups_carry_mtimes() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("a.bin"), b"same")?;
+    fs::write(root.join("b.bin"), b"same")?;
+
+    let report = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict)
+        .with_times(true)
+        .run()
+        .unwrap();
+
+    let export_path = root.join("export.json");
+    export::write_json(&report.groups, fs::File::create(&export_path)?)?;
+    let groups = algorithm::DuplicateGroup::load_json(&export_path).unwrap();
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mtimes = groups[0].mtimes.as_ref().expect("mtimes were exported");
+    assert_eq!(mtimes.len(), groups[0].paths.len());
+    for mtime in mtimes {
+        assert!(now - 3600 < *mtime && *mtime <= now + 60, "{}", mtime);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn file_list_finds_duplicates_among_listed_files() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("a.bin"), b"listed copy")?;
+    fs::write(root.join("b.bin"), b"listed copy")?;
+    fs::write(root.join("c.bin"), b"other bytes")?;
+    // Same content, but not in the list
+    fs::write(root.join("unlisted.bin"), b"listed copy")?;
+
+    let list = root.join("list.txt");
+    let listed = |name: &str| root.join(name).to_string_lossy().to_string();
+    fs::write(
+        &list,
+        [
+            listed("a.bin"),
+            String::new(),
+            listed("b.bin"),
+            listed("missing.bin"),
+            listed("c.bin"),
+            listed("a.bin"),
+            "   ".to_string(),
+        ]
+        .join("\n"),
+    )?;
+
+    let report = DedupBuilder::new(list.to_str().unwrap())
+        .backend(Backend::FileList)
+        .comparison(Comparison::Strict)
+        .run()
+        .unwrap();
+
+    assert_eq!(report.scanned, 3);
+    assert_eq!(report.groups.len(), 1);
+    let mut paths = report.groups[0].paths.clone();
+    paths.sort();
+    assert_eq!(paths, vec![listed("a.bin"), listed("b.bin")]);
+
+    Ok(())
+}
+
+#[test]
+fn groups_record_how_they_were_compared() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().join("tree");
+    fs::create_dir(&root)?;
+    fs::write(root.join("a.bin"), b"same")?;
+    fs::write(root.join("b.bin"), b"same")?;
+
+    let builder = DedupBuilder::new(root.to_str().unwrap()).backend(Backend::WalkDir);
+    for (comparison, confidence) in [
+        (Comparison::Strict, Confidence::Exact),
+        (Comparison::Fuzzy, Confidence::Fuzzy),
+    ] {
+        let report = builder.clone().comparison(comparison).run().unwrap();
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].confidence, confidence);
+
+        let export_path = dir.path().join("export.json");
+        export::write_json(&report.groups, fs::File::create(&export_path)?)?;
+        let groups = algorithm::DuplicateGroup::load_json(&export_path).unwrap();
+        assert_eq!(groups[0].confidence, confidence);
+    }
+
+    // Exports from before the field existed can't vouch for their groups
+    let old_export = dir.path().join("old.json");
+    fs::write(
+        &old_export,
+        r#"[{"size":4,"paths":["C:\\a.bin","C:\\b.bin"]}]"#,
+    )?;
+    let groups = algorithm::DuplicateGroup::load_json(&old_export).unwrap();
+    assert_eq!(groups[0].confidence, Confidence::Fuzzy);
+
+    Ok(())
+}
+
+#[test]
+fn prefiltered_strict_run_matches_naive_strict_run() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    let mut state = 0x9e37_79b9_7f4a_7c15u64;
+    let original: Vec<u8> = (0..1024 * 1024)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    let changed_at = |offset: usize| {
+        let mut content = original.clone();
+        content[offset] ^= 0xff;
+        content
+    };
+
+    fs::write(root.join("a.bin"), &original)?;
+    fs::write(root.join("b.bin"), &original)?;
+    // Between the sampled chunks, so only reading the whole file tells
+    fs::write(root.join("c.bin"), changed_at(5000))?;
+    fs::write(root.join("f.bin"), changed_at(5000))?;
+    // In the first block and in a sampled chunk
+    fs::write(root.join("d.bin"), changed_at(0))?;
+    fs::write(root.join("e.bin"), changed_at(8200))?;
+
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict);
+    let paths = |prefilter: bool| -> Vec<Vec<String>> {
+        let report = builder.clone().prefilter(prefilter).run().unwrap();
+        report
+            .groups
+            .iter()
+            .map(|group| {
+                group
+                    .paths
+                    .iter()
+                    .map(|path| path.rsplit('\\').next().unwrap().to_string())
+                    .collect()
+            })
+            .collect()
+    };
+
+    let naive = paths(false);
+    assert_eq!(naive, [["a.bin", "b.bin"], ["c.bin", "f.bin"]]);
+    assert_eq!(paths(true), naive);
+
+    Ok(())
+}
+
+#[test]
+fn min_copies_drops_pairs_but_keeps_triples() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("pair1.bin"), b"pair")?;
+    fs::write(root.join("pair2.bin"), b"pair")?;
+    for i in 0..3 {
+        fs::write(root.join(format!("triple{}.bin", i)), b"triple")?;
+    }
+
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict);
+    assert_eq!(builder.clone().run().unwrap().groups.len(), 2);
+
+    let report = builder.min_copies(3).run().unwrap();
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.groups[0].size, 6);
+    assert_eq!(report.groups[0].paths.len(), 3);
+    assert_eq!(report.stats.duplicate_groups, 1);
+    assert_eq!(report.stats.redundant_files, 2);
+
+    Ok(())
+}
+
+#[test]
+fn junction_into_tree_is_not_listed_twice() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("a"))?;
+    fs::write(root.join("a").join("file.bin"), b"only one copy")?;
+    let status = std::process::Command::new("cmd")
+        .arg("/C")
+        .arg("mklink")
+        .arg("/J")
+        .arg(root.join("loop"))
+        .arg(root.join("a"))
+        .output()?
+        .status;
+    assert!(status.success(), "mklink /J failed");
+
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict);
+    let report = builder.clone().run().unwrap();
+    assert_eq!(report.scanned, 1);
+    assert!(report.groups.is_empty());
+
+    // Following the junction lists the same file under both names, which
+    // collapse into one like hardlinks do, so there is still nothing to link
+    let report = builder.follow_reparse(true).run().unwrap();
+    assert_eq!(report.scanned, 2);
+    assert!(report.groups.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn empty_files_are_skipped_unless_included() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    for name in ["a.log", "b.log", "c.log"] {
+        fs::write(root.join(name), b"")?;
+    }
+    fs::write(root.join("d.txt"), b"same")?;
+    fs::write(root.join("e.txt"), b"same")?;
+
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict);
+    let report = builder.clone().run().unwrap();
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.groups[0].size, 4);
+
+    let report = builder.include_empty(true).run().unwrap();
+    assert_eq!(report.groups.len(), 2);
+    assert!(report
+        .groups
+        .iter()
+        .any(|group| group.size == 0 && group.paths.len() == 3));
+
+    Ok(())
+}
+
+#[test]
+fn hidden_files_are_excluded_on_request() -> Result<(), std::io::Error> {
+    use std::io::Write;
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::write(root.join("visible.txt"), b"same")?;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .attributes(FILE_ATTRIBUTE_HIDDEN)
+        .open(root.join("hidden.txt"))?
+        .write_all(b"same")?;
+
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict);
+    assert_eq!(builder.clone().run().unwrap().groups.len(), 1);
+
+    let report = builder.exclude_hidden(true).run().unwrap();
+    assert_eq!(report.scanned, 1);
+    assert!(report.groups.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn walkdir_and_file_list_entries_carry_times_and_attributes() -> Result<(), std::io::Error> {
+    const FILE_ATTRIBUTE_READONLY: u32 = 0x1;
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    let locked = root.join("locked.bin");
+    fs::write(&locked, b"read only")?;
+    let mut permissions = fs::metadata(&locked)?.permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(&locked, permissions)?;
+    let list = root.join("list.txt");
+    fs::write(&list, locked.to_string_lossy().as_bytes())?;
+
+    let walked = DirList::from_walkdir(
+        root.to_str().unwrap(),
+        &[glob::Pattern::new("*.bin").unwrap()],
+        &[],
+        match_options(),
+        EntryFilter::default(),
+    )
+    .unwrap();
+    let listed = DirList::from_file_list(
+        list.to_str().unwrap(),
+        &[],
+        &[],
+        match_options(),
+        EntryFilter::default(),
+    )
+    .unwrap();
+
+    for listing in [walked, listed] {
+        let entries = listing.entries().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, locked);
+        assert_eq!(entries[0].size, 9);
+        assert!(entries[0].mtime.is_some());
+        assert_ne!(entries[0].attributes & FILE_ATTRIBUTE_READONLY, 0);
+    }
+
+    // Let the temporary directory be removed
+    let mut permissions = fs::metadata(&locked)?.permissions();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    fs::set_permissions(&locked, permissions)?;
+
+    Ok(())
+}
+
+#[test]
+fn observer_sees_every_phase_and_monotonic_counts() -> Result<(), std::io::Error> {
+    use ddup::{Phase, ProgressObserver};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder {
+        phases: Mutex<Vec<Phase>>,
+        counts: Mutex<Vec<(Phase, u64, u64)>>,
+    }
+
+    impl ProgressObserver for Recorder {
+        fn on_phase(&self, phase: Phase) {
+            self.phases.lock().unwrap().push(phase);
+        }
+
+        fn on_file(&self, done: u64, total: u64) {
+            let phase = *self.phases.lock().unwrap().last().unwrap();
+            self.counts.lock().unwrap().push((phase, done, total));
+        }
+    }
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    for i in 0..10 {
+        fs::write(
+            root.join(format!("{}.txt", i)),
+            format!("content {}", i % 3),
+        )?;
+    }
+
+    let recorder = Arc::new(Recorder::default());
+    DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .progress(ProgressReporter::hidden().with_observer(recorder.clone()))
+        .run()
+        .unwrap();
+
+    assert_eq!(
+        *recorder.phases.lock().unwrap(),
+        vec![Phase::Listing, Phase::GroupingBySize, Phase::Hashing]
+    );
+    let counts = recorder.counts.lock().unwrap();
+    for phase in [Phase::GroupingBySize, Phase::Hashing].iter() {
+        let seen: Vec<(u64, u64)> = counts
+            .iter()
+            .filter(|(p, _, _)| p == phase)
+            .map(|(_, done, total)| (*done, *total))
+            .collect();
+        assert!(!seen.is_empty(), "no counts during {:?}", phase);
+        assert!(seen.windows(2).all(|w| w[0].0 < w[1].0));
+        let (done, total) = *seen.last().unwrap();
+        assert_eq!(done, total);
+    }
+    assert_eq!(
+        counts
+            .iter()
+            .rev()
+            .find(|(p, _, _)| *p == Phase::GroupingBySize)
+            .map(|(_, _, total)| *total),
+        Some(10)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn cancelled_run_reports_the_groups_hashed_so_far() -> Result<(), std::io::Error> {
+    use ddup::{CancelFlag, Phase, ProgressObserver};
+    use std::sync::{Arc, Mutex};
+
+    /// Cancels as soon as the second size group is being hashed, which is
+    /// then left out as it's incomplete
+    struct CancelOnHashing {
+        cancel: CancelFlag,
+        phase: Mutex<Option<Phase>>,
+    }
+
+    impl ProgressObserver for CancelOnHashing {
+        fn on_phase(&self, phase: Phase) {
+            *self.phase.lock().unwrap() = Some(phase);
+        }
+
+        fn on_file(&self, done: u64, _total: u64) {
+            if *self.phase.lock().unwrap() == Some(Phase::Hashing) && done >= 2 {
+                self.cancel.cancel();
+            }
+        }
+    }
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path().join("tree");
+    fs::create_dir(&root)?;
+    for i in 0..20 {
+        let content = vec![b'x'; i + 1];
+        fs::write(root.join(format!("{}a.bin", i)), &content)?;
+        fs::write(root.join(format!("{}b.bin", i)), &content)?;
+    }
+    let checkpoint = dir.path().join("scan.checkpoint");
+
+    let cancel = CancelFlag::new();
+    let observer = Arc::new(CancelOnHashing {
+        cancel: cancel.clone(),
+        phase: Mutex::new(None),
+    });
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict)
+        .resume(&checkpoint)
+        .threads(1);
+    let report = builder
+        .clone()
+        .progress(
+            ProgressReporter::hidden()
+                .with_observer(observer)
+                .with_cancel(cancel),
+        )
+        .run()
+        .unwrap();
+
+    assert!(report.stats.interrupted);
+    assert_eq!(report.groups.len(), 1);
+    assert_eq!(report.groups[0].paths.len(), 2);
+    assert_eq!(report.stats.duplicate_groups, 1);
+    // Kept for the next run to pick up
+    assert!(checkpoint.exists());
+
+    let resumed = builder.run().unwrap();
+    assert!(!resumed.stats.interrupted);
+    assert_eq!(resumed.groups.len(), 20);
+    assert!(!checkpoint.exists());
+
+    Ok(())
+}
+
+#[test]
+fn files_modified_before_the_cutoff_are_skipped() -> Result<(), std::io::Error> {
+    use std::time::{Duration, SystemTime};
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    for name in ["recent-a.bin", "recent-b.bin"] {
+        fs::write(root.join(name), b"downloaded again")?;
+    }
+    for name in ["old-a.bin", "old-b.bin"] {
+        fs::write(root.join(name), b"downloaded ages ago")?;
+        fs::File::options()
+            .write(true)
+            .open(root.join(name))?
+            .set_modified(SystemTime::now() - 10 * DAY)?;
+    }
+
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict);
+    assert_eq!(builder.clone().run().unwrap().groups.len(), 2);
+
+    let report = builder
+        .modified_since(SystemTime::now() - 7 * DAY)
+        .run()
+        .unwrap();
+    assert_eq!(report.scanned, 2);
+    assert_eq!(report.groups.len(), 1);
+    assert!(report.groups[0].paths[0].ends_with("recent-a.bin"));
+
+    Ok(())
+}
+
+#[test]
+fn max_depth_excludes_files_in_sub_subfolders() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    fs::create_dir_all(root.join("sub").join("deeper"))?;
+    fs::write(root.join("top.bin"), b"same bytes")?;
+    fs::write(root.join("sub").join("middle.bin"), b"same bytes")?;
+    fs::write(
+        root.join("sub").join("deeper").join("bottom.bin"),
+        b"same bytes",
+    )?;
+
+    let builder = DedupBuilder::new(root.to_str().unwrap())
+        .backend(Backend::WalkDir)
+        .comparison(Comparison::Strict);
+    assert_eq!(builder.clone().run().unwrap().groups[0].paths.len(), 3);
+
+    let report = builder.clone().max_depth(1).run().unwrap();
+    assert_eq!(report.scanned, 2);
+    let mut paths = report.groups[0].paths.clone();
+    paths.sort();
+    assert_eq!(
+        paths,
+        [
+            root.join("sub").join("middle.bin").to_string_lossy(),
+            root.join("top.bin").to_string_lossy(),
+        ]
+    );
+
+    let report = builder.max_depth(0).run().unwrap();
+    assert_eq!(report.scanned, 1);
+    assert!(report.groups.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn file_shrunk_after_listing_is_dropped_as_changed() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    for name in ["a.bin", "b.bin", "shrunk.bin"] {
+        fs::write(root.join(name), b"same bytes")?;
+    }
+    let csv = root.join("export.csv");
+    let rows: Vec<String> = ["a.bin", "b.bin", "shrunk.bin"]
+        .iter()
+        .map(|name| format!("\"{}\",10", root.join(name).display()))
+        .collect();
+    fs::write(&csv, format!("File Name,Size\n{}\n", rows.join("\n")))?;
+    // Truncated to a prefix of the others after the listing was taken
+    fs::write(root.join("shrunk.bin"), b"same")?;
+
+    for comparison in [Comparison::Fuzzy, Comparison::Strict] {
+        let report = DedupBuilder::new(csv.to_str().unwrap())
+            .backend(Backend::WizTree)
+            .comparison(comparison)
+            .run()
+            .unwrap();
+
+        assert_eq!(report.errors, 0, "{:?}", comparison);
+        assert_eq!(report.stats.changed_files, 1, "{:?}", comparison);
+        assert_eq!(report.groups.len(), 1);
+        let mut paths = report.groups[0].paths.clone();
+        paths.sort();
+        assert_eq!(
+            paths,
+            [
+                root.join("a.bin").to_string_lossy().to_string(),
+                root.join("b.bin").to_string_lossy().to_string()
+            ]
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn files_changed_after_wiztree_export_are_skipped_at_hash_time() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let root = dir.path();
+    let names = ["a.bin", "b.bin", "gone.bin", "grown.bin"];
+    for name in names {
+        fs::write(root.join(name), b"same bytes")?;
+    }
+    let csv = root.join("export.csv");
+    let rows: Vec<String> = names
+        .iter()
+        .map(|name| format!("\"{}\",10", root.join(name).display()))
+        .collect();
+    fs::write(&csv, format!("File Name,Size\n{}\n", rows.join("\n")))?;
+    fs::remove_file(root.join("gone.bin"))?;
+    // Still starts with the listed 10 bytes, which is all a fuzzy hash of
+    // that size would read
+    fs::write(root.join("grown.bin"), b"same bytes and then some")?;
+
+    for comparison in [Comparison::Fuzzy, Comparison::Strict] {
+        let report = DedupBuilder::new(csv.to_str().unwrap())
+            .backend(Backend::WizTree)
+            .comparison(comparison)
+            .run()
+            .unwrap();
+
+        // Listed from the export without a stat, then dropped when opened
+        assert_eq!(report.scanned, 4);
+        assert_eq!(report.errors, 2);
+        assert_eq!(report.groups.len(), 1);
+        let paths = &report.groups[0].paths;
+        assert_eq!(paths.len(), 2, "{:?}", comparison);
+        assert!(
+            !paths.iter().any(|p| p.ends_with("grown.bin")),
+            "{:?}",
+            paths
+        );
+    }
+
+    Ok(())
+}